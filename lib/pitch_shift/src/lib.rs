@@ -83,7 +83,6 @@ impl PitchShifter {
         let bin_frequencies = sample_rate as f32 / fs_real;
         let expected = TAU / (over_sampling as f32);
         let fifo_latency = frame_size - step;
-        println!("{}", fifo_latency);
         let overlap= fifo_latency;
 
         let pitch_weight = shift * bin_frequencies;
@@ -0,0 +1,63 @@
+//! A bank of 96 bandpass bands, the layout of copiremap, against 96 [Filter]s evaluated one
+//! after the other on the same block.
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use simple_eq::bank::FilterBank;
+use simple_eq::design::Curve;
+use simple_eq::filter::Filter;
+
+const BANDS: usize = 96;
+const SAMPLE_RATE: f32 = 48e3;
+
+/// The center frequency of band `idx`, a semitone apart from 27.5 Hz.
+fn frequency(idx: usize) -> f32 {
+    27.5 * 2f32.powf(idx as f32 / 12.0)
+}
+
+fn input(len: usize) -> Vec<f32> {
+    (0..len).map(|n| (n as f32 * 0.1).sin()).collect()
+}
+
+fn bank(c: &mut Criterion) {
+    let mut group = c.benchmark_group("96 bands");
+    for block in [64, 512] {
+        let input = input(block);
+        let mut outputs = vec![vec![0.0; block]; BANDS];
+        group.throughput(Throughput::Elements(block as u64));
+
+        let mut bank = FilterBank::<f32, BANDS>::new(SAMPLE_RATE);
+        for idx in 0..BANDS {
+            bank.set(idx, Curve::Bandpass, frequency(idx), 30.0, 1.0);
+        }
+        group.bench_with_input(BenchmarkId::new("FilterBank", block), &input, |b, input| {
+            b.iter(|| {
+                let mut frame = [0.0; BANDS];
+                for (n, x) in input.iter().enumerate() {
+                    bank.process_parallel(black_box(*x), &mut frame);
+                    for (output, y) in outputs.iter_mut().zip(frame.iter()) {
+                        output[n] = *y;
+                    }
+                }
+                black_box(&outputs);
+            })
+        });
+
+        let mut filters = [Filter::new(SAMPLE_RATE); BANDS];
+        for (idx, filter) in filters.iter_mut().enumerate() {
+            filter.set(Curve::Bandpass, frequency(idx), 30.0, 1.0, SAMPLE_RATE);
+        }
+        group.bench_with_input(BenchmarkId::new("Filter", block), &input, |b, input| {
+            b.iter(|| {
+                for (filter, output) in filters.iter_mut().zip(outputs.iter_mut()) {
+                    for (y, x) in output.iter_mut().zip(input.iter()) {
+                        *y = filter.filter(black_box(*x));
+                    }
+                }
+                black_box(&outputs);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bank);
+criterion_main!(benches);
@@ -0,0 +1,65 @@
+//! Uses every filter of simple-eq without the standard library, so that a plain
+//!
+//! ```sh
+//! rustup target add thumbv7em-none-eabihf
+//! cargo build --release
+//! ```
+//!
+//! in this directory fails as soon as something pulls `std` or allocates.
+#![no_std]
+
+use simple_eq::bank::FilterBank;
+use simple_eq::cascade::{CascadeFilter, Response};
+use simple_eq::crossover::{BandSplit, Crossover};
+use simple_eq::design::Curve;
+use simple_eq::filter::{Filter, Topology};
+use simple_eq::{Equalizer, Link};
+
+const SAMPLE_RATE: f32 = 48.0e3;
+
+pub struct Pedal {
+    eq: Equalizer<f32, 4, 2>,
+    tone: Filter<f32>,
+    cascade: CascadeFilter<f32, 4>,
+    split: BandSplit<f32, 2>,
+    bank: FilterBank<f32, 8>,
+}
+
+impl Pedal {
+    pub fn new() -> Self {
+        let mut eq = Equalizer::with_link(SAMPLE_RATE, Link::MidSide);
+        let _ = eq.load_apo("Filter 1: ON PK Fc 1000 Hz Gain -3 dB Q 1.41\nFilter 2: ON HP Fc 40 Hz");
+        let mut tone = Filter::new(SAMPLE_RATE);
+        tone.set(Curve::Lowshelf, 200.0, 0.7, 2.0, SAMPLE_RATE);
+        tone.set_topology(Topology::Tpt);
+        tone.set_smoothing(0.01);
+        let mut cascade = CascadeFilter::new(SAMPLE_RATE);
+        cascade.set(Curve::Lowpass, Response::Butterworth, 8000.0, 0.7, 8, SAMPLE_RATE);
+        let mut split = BandSplit::new(Crossover::LR4, SAMPLE_RATE);
+        split.set(Crossover::LR4, [200.0, 2000.0], SAMPLE_RATE);
+        let mut bank = FilterBank::new(SAMPLE_RATE);
+        for idx in 0..8 {
+            bank.set(idx, Curve::Bandpass, 100.0 * (idx + 1) as f32, 10.0, 1.0);
+        }
+        Self { eq, tone, cascade, split, bank }
+    }
+
+    /// Process a stereo frame in place.
+    pub fn process(&mut self, frame: &mut [f32; 2]) {
+        self.eq.process_frame(frame);
+        let mono = self.cascade.filter(self.tone.filter(frame[0] + frame[1]));
+        let mut bands = [0.0; 3];
+        self.split.split(mono, &mut bands);
+        let mut lanes = [0.0; 8];
+        self.bank.process_parallel(bands[1], &mut lanes);
+        let sum: f32 = lanes.iter().sum();
+        frame[0] += sum;
+        frame[1] -= sum;
+    }
+}
+
+impl Default for Pedal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
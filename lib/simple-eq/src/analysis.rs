@@ -0,0 +1,233 @@
+//! Time domain responses of filters, and metrics to check a design automatically.
+//!
+//! The responses are rendered through a closure that filters one sample, so they work
+//! with anything that processes samples. [Filter] and a mono [Equalizer] render from a
+//! reset copy of themselves, so their state is left untouched.
+use crate::design::*;
+use crate::filter::Filter;
+use crate::response::*;
+use crate::Equalizer;
+use nalgebra::{convert as _c, RealField as Real};
+
+/// Number of points of the sweep that brackets the -3dB points before they are refined.
+const SWEEP_POINTS: usize = 512;
+
+/// Render the response to a unit impulse into `out`.
+pub fn impulse_response<R: Real + Copy>(out: &mut [R], mut process: impl FnMut(R) -> R) {
+    for (n, y) in out.iter_mut().enumerate() {
+        *y = process(if n == 0 { R::one() } else { R::zero() });
+    }
+}
+
+/// Render the response to a unit step into `out`.
+pub fn step_response<R: Real + Copy>(out: &mut [R], mut process: impl FnMut(R) -> R) {
+    for y in out.iter_mut() {
+        *y = process(R::one());
+    }
+}
+
+/// The largest absolute value of a response.
+fn peak<R: Real + Copy>(response: &[R]) -> R {
+    response.iter().fold(R::zero(), |p, y| p.max(y.abs()))
+}
+
+/// The number of samples after which `response` stays within `tolerance` of its last
+/// value, relative to its peak. A result close to the length of the response means it
+/// had not settled yet, render a longer one.
+pub fn settling_time<R: Real + Copy>(response: &[R], tolerance: R) -> usize {
+    let last = match response.last() {
+        Some(last) => *last,
+        None => return 0,
+    };
+    let band = tolerance * peak(response);
+    response.iter().rposition(|y| (*y - last).abs() > band).map_or(0, |n| n + 1)
+}
+
+/// The number of samples of an impulse response until it decays below `threshold_dB`
+/// relative to its peak for good, `-60` giving the usual decay time.
+#[allow(non_snake_case)]
+pub fn ringing_length<R: Real + Copy>(impulse: &[R], threshold_dB: R) -> usize {
+    let threshold = peak(impulse) * _c::<f64, R>(10.0).powf(threshold_dB / _c(20.0));
+    impulse.iter().rposition(|y| y.abs() > threshold).map_or(0, |n| n + 1)
+}
+
+/// The frequencies below and above the peak of the magnitude between `start` and `stop`
+/// where it is 3dB down from the peak, in the units of the response. A lowpass has no
+/// lower point and a highpass no upper one, and neither has a flat response.
+pub fn half_power_points<R, F>(response: &F, start: R, stop: R) -> (Option<R>, Option<R>)
+where
+    R: Real + Copy,
+    F: FrequencyResponse<R>,
+{
+    let ratio = stop / start;
+    let last: R = _c((SWEEP_POINTS - 1) as f64);
+    let frequency = |i: usize| start * ratio.powf(_c::<f64, R>(i as f64) / last);
+    let (peak_idx, peak) = (0..SWEEP_POINTS)
+        .map(|i| (i, response.magnitude(frequency(i))))
+        .fold((0, R::zero()), |(pi, p), (i, m)| if m > p { (i, m) } else { (pi, m.max(p)) });
+    // The peak lies between the neighbours of the largest point of the sweep, find it by a
+    // golden section search on a log scale.
+    let (mut low, mut high) = (frequency(peak_idx.saturating_sub(1)), frequency((peak_idx + 1).min(SWEEP_POINTS - 1)));
+    let golden: R = (_c::<f64, R>(5.0).sqrt() - R::one()) / _c(2.0);
+    for _ in 0..48 {
+        let span = (high / low).ln();
+        let (a, b) = (high * (-golden * span).exp(), low * (golden * span).exp());
+        if response.magnitude(a) < response.magnitude(b) {
+            low = a;
+        } else {
+            high = b;
+        }
+    }
+    let peak = peak.max(response.magnitude((low * high).sqrt()));
+    let threshold = peak * _c(core::f64::consts::FRAC_1_SQRT_2);
+
+    // Bisect between a point above the threshold and a point below it, on a log scale.
+    let refine = |mut above: R, mut below: R| {
+        for _ in 0..32 {
+            let middle = (above * below).sqrt();
+            if response.magnitude(middle) >= threshold {
+                above = middle;
+            } else {
+                below = middle;
+            }
+        }
+        (above * below).sqrt()
+    };
+    let lower = (0..peak_idx)
+        .rev()
+        .find(|i| response.magnitude(frequency(*i)) < threshold)
+        .map(|i| refine(frequency(i + 1), frequency(i)));
+    let upper = (peak_idx + 1..SWEEP_POINTS)
+        .find(|i| response.magnitude(frequency(*i)) < threshold)
+        .map(|i| refine(frequency(i - 1), frequency(i)));
+    (lower, upper)
+}
+
+impl<R: Real + Copy> Filter<R> {
+    /// Render the impulse response of the filter from a reset copy of it.
+    pub fn impulse_response(&self, out: &mut [R]) {
+        let mut filter = *self;
+        filter.reset();
+        impulse_response(out, |x| filter.filter(x));
+    }
+
+    /// Render the step response of the filter from a reset copy of it.
+    pub fn step_response(&self, out: &mut [R]) {
+        let mut filter = *self;
+        filter.reset();
+        step_response(out, |x| filter.filter(x));
+    }
+
+    /// The -3dB points of the filter in Hertz, see [half_power_points].
+    pub fn half_power_points(&self) -> (Option<R>, Option<R>) {
+        let sample_rate = self.get_sample_rate();
+        let (start, stop) = (sample_rate * _c(MIN_FREQUENCY), sample_rate * _c(MAX_FREQUENCY));
+        half_power_points(self, start, stop)
+    }
+}
+
+impl<R: Real + Default + Copy, const B: usize> Equalizer<R, B, 1> {
+    /// Render the impulse response of the equalizer from a reset copy of it.
+    pub fn impulse_response(&self, out: &mut [R]) {
+        let mut eq = *self;
+        eq.reset();
+        impulse_response(out, |x| eq.process(x));
+    }
+
+    /// Render the step response of the equalizer from a reset copy of it.
+    pub fn step_response(&self, out: &mut [R]) {
+        let mut eq = *self;
+        eq.reset();
+        step_response(out, |x| eq.process(x));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `y = a y + (1 - a) x`, a one pole lowpass of unity DC gain.
+    fn one_pole(a: f64) -> impl FnMut(f64) -> f64 {
+        let mut y = 0.0;
+        move |x| {
+            y = a * y + (1.0 - a) * x;
+            y
+        }
+    }
+
+    #[test]
+    fn one_pole_settling_time() {
+        // The step response is 1 - a^(n + 1).
+        let mut step = vec![0.0; 20000];
+        for a in [0.5, 0.9, 0.99] {
+            step_response(&mut step, one_pole(a));
+            for tolerance in [1e-2, 1e-3] {
+                let expected = f64::ln(tolerance) / f64::ln(a);
+                let settled = settling_time(&step, tolerance);
+                assert!((settled as f64 - expected).abs() <= 1.0, "a = {}: {} against {}", a, settled, expected);
+            }
+        }
+        assert_eq!(settling_time::<f64>(&[], 0.1), 0);
+        assert_eq!(settling_time(&[1.0; 16], 0.1), 0);
+    }
+
+    #[test]
+    fn one_pole_ringing_length() {
+        // The impulse response is (1 - a) a^n, it falls 60dB below its peak at n = -3 / log10(a).
+        let mut impulse = vec![0.0; 20000];
+        for a in [0.5, 0.9, 0.99] {
+            impulse_response(&mut impulse, one_pole(a));
+            let expected = -3.0 / a.log10();
+            let length = ringing_length(&impulse, -60.0);
+            assert!((length as f64 - expected).abs() <= 1.0, "a = {}: {} against {}", a, length, expected);
+        }
+        let mut silence = [0.0; 16];
+        impulse_response(&mut silence, |_| 0.0);
+        assert_eq!(ringing_length(&silence, -60.0), 0);
+    }
+
+    #[test]
+    fn bandpass_half_power_points() {
+        let sample_rate = 48e3;
+        for (center, resonance) in [(100.0, 2.0), (1e3, 4.0), (10e3, 0.7)] {
+            let mut filter = Filter::new(sample_rate);
+            filter.set(Curve::Bandpass, center, resonance, 1.0, sample_rate);
+            // The analog band edges, where |w / w0 - w0 / w| = 1 / Q, through the bilinear warping.
+            let omega = 4.0 * (core::f64::consts::PI * center / sample_rate).tan();
+            let offset = 1.0 / (2.0 * resonance);
+            let edge = |sign: f64| (omega * (sign * offset + (1.0 + offset * offset).sqrt()) / 4.0).atan() / core::f64::consts::PI * sample_rate;
+            let (lower, upper) = filter.half_power_points();
+            let (lower, upper) = (lower.unwrap(), upper.unwrap());
+            assert!((lower / edge(-1.0) - 1.0).abs() < 1e-6, "{} Hz: {} against {}", center, lower, edge(-1.0));
+            assert!((upper / edge(1.0) - 1.0).abs() < 1e-6, "{} Hz: {} against {}", center, upper, edge(1.0));
+        }
+    }
+
+    #[test]
+    fn lowpass_and_highpass_half_power_points() {
+        let mut filter = Filter::new(48e3);
+        filter.set(Curve::Lowpass, 2e3, core::f64::consts::FRAC_1_SQRT_2, 1.0, 48e3);
+        let (lower, upper) = filter.half_power_points();
+        assert!(lower.is_none() && (upper.unwrap() / 2e3 - 1.0).abs() < 1e-6, "{:?}", upper);
+        filter.set(Curve::Highpass, 2e3, core::f64::consts::FRAC_1_SQRT_2, 1.0, 48e3);
+        let (lower, upper) = filter.half_power_points();
+        assert!(upper.is_none() && (lower.unwrap() / 2e3 - 1.0).abs() < 1e-6, "{:?}", lower);
+        filter.set(Curve::Allpass, 2e3, 1.0, 1.0, 48e3);
+        assert_eq!(filter.half_power_points(), (None, None));
+    }
+
+    #[test]
+    fn responses_leave_the_filter_untouched() {
+        let mut filter = Filter::new(48e3);
+        filter.set(Curve::Lowpass, 100.0, 1.0, 1.0, 48e3);
+        for _ in 0..100 {
+            filter.filter(1.0);
+        }
+        let state = filter.get_state();
+        let mut out = [0.0; 64];
+        filter.impulse_response(&mut out);
+        assert_eq!(out[0], filter.get_design().digital_xfer_fn().0[0]);
+        filter.step_response(&mut out);
+        assert_eq!(filter.get_state(), state);
+    }
+}
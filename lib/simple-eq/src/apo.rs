@@ -0,0 +1,330 @@
+//! The filter text format of Equalizer APO, also exported by Room EQ Wizard.
+//!
+//! Every filter is a line like `Filter 1: ON PK Fc 1000 Hz Gain -3 dB Q 1.41`. The types
+//! `PK`, `LP`, `LPQ`, `HP`, `HPQ`, `BP`, `NO`, `AP`, `LS`, `LSC`, `HS` and `HSC` are
+//! understood, with the bandwidth given either as `Q` or as `BW Oct`. Shelf slopes in
+//! dB (`LSC 12dB`) are ignored, the slope of a second order shelf is set by its `Q`.
+//! Other lines, such as `Preamp:` or `Channel:`, are skipped.
+use crate::design::*;
+use crate::error::Error;
+use crate::Equalizer;
+use core::fmt;
+use nalgebra::{convert as _c, RealField as Real};
+
+/// A single filter line, with its frequency in Hertz and its gain in decibels.
+#[derive(Copy, Clone, Debug)]
+#[allow(non_snake_case)]
+pub struct ApoFilter<R: Real> {
+    pub enabled: bool,
+    pub curve: Curve,
+    pub frequency: R,
+    pub resonance: R,
+    pub gain_dB: R,
+}
+
+/// The APO type of a curve, if the format has one.
+fn apo_type(curve: Curve) -> Option<&'static str> {
+    match curve {
+        Curve::Peak => Some("PK"),
+        Curve::Lowpass => Some("LPQ"),
+        Curve::Highpass => Some("HPQ"),
+        Curve::Bandpass => Some("BP"),
+        Curve::Notch => Some("NO"),
+        Curve::Allpass => Some("AP"),
+        Curve::Lowshelf => Some("LSC"),
+        Curve::Highshelf => Some("HSC"),
+        _ => None,
+    }
+}
+
+/// Parse the next token as a number.
+fn number<'a, R: Real + Copy>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<R, Error> {
+    tokens
+        .next()
+        .and_then(|t| t.parse::<f64>().ok())
+        .map(_c)
+        .ok_or(Error::InvalidSyntax)
+}
+
+impl<R: Real + Copy> ApoFilter<R> {
+    /// Parse a line of text. Lines that are not filters, and filters of type `None`, are `None`.
+    pub fn parse(line: &str) -> Result<Option<Self>, Error> {
+        let line = match line.trim().strip_prefix("Filter") {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        // The filter number is optional. Other lines starting with `Filter`, like the
+        // `Filter Settings file` header of REW, are not filters.
+        let is_index = |t: &str| t.is_empty() || t.parse::<u32>().is_ok();
+        let line = match line.split_once(':') {
+            Some((index, line)) if is_index(index.trim()) => line,
+            None if is_index(line.split_whitespace().next().unwrap_or("")) => return Err(Error::InvalidSyntax),
+            _ => return Ok(None),
+        };
+        let mut tokens = line.split_whitespace();
+        let enabled = match tokens.next() {
+            Some("ON") => true,
+            Some("OFF") => false,
+            _ => return Err(Error::InvalidSyntax),
+        };
+        let curve = match tokens.next() {
+            Some("PK") | Some("PEQ") => Curve::Peak,
+            Some("LP") | Some("LPQ") => Curve::Lowpass,
+            Some("HP") | Some("HPQ") => Curve::Highpass,
+            Some("BP") => Curve::Bandpass,
+            Some("NO") => Curve::Notch,
+            Some("AP") => Curve::Allpass,
+            Some("LS") | Some("LSC") => Curve::Lowshelf,
+            Some("HS") | Some("HSC") => Curve::Highshelf,
+            Some("None") | None => return Ok(None),
+            Some(_) => return Err(Error::UnsupportedCurve),
+        };
+        let mut filter = ApoFilter {
+            enabled,
+            curve,
+            frequency: R::zero(),
+            resonance: _c(core::f64::consts::FRAC_1_SQRT_2),
+            gain_dB: R::zero(),
+        };
+        let mut frequency = false;
+        while let Some(token) = tokens.next() {
+            match token {
+                "Fc" => {
+                    filter.frequency = number(&mut tokens)?;
+                    frequency = true;
+                }
+                "Gain" => filter.gain_dB = number(&mut tokens)?,
+                "Q" => filter.resonance = number(&mut tokens)?,
+                "BW" => {
+                    if tokens.next() != Some("Oct") {
+                        return Err(Error::InvalidSyntax);
+                    }
+                    let ratio = _c::<f64, R>(2.0).powf(number(&mut tokens)?);
+                    filter.resonance = ratio.sqrt() / (ratio - R::one());
+                }
+                "Hz" | "dB" => {}
+                t if t.ends_with("dB") => {}
+                _ => return Err(Error::InvalidSyntax),
+            }
+        }
+        if !frequency {
+            return Err(Error::InvalidSyntax);
+        }
+        Ok(Some(filter))
+    }
+
+    /// The filter of a design, at `sample_rate`.
+    pub fn from_design(design: &Design<R>, enabled: bool, sample_rate: R) -> Result<Self, Error> {
+        apo_type(design.curve).ok_or(Error::UnsupportedCurve)?;
+        Ok(ApoFilter {
+            enabled,
+            curve: design.curve,
+            frequency: design.frequency * sample_rate,
+            resonance: design.resonance,
+            gain_dB: _c::<f64, R>(20.0) * design.gain.log10(),
+        })
+    }
+
+    /// The linear gain of the filter, as used by a [Design].
+    pub fn gain(&self) -> R {
+        _c::<f64, R>(10.0).powf(self.gain_dB / _c(20.0))
+    }
+}
+
+impl<R: Real + Copy> fmt::Display for ApoFilter<R> {
+    /// The filter line, without the `Filter N:` prefix. Curves the format lacks are
+    /// written with their [Curve::name].
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.enabled { "ON" } else { "OFF" };
+        let kind = apo_type(self.curve).unwrap_or_else(|| self.curve.name());
+        match self.curve {
+            Curve::Lowpass | Curve::Highpass | Curve::Bandpass | Curve::Notch | Curve::Allpass => write!(
+                f,
+                "{} {} Fc {:.2} Hz Q {:.4}",
+                state, kind, self.frequency, self.resonance
+            ),
+            _ => write!(
+                f,
+                "{} {} Fc {:.2} Hz Gain {:.2} dB Q {:.4}",
+                state, kind, self.frequency, self.gain_dB, self.resonance
+            ),
+        }
+    }
+}
+
+impl<R: Real + Default + Copy, const B: usize, const C: usize> Equalizer<R, B, C> {
+    /// Load the filters of an Equalizer APO or REW text file, in order, into the bands of
+    /// every channel. Bands without a filter are bypassed, and so are filters that are `OFF`.
+    /// Returns the number of filters. The equalizer is left untouched on failure.
+    pub fn load_apo(&mut self, text: &str) -> Result<usize, Error> {
+        let mut filters = [None; B];
+        let mut count = 0;
+        for line in text.lines() {
+            if let Some(filter) = ApoFilter::<R>::parse(line)? {
+                try_normalize_frequency(filter.frequency, self.sample_rate)?;
+                check_resonance(filter.resonance)?;
+                *filters.get_mut(count).ok_or(Error::TooManyBands)? = Some(filter);
+                count += 1;
+            }
+        }
+        for (idx, filter) in filters.iter().enumerate() {
+            match filter {
+                Some(f) => {
+                    self.set(idx, f.curve, f.frequency, f.resonance, f.gain());
+                    self.set_bypass(idx, !f.enabled);
+                }
+                None => self.set_bypass(idx, true),
+            }
+        }
+        Ok(count)
+    }
+
+    /// The bands of channel 0 in the Equalizer APO text format, one line per band that is
+    /// not bypassed. Fails if a band uses a curve the format lacks.
+    #[cfg(feature = "std")]
+    pub fn to_apo(&self) -> Result<String, Error> {
+        let mut text = String::new();
+        let bands = (0..B).filter(|idx| !self.bypass[0][*idx]);
+        for (number, idx) in bands.enumerate() {
+            let filter = ApoFilter::from_design(&self.design[0][idx], true, self.sample_rate)?;
+            text.push_str(&format!("Filter {}: {}\n", number + 1, filter));
+        }
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::FrequencyResponse;
+
+    const REW: &str = "Filter Settings file\r\n\
+        \r\n\
+        Room EQ V5.20\r\n\
+        Preamp: -6.5 dB\r\n\
+        Filter  1: ON  PK       Fc    63.0 Hz  Gain  -3.0 dB  Q  4.00\r\n\
+        Filter  2: OFF PK       Fc   125.0 Hz  Gain   2.5 dB  Q  1.41\r\n\
+        Filter\t3:\tON\tLSC 12dB\tFc 80 Hz\tGain 4 dB\tQ 0.71\r\n\
+        Filter: ON HPQ Fc 20 Hz Q 0.5\r\n\
+        Filter 5: ON PK Fc 1000 Hz Gain -1 dB BW Oct 1\r\n\
+        Filter 6: ON None\r\n";
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() <= 1e-9 * a.abs().max(1.0)
+    }
+
+    #[test]
+    fn parse_rew() {
+        let filters: Vec<ApoFilter<f64>> = REW.lines().filter_map(|l| ApoFilter::parse(l).unwrap()).collect();
+        assert_eq!(filters.len(), 5);
+        let expected = [
+            (true, Curve::Peak, 63.0, 4.0, -3.0),
+            (false, Curve::Peak, 125.0, 1.41, 2.5),
+            (true, Curve::Lowshelf, 80.0, 0.71, 4.0),
+            (true, Curve::Highpass, 20.0, 0.5, 0.0),
+        ];
+        for (filter, (enabled, curve, frequency, resonance, gain_db)) in filters.iter().zip(expected) {
+            assert_eq!(filter.enabled, enabled);
+            assert_eq!(filter.curve.name(), curve.name());
+            assert!(close(filter.frequency, frequency) && close(filter.resonance, resonance) && close(filter.gain_dB, gain_db));
+        }
+        // One octave is a Q of sqrt(2).
+        assert!(close(filters[4].resonance, 2f64.sqrt()));
+    }
+
+    #[test]
+    fn reject_invalid_lines() {
+        assert_eq!(ApoFilter::<f64>::parse("Filter 1: ON TILT Fc 1000 Hz Gain 3 dB").err(), Some(Error::UnsupportedCurve));
+        assert_eq!(ApoFilter::<f64>::parse("Filter 1: MAYBE PK Fc 1000 Hz").err(), Some(Error::InvalidSyntax));
+        assert_eq!(ApoFilter::<f64>::parse("Filter 1: ON PK Gain 3 dB Q 1").err(), Some(Error::InvalidSyntax));
+        assert_eq!(ApoFilter::<f64>::parse("Filter 1: ON PK Fc abc Hz").err(), Some(Error::InvalidSyntax));
+        assert_eq!(ApoFilter::<f64>::parse("Filter 1: ON PK Fc 100 Hz BW 1").err(), Some(Error::InvalidSyntax));
+        assert_eq!(ApoFilter::<f64>::parse("Filter 1 ON PK Fc 100 Hz").err(), Some(Error::InvalidSyntax));
+        assert!(ApoFilter::<f64>::parse("Preamp: -3 dB").unwrap().is_none());
+
+        let tilt = Design { curve: Curve::Tilt, frequency: 0.01, resonance: 1.0, gain: 2.0, method: Method::Bilinear };
+        assert_eq!(ApoFilter::from_design(&tilt, true, 48e3).err(), Some(Error::UnsupportedCurve));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn write_rejects_tilt() {
+        let mut eq = Equalizer::<f64, 4>::with_link(48e3, crate::Link::Linked);
+        eq.set(0, Curve::Tilt, 1e3, 1.0, 2.0);
+        assert_eq!(eq.to_apo().err(), Some(Error::UnsupportedCurve));
+    }
+
+    #[test]
+    fn load_rew() {
+        let mut eq = Equalizer::<f64, 8>::with_link(48e3, crate::Link::Linked);
+        assert_eq!(eq.load_apo(REW), Ok(5));
+        assert!(!eq.bypass[0][0] && eq.bypass[0][1] && !eq.bypass[0][4] && eq.bypass[0][5]);
+        let peak = eq.get_design(0);
+        assert!(close(peak.frequency * 48e3, 63.0) && close(peak.gain, 10f64.powf(-3.0 / 20.0)));
+
+        let mut small = Equalizer::<f64, 4>::with_link(48e3, crate::Link::Linked);
+        assert_eq!(small.load_apo(REW), Err(Error::TooManyBands));
+        assert!(small.bypass[0].iter().all(|b| *b), "untouched on failure");
+        assert_eq!(small.load_apo("Filter 1: ON PK Fc 30000 Hz Gain 1 dB Q 1"), Err(Error::FrequencyOutOfRange));
+    }
+
+    #[test]
+    fn shelves_match_rew() {
+        // REW shelves have their whole gain on the shelf side and 0dB on the other.
+        let text = "Filter 1: ON LSC 12dB Fc 100 Hz Gain 6 dB Q 0.71\n\
+            Filter 2: ON HS Fc 2000 Hz Gain -4.5 dB\n";
+        let mut eq = Equalizer::<f64, 2>::with_link(48e3, crate::Link::Linked);
+        assert_eq!(eq.load_apo(text), Ok(2));
+        for method in [Method::Bilinear, Method::Matched] {
+            let low = Design { method, ..eq.get_design(0) };
+            let high = Design { method, ..eq.get_design(1) };
+            let db = |design: &Design<f64>, frequency: f64| design.magnitude_dB(frequency / 48e3);
+            assert!((db(&low, 5.0) - 6.0).abs() < 0.01 && db(&low, 20e3).abs() < 0.01, "{:?}: {} {}", method, db(&low, 5.0), db(&low, 20e3));
+            assert!((db(&high, 23e3) + 4.5).abs() < 0.05 && db(&high, 5.0).abs() < 0.01, "{:?}: {} {}", method, db(&high, 23e3), db(&high, 5.0));
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn design_round_trip() {
+        let curves = [Curve::Peak, Curve::Lowpass, Curve::Highpass, Curve::Bandpass, Curve::Notch, Curve::Allpass, Curve::Lowshelf, Curve::Highshelf];
+        let mut eq = Equalizer::<f64, 8>::with_link(48e3, crate::Link::Linked);
+        for (idx, curve) in curves.iter().enumerate() {
+            let gain = if matches!(curve, Curve::Peak | Curve::Lowshelf | Curve::Highshelf) { 0.5 + idx as f64 } else { 1.0 };
+            eq.set(idx, *curve, 100.0 * (idx + 1) as f64, 0.5 + 0.25 * idx as f64, gain);
+        }
+        let text = eq.to_apo().unwrap();
+        let mut loaded = Equalizer::<f64, 8>::with_link(48e3, crate::Link::Linked);
+        assert_eq!(loaded.load_apo(&text), Ok(curves.len()));
+        for idx in 0..curves.len() {
+            let (a, b) = (eq.get_design(idx), loaded.get_design(idx));
+            assert_eq!(a.curve.name(), b.curve.name());
+            // The text keeps two decimals of the frequency and gain, and four of the Q.
+            assert!((a.frequency - b.frequency).abs() * 48e3 < 0.005, "{}", text);
+            assert!((a.resonance - b.resonance).abs() < 5e-5, "{}", text);
+            assert!((20.0 * (a.gain / b.gain).log10()).abs() < 0.005, "{}", text);
+        }
+        assert_eq!(loaded.to_apo().unwrap(), text);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let designs = [
+            Design { curve: Curve::Peak, frequency: 0.01, resonance: 2.0, gain: 0.5, method: Method::Bilinear },
+            Design { curve: Curve::Tilt, frequency: 0.2, resonance: 0.7, gain: 4.0, method: Method::Matched },
+            Design { curve: Curve::BandpassConstantSkirt, frequency: 1e-3, resonance: 30.0, gain: 1.0, method: Method::Bilinear },
+        ];
+        for design in designs {
+            let json = serde_json::to_string(&design).unwrap();
+            let loaded: Design<f64> = serde_json::from_str(&json).unwrap();
+            assert_eq!(loaded.curve.name(), design.curve.name());
+            assert_eq!(loaded.method, design.method);
+            assert_eq!((loaded.frequency, loaded.resonance, loaded.gain), (design.frequency, design.resonance, design.gain));
+        }
+        let json = r#"{"curve":"Lowshelf","frequency":0.002,"resonance":0.71,"gain":2.0,"method":"Matched"}"#;
+        let design: Design<f64> = serde_json::from_str(json).unwrap();
+        assert!(matches!(design.curve, Curve::Lowshelf) && design.method == Method::Matched);
+        assert!(serde_json::from_str::<Design<f64>>(r#"{"curve":"Shelf","frequency":0.1,"resonance":1,"gain":1,"method":"Bilinear"}"#).is_err());
+    }
+}
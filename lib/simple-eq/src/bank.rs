@@ -0,0 +1,297 @@
+//! Many independent filter bands evaluated in lockstep.
+//!
+//! A [FilterBank] holds the same state-space formulation as a [Kernel](crate::kernel::Kernel),
+//! but stores every coefficient and state as an array with one lane per band (structure of
+//! arrays). The inner loops have no branches and no dependency between lanes, so the compiler
+//! vectorizes them, which is a lot cheaper than evaluating `N` kernels one after the other.
+use crate::design::*;
+use crate::error::Error;
+use crate::response::*;
+use nalgebra::{Complex, RealField as Real};
+
+#[derive(Copy, Clone, Debug)]
+pub struct FilterBank<R: Real, const N: usize> {
+    // y = c x + s0, then s0 <- b0 x + s1 - a1 s0 and s1 <- b1 x - a2 s0, one lane per band.
+    c: [R; N],
+    b0: [R; N],
+    b1: [R; N],
+    a1: [R; N],
+    a2: [R; N],
+    s0: [R; N],
+    s1: [R; N],
+    design: [Design<R>; N],
+    bypass: [bool; N],
+    sample_rate: R,
+}
+
+impl<R: Real + Copy, const N: usize> FilterBank<R, N> {
+    /// Construct a new bank, with every band bypassed.
+    pub fn new(sample_rate: R) -> Self {
+        Self {
+            c: [R::one(); N],
+            b0: [R::zero(); N],
+            b1: [R::zero(); N],
+            a1: [R::zero(); N],
+            a2: [R::zero(); N],
+            s0: [R::zero(); N],
+            s1: [R::zero(); N],
+            design: [Design::default(); N],
+            bypass: [true; N],
+            sample_rate,
+        }
+    }
+
+    #[inline]
+    pub fn set(&mut self, idx: usize, curve: Curve, frequency: R, resonance: R, gain: R) {
+        self.design[idx] = Design {
+            frequency: normalize_frequency(frequency, self.sample_rate),
+            gain,
+            resonance,
+            curve,
+            method: self.design[idx].method,
+        };
+        self.bypass[idx] = false;
+        self.update(idx);
+    }
+
+    /// Like [FilterBank::set], but fails instead of clamping out of range parameters.
+    /// The band is left untouched on failure.
+    #[inline]
+    pub fn try_set(&mut self, idx: usize, curve: Curve, frequency: R, resonance: R, gain: R) -> Result<(), Error> {
+        try_normalize_frequency(frequency, self.sample_rate)?;
+        check_resonance(resonance)?;
+        self.set(idx, curve, frequency, resonance, gain);
+        Ok(())
+    }
+
+    /// Set the critical frequency of a single band.
+    #[inline]
+    pub fn set_frequency(&mut self, idx: usize, freq_hz: R) {
+        self.design[idx].frequency = normalize_frequency(freq_hz, self.sample_rate);
+        self.update(idx);
+    }
+
+    /// Set the resonance/Q factor of a single band.
+    #[inline]
+    pub fn set_resonance(&mut self, idx: usize, resonance: R) {
+        self.design[idx].resonance = resonance;
+        self.update(idx);
+    }
+
+    /// Set how a single band is mapped from its analog prototype.
+    #[inline]
+    pub fn set_method(&mut self, idx: usize, method: Method) {
+        self.design[idx].method = method;
+        self.update(idx);
+    }
+
+    /// Bypass a single band. A bypassed band passes its input through unchanged.
+    #[inline]
+    pub fn set_bypass(&mut self, idx: usize, bypass: bool) {
+        self.bypass[idx] = bypass;
+        self.update(idx);
+    }
+
+    /// returns the bypass state of a single band
+    #[inline]
+    pub fn is_bypassed(&self, idx: usize) -> bool {
+        self.bypass[idx]
+    }
+
+    /// Change the sample rate of every band, keeping their frequencies in Hertz.
+    pub fn set_sample_rate(&mut self, sample_rate: R) {
+        for idx in 0..N {
+            let freq_hz = self.design[idx].frequency * self.sample_rate;
+            self.design[idx].frequency = normalize_frequency(freq_hz, sample_rate);
+            self.update(idx);
+        }
+        self.sample_rate = sample_rate;
+    }
+
+    /// Gets the design of a single band. Note that the frequency parameter is
+    /// in the units of normalized frequency (1/samples).
+    pub fn get_design(&self, idx: usize) -> Design<R> {
+        self.design[idx]
+    }
+
+    /// The response of a single band at `frequency` in Hertz.
+    pub fn response(&self, idx: usize, frequency: R) -> Complex<R> {
+        if self.bypass[idx] {
+            return Complex::new(R::one(), R::zero());
+        }
+        self.design[idx].response(frequency / self.sample_rate)
+    }
+
+    /// Zero the state of every band.
+    pub fn reset(&mut self) {
+        self.s0 = [R::zero(); N];
+        self.s1 = [R::zero(); N];
+    }
+
+    #[inline]
+    fn update(&mut self, idx: usize) {
+        if self.bypass[idx] {
+            // Keep the lane in the loop as a pass-through, with its state decaying to zero.
+            self.c[idx] = R::one();
+            self.b0[idx] = R::zero();
+            self.b1[idx] = R::zero();
+            self.a1[idx] = R::zero();
+            self.a2[idx] = R::zero();
+            return;
+        }
+        let (num, den) = self.design[idx].digital_xfer_fn();
+        self.c[idx] = num[0];
+        self.b0[idx] = num[1] - den[1] * num[0];
+        self.b1[idx] = num[2] - den[2] * num[0];
+        self.a1[idx] = den[1];
+        self.a2[idx] = den[2];
+    }
+
+    /// Process one sample per band, in place.
+    #[inline]
+    pub fn process(&mut self, samples: &mut [R; N]) {
+        for (i, x) in samples.iter_mut().enumerate() {
+            let s0 = self.s0[i];
+            self.s0[i] = self.b0[i] * *x + self.s1[i] - self.a1[i] * s0;
+            self.s1[i] = self.b1[i] * *x - self.a2[i] * s0;
+            *x = self.c[i] * *x + s0;
+        }
+    }
+
+    /// Process the same input sample with every band, writing one output per band.
+    #[inline]
+    pub fn process_parallel(&mut self, input: R, output: &mut [R; N]) {
+        *output = [input; N];
+        self.process(output);
+    }
+
+    /// Process a buffer with each band, one buffer per band.
+    pub fn process_buffers(&mut self, buffers: &mut [&mut [R]; N]) {
+        let len = buffers.iter().map(|b| b.len()).min().unwrap_or(0);
+        let mut frame = [R::zero(); N];
+        for n in 0..len {
+            for (x, buffer) in frame.iter_mut().zip(buffers.iter()) {
+                *x = buffer[n];
+            }
+            self.process(&mut frame);
+            for (x, buffer) in frame.iter().zip(buffers.iter_mut()) {
+                buffer[n] = *x;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Filter;
+
+    const SR: f64 = 48e3;
+
+    #[derive(Copy, Clone, Debug)]
+    enum Mode {
+        Process,
+        Parallel,
+        Buffers,
+    }
+
+    /// A bank and the independent filters it should match, `None` being a bypassed band.
+    /// The last band is never set and stays bypassed.
+    fn bank() -> (FilterBank<f64, 6>, [Option<Filter<f64>>; 6]) {
+        let bands = [
+            (Curve::Peak, 100.0, 2.0, 4.0),
+            (Curve::Lowpass, 1e3, 0.7, 1.0),
+            (Curve::Highshelf, 5e3, 1.0, 0.5),
+            (Curve::Notch, 440.0, 10.0, 1.0),
+            (Curve::AllpassFirstOrder, 2e3, 1.0, 1.0),
+        ];
+        let mut bank = FilterBank::new(SR);
+        let mut filters = [None; 6];
+        for (idx, (curve, frequency, resonance, gain)) in bands.iter().copied().enumerate() {
+            bank.set(idx, curve, frequency, resonance, gain);
+            let mut filter = Filter::new(SR);
+            filter.set(curve, frequency, resonance, gain, SR);
+            filters[idx] = Some(filter);
+        }
+        (bank, filters)
+    }
+
+    /// `len` frames of noise, the same in every band for [Mode::Parallel].
+    fn frames(mode: Mode, len: usize) -> Vec<[f64; 6]> {
+        let mut noise = crate::filter::tests::noise(6 * len);
+        (0..len)
+            .map(|_| match mode {
+                Mode::Parallel => [noise.next().unwrap(); 6],
+                _ => core::array::from_fn(|_| noise.next().unwrap()),
+            })
+            .collect()
+    }
+
+    fn run_bank(bank: &mut FilterBank<f64, 6>, mode: Mode, input: &[[f64; 6]]) -> Vec<[f64; 6]> {
+        match mode {
+            Mode::Process => input
+                .iter()
+                .map(|frame| {
+                    let mut frame = *frame;
+                    bank.process(&mut frame);
+                    frame
+                })
+                .collect(),
+            Mode::Parallel => input
+                .iter()
+                .map(|frame| {
+                    let mut output = [0.0; 6];
+                    bank.process_parallel(frame[0], &mut output);
+                    output
+                })
+                .collect(),
+            Mode::Buffers => {
+                let mut buffers: [Vec<f64>; 6] = core::array::from_fn(|idx| input.iter().map(|frame| frame[idx]).collect());
+                bank.process_buffers(&mut buffers.each_mut().map(|buffer| buffer.as_mut_slice()));
+                (0..input.len()).map(|n| core::array::from_fn(|idx| buffers[idx][n])).collect()
+            }
+        }
+    }
+
+    fn run_filters(filters: &mut [Option<Filter<f64>>; 6], input: &[[f64; 6]]) -> Vec<[f64; 6]> {
+        input
+            .iter()
+            .map(|frame| core::array::from_fn(|idx| filters[idx].as_mut().map_or(frame[idx], |filter| filter.filter(frame[idx]))))
+            .collect()
+    }
+
+    /// Compare the bank with the filters from the `skip`th sample on.
+    fn compare(bank: &mut FilterBank<f64, 6>, filters: &mut [Option<Filter<f64>>; 6], mode: Mode, skip: usize, step: &str) {
+        let input = frames(mode, 4000);
+        let expected = run_filters(filters, &input);
+        for (n, (y, e)) in run_bank(bank, mode, &input).iter().zip(expected.iter()).enumerate().skip(skip) {
+            for idx in 0..6 {
+                assert!((y[idx] - e[idx]).abs() < 1e-12, "{:?} {} at {}, band {}: {} {}", mode, step, n, idx, y[idx], e[idx]);
+            }
+        }
+    }
+
+    #[test]
+    fn bank_matches_independent_filters() {
+        for mode in [Mode::Process, Mode::Parallel, Mode::Buffers] {
+            let (mut bank, mut filters) = bank();
+            compare(&mut bank, &mut filters, mode, 0, "set");
+
+            bank.reset();
+            filters.iter_mut().flatten().for_each(|filter| filter.reset());
+            compare(&mut bank, &mut filters, mode, 0, "reset");
+
+            // The state of a newly bypassed band takes two samples to leave the output.
+            bank.set_bypass(1, true);
+            let mut bypassed = filters;
+            bypassed[1] = None;
+            compare(&mut bank, &mut bypassed, mode, 2, "bypass");
+
+            // By then the state is zero, as the one of a reset filter.
+            bank.set_bypass(1, false);
+            filters[1].as_mut().unwrap().reset();
+            bypassed[1] = filters[1];
+            compare(&mut bank, &mut bypassed, mode, 0, "unbypass");
+        }
+    }
+}
@@ -0,0 +1,549 @@
+#![allow(clippy::just_underscores_and_digits)]
+//! Higher order filters built from a cascade of second order sections.
+//!
+//! The analog prototypes are designed from their poles and zeros, moved to the
+//! requested band and mapped to the digital domain with a prewarped bilinear
+//! transform, one [Kernel] per conjugate pair.
+use crate::design::*;
+use crate::error::Error;
+use crate::kernel::*;
+use nalgebra::{convert as _c, Complex, ComplexField, RealField as Real, Vector3 as Vec3};
+
+/// Number of Landen iterations used by the elliptic functions.
+const LANDEN: usize = 7;
+
+/// The family of the analog prototype.
+#[derive(Copy, Clone, Debug)]
+pub enum Response<R: Real> {
+    /// Maximally flat, `frequency` is the -3dB point.
+    Butterworth,
+    /// Equiripple passband with `ripple` dB, `frequency` is the passband edge.
+    ChebyshevI { ripple: R },
+    /// Equiripple stopband `attenuation` dB down, `frequency` is the stopband edge.
+    ChebyshevII { attenuation: R },
+    /// Equiripple in both bands, `frequency` is the passband edge.
+    Elliptic { ripple: R, attenuation: R },
+}
+
+/// A filter made of up to `N` second order sections, for orders up to `2 * N`.
+///
+/// Only [Curve::Lowpass], [Curve::Highpass] and [Curve::Bandpass] are supported, the other
+/// curves leave the curve unchanged. For the bandpass, `resonance` is the center frequency
+/// over the bandwidth of the passband.
+#[derive(Copy, Clone, Debug)]
+pub struct CascadeFilter<R: Real, const N: usize> {
+    kernel: [Kernel<R>; N],
+    sections: usize,
+    curve: Curve,
+    response: Response<R>,
+    frequency: R,
+    resonance: R,
+    sample_rate: R,
+}
+
+impl<R: Real + Copy, const N: usize> CascadeFilter<R, N> {
+    /// Construct a new Butterworth lowpass of the highest order the cascade allows.
+    pub fn new(sample_rate: R) -> Self {
+        let mut self_ = Self {
+            kernel: [Kernel::new(); N],
+            sections: N,
+            curve: Curve::Lowpass,
+            response: Response::Butterworth,
+            frequency: sample_rate / _c(8.0),
+            resonance: _c(core::f64::consts::FRAC_1_SQRT_2),
+            sample_rate,
+        };
+        self_.update();
+        self_
+    }
+
+    /// Set every design parameter at once. `order` is rounded down to an even number and
+    /// clamped between 2 and `2 * N`, so that an order of 3 builds a second order filter.
+    #[allow(clippy::too_many_arguments)]
+    pub fn set(&mut self, curve: Curve, response: Response<R>, frequency: R, resonance: R, order: usize, sample_rate: R) {
+        if Self::check_curve(curve).is_ok() {
+            self.curve = curve;
+        }
+        self.response = response;
+        self.frequency = frequency;
+        self.resonance = resonance;
+        self.sample_rate = sample_rate;
+        self.sections = Self::sections(order);
+        self.update();
+    }
+
+    /// Like [CascadeFilter::set], but fails on an unsupported curve, or on an order that is
+    /// odd or outside of 2 to `2 * N`, instead of rounding it. The filter is left untouched
+    /// on failure.
+    #[allow(clippy::too_many_arguments)]
+    pub fn try_set(&mut self, curve: Curve, response: Response<R>, frequency: R, resonance: R, order: usize, sample_rate: R) -> Result<(), Error> {
+        Self::check_curve(curve)?;
+        if order < 2 || order > 2 * N || !order.is_multiple_of(2) {
+            return Err(Error::InvalidOrder);
+        }
+        try_normalize_frequency(frequency, sample_rate)?;
+        check_resonance(resonance)?;
+        self.set(curve, response, frequency, resonance, order, sample_rate);
+        Ok(())
+    }
+
+    /// Set the order of the filter, from 2 up to `2 * N`, rounded down to an even number and
+    /// clamped to that range.
+    #[inline]
+    pub fn set_order(&mut self, order: usize) {
+        self.sections = Self::sections(order);
+        self.update();
+    }
+
+    /// Get the order of the filter.
+    #[inline]
+    pub fn get_order(&self) -> usize {
+        2 * self.sections
+    }
+
+    /// Set the curve parameter (lowpass, highpass or bandpass) of the filter.
+    #[inline]
+    pub fn set_curve(&mut self, curve: Curve) {
+        if Self::check_curve(curve).is_ok() {
+            self.curve = curve;
+            self.update();
+        }
+    }
+
+    /// Like [CascadeFilter::set_curve], but fails on an unsupported curve.
+    #[inline]
+    pub fn try_set_curve(&mut self, curve: Curve) -> Result<(), Error> {
+        Self::check_curve(curve)?;
+        self.set_curve(curve);
+        Ok(())
+    }
+
+    /// Set the prototype response of the filter.
+    #[inline]
+    pub fn set_response(&mut self, response: Response<R>) {
+        self.response = response;
+        self.update();
+    }
+
+    /// Set the critical frequency of the filter.
+    #[inline]
+    pub fn set_frequency(&mut self, freq_hz: R) {
+        self.frequency = freq_hz;
+        self.update();
+    }
+
+    /// Set the resonance (aka "Q" factor) of a bandpass.
+    #[inline]
+    pub fn set_resonance(&mut self, resonance: R) {
+        self.resonance = resonance;
+        self.update();
+    }
+
+    /// Change the sample rate of the filter.
+    #[inline]
+    pub fn set_sample_rate(&mut self, sample_rate: R) {
+        self.sample_rate = sample_rate;
+        self.update();
+    }
+
+    /// Take the design of `other`, keeping the state of this filter, so that a design can be
+    /// computed ahead, e.g. on another thread.
+    pub fn copy_design(&mut self, other: &Self) {
+        for (kernel, design) in self.kernel.iter_mut().zip(other.kernel.iter()) {
+            kernel.copy_coefficients(design);
+        }
+        self.sections = other.sections;
+        self.curve = other.curve;
+        self.response = other.response;
+        self.frequency = other.frequency;
+        self.resonance = other.resonance;
+        self.sample_rate = other.sample_rate;
+    }
+
+    /// Get the second order sections currently in use.
+    pub fn get_kernels(&self) -> &[Kernel<R>] {
+        &self.kernel[..self.sections]
+    }
+
+    /// Zero the state of the filter.
+    pub fn reset(&mut self) {
+        for k in self.kernel.iter_mut() {
+            k.reset();
+        }
+    }
+
+    #[inline]
+    pub fn filter(&mut self, x: R) -> R {
+        self.kernel[..self.sections].iter_mut().fold(x, |x, k| k.eval(x))
+    }
+
+    #[inline]
+    pub fn filter_buffer(&mut self, input: &mut [R]) {
+        for x in input {
+            *x = self.filter(*x);
+        }
+    }
+
+    fn check_curve(curve: Curve) -> Result<(), Error> {
+        match curve {
+            Curve::Lowpass | Curve::Highpass | Curve::Bandpass => Ok(()),
+            _ => Err(Error::UnsupportedCurve),
+        }
+    }
+
+    fn sections(order: usize) -> usize {
+        (order / 2).clamp(1, N)
+    }
+
+    fn update(&mut self) {
+        let pi = R::pi();
+        let (_1, _2) = (_c::<f64, R>(1.0), _c::<f64, R>(2.0));
+        let normalized = normalize_frequency(self.frequency, self.sample_rate);
+        let omega = _2 * (pi * normalized).tan();
+        let bandwidth = omega / self.resonance.max(_c(MIN_RESONANCE));
+        let (z_ref, prototype_order) = match self.curve {
+            Curve::Lowpass => (Complex::new(_1, R::zero()), 2 * self.sections),
+            Curve::Highpass => (Complex::new(-_1, R::zero()), 2 * self.sections),
+            // The setters only store the curves above.
+            _ => (Complex::new((_2 * pi * normalized).cos(), (_2 * pi * normalized).sin()), self.sections),
+        };
+
+        let origin = Complex::new(R::zero(), R::zero());
+        let omega_c = Complex::new(omega, R::zero());
+        let mut idx = 0;
+        for i in 0..prototype_order.div_ceil(2) {
+            let (pole, zero) = prototype(self.response, prototype_order, i);
+            let sections = match self.curve {
+                Curve::Lowpass => {
+                    let (pole, zero) = (pole * omega, zero.map(|z| z * omega));
+                    [Some(([pole, pole.conj()], [zero, zero.map(|z| z.conj())])), None]
+                }
+                Curve::Highpass => {
+                    let (pole, zero) = (omega_c / pole, zero.map(|z| omega_c / z).unwrap_or(origin));
+                    [Some(([pole, pole.conj()], [Some(zero), Some(zero.conj())])), None]
+                }
+                _ => {
+                    let (pole_a, pole_b) = lowpass_to_bandpass(pole, omega, bandwidth);
+                    if pole.im <= R::zero() {
+                        // A real prototype pole maps to a single section.
+                        [Some(([pole_a, pole_b], [Some(origin), None])), None]
+                    } else {
+                        let (pole_a, pole_b) = sort_by_height(upper(pole_a), upper(pole_b));
+                        let (zero_a, zero_b) = match zero {
+                            Some(zero) => {
+                                let (zero_a, zero_b) = lowpass_to_bandpass(zero, omega, bandwidth);
+                                let (zero_a, zero_b) = sort_by_height(upper(zero_a), upper(zero_b));
+                                ([Some(zero_a), Some(zero_a.conj())], [Some(zero_b), Some(zero_b.conj())])
+                            }
+                            None => ([Some(origin), None], [Some(origin), None]),
+                        };
+                        [Some(([pole_a, pole_a.conj()], zero_a)), Some(([pole_b, pole_b.conj()], zero_b))]
+                    }
+                }
+            };
+            for section in sections.iter().flatten() {
+                self.kernel[idx].set_section(*section, z_ref);
+                idx += 1;
+            }
+        }
+
+        // Even order Chebyshev I and elliptic responses start at the bottom of the ripple.
+        let ripple = match self.response {
+            Response::ChebyshevI { ripple } | Response::Elliptic { ripple, .. } if prototype_order % 2 == 0 => {
+                _1 / (_1 + ripple_epsilon(ripple).powi(2)).sqrt()
+            }
+            _ => _1,
+        };
+        self.kernel[0].scale(ripple);
+    }
+}
+
+/// An analog second order section as its two poles and two zeros, `None` being a zero at infinity.
+type Section<R> = ([Complex<R>; 2], [Option<Complex<R>>; 2]);
+
+impl<R: Real + Copy> Kernel<R> {
+    /// Set the kernel from an analog section through the bilinear transform, with unity gain at `z_ref`.
+    fn set_section(&mut self, section: Section<R>, z_ref: Complex<R>) {
+        let (poles, zeros) = section;
+        let nyquist = Complex::new(-R::one(), R::zero());
+        let den = quadratic(bilinear(poles[0]), bilinear(poles[1]));
+        let num = quadratic(zeros[0].map_or(nyquist, bilinear), zeros[1].map_or(nyquist, bilinear));
+        let z_inv = z_ref.conj();
+        let eval = |c: &Vec3<R>| Complex::new(c[0], R::zero()) + (Complex::new(c[1], R::zero()) + z_inv * c[2]) * z_inv;
+        let gain = (eval(&den) / eval(&num)).modulus();
+        self.set(num * gain, den);
+    }
+
+    /// Scale the output of the kernel.
+    fn scale(&mut self, gain: R) {
+        self.B *= gain;
+        self.C[0] *= gain;
+    }
+}
+
+/// Bilinear transform of an analog root, for a prewarped frequency of `2 * tan(pi * f)`.
+#[inline]
+pub(crate) fn bilinear<R: Real + Copy>(s: Complex<R>) -> Complex<R> {
+    let two = Complex::new(_c::<f64, R>(2.0), R::zero());
+    (two + s) / (two - s)
+}
+
+/// Coefficients of `(1 - a z^-1)(1 - b z^-1)`.
+#[inline]
+pub(crate) fn quadratic<R: Real + Copy>(a: Complex<R>, b: Complex<R>) -> Vec3<R> {
+    Vec3::new(R::one(), -(a + b).re, (a * b).re)
+}
+
+/// Map a lowpass prototype root to the two roots of `s^2 - root * bandwidth * s + omega^2`.
+fn lowpass_to_bandpass<R: Real + Copy>(root: Complex<R>, omega: R, bandwidth: R) -> (Complex<R>, Complex<R>) {
+    let half = root * bandwidth * _c::<f64, R>(0.5);
+    let delta = ComplexField::sqrt(half * half - Complex::new(omega * omega, R::zero()));
+    (half + delta, half - delta)
+}
+
+/// The representative of a conjugate pair in the upper half plane.
+#[inline]
+fn upper<R: Real + Copy>(z: Complex<R>) -> Complex<R> {
+    if z.im < R::zero() { z.conj() } else { z }
+}
+
+#[inline]
+fn sort_by_height<R: Real + Copy>(a: Complex<R>, b: Complex<R>) -> (Complex<R>, Complex<R>) {
+    if a.im.abs() <= b.im.abs() { (a, b) } else { (b, a) }
+}
+
+#[inline]
+fn ripple_epsilon<R: Real + Copy>(db: R) -> R {
+    (_c::<f64, R>(10.0).powf(db / _c(10.0)) - R::one()).sqrt()
+}
+
+/// The `i`th pole of the normalized lowpass prototype of `order`, taken in the upper half
+/// plane, and its matching zero. The last pole of an odd order prototype is real.
+pub(crate) fn prototype<R: Real + Copy>(response: Response<R>, order: usize, i: usize) -> (Complex<R>, Option<Complex<R>>) {
+    let (_1, pi) = (R::one(), R::pi());
+    let n: R = _c(order as f64);
+    let theta = pi * _c::<f64, R>((2 * i + 1) as f64) / (_c::<f64, R>(2.0) * n);
+    let real = 2 * i + 1 == order;
+    let (sin, cos) = (theta.sin(), if real { R::zero() } else { theta.cos() });
+    match response {
+        Response::Butterworth => (Complex::new(-sin, cos), None),
+        Response::ChebyshevI { ripple } => {
+            let mu = (_1 / ripple_epsilon(ripple)).asinh() / n;
+            (Complex::new(-mu.sinh() * sin, mu.cosh() * cos), None)
+        }
+        Response::ChebyshevII { attenuation } => {
+            let mu = ripple_epsilon(attenuation).asinh() / n;
+            let pole = upper(Complex::new(_1, R::zero()) / Complex::new(-mu.sinh() * sin, mu.cosh() * cos));
+            let zero = if real { None } else { Some(Complex::new(R::zero(), _1 / cos)) };
+            (pole, zero)
+        }
+        Response::Elliptic { ripple, attenuation } => elliptic(ripple, attenuation, order, i),
+    }
+}
+
+/// Elliptic prototype after S. J. Orfanidis, "Lecture Notes on Elliptic Filter Design".
+fn elliptic<R: Real + Copy>(ripple: R, attenuation: R, order: usize, i: usize) -> (Complex<R>, Option<Complex<R>>) {
+    let j = Complex::new(R::zero(), R::one());
+    let n: R = _c(order as f64);
+    let (ep, es) = (ripple_epsilon(ripple), ripple_epsilon(attenuation));
+    let k1 = ep / es;
+    let k = ellipdeg(order, k1);
+    let v0 = (-j * asne(j / ep, k1) / n).re;
+    if 2 * i + 1 == order {
+        let pole = j * sne(Complex::new(R::zero(), v0), k);
+        return (Complex::new(pole.re, R::zero()), None);
+    }
+    let u = _c::<f64, R>((2 * i + 1) as f64) / n;
+    let zeta = cde(Complex::new(u, R::zero()), k).re;
+    let zero = Complex::new(R::zero(), R::one() / (k * zeta));
+    let pole = j * cde(Complex::new(u, -v0), k);
+    (upper(pole), Some(zero))
+}
+
+/// Descending Landen sequence of the modulus `k`.
+fn landen<R: Real + Copy>(k: R) -> [R; LANDEN] {
+    let mut v = [R::zero(); LANDEN];
+    let mut k = k;
+    for v in v.iter_mut() {
+        k = (k / (R::one() + (R::one() - k * k).sqrt())).powi(2);
+        *v = k;
+    }
+    v
+}
+
+/// Complete elliptic integral of the first kind.
+fn ellipk<R: Real + Copy>(k: R) -> R {
+    landen(k).iter().fold(R::frac_pi_2(), |acc, v| acc * (R::one() + *v))
+}
+
+/// Solve the degree equation for the modulus `k` given the order and `k1`.
+fn ellipdeg<R: Real + Copy>(order: usize, k1: R) -> R {
+    let n: R = _c(order as f64);
+    let k1p = (R::one() - k1 * k1).sqrt();
+    let q1 = (-R::pi() * ellipk(k1p) / ellipk(k1)).exp();
+    let q = q1.powf(R::one() / n);
+    let (mut a, mut b) = (R::zero(), R::zero());
+    for m in 1..=LANDEN {
+        let m: R = _c(m as f64);
+        a += q.powf(m * (m + R::one()));
+        b += q.powf(m * m);
+    }
+    _c::<f64, R>(4.0) * q.sqrt() * ((R::one() + a) / (R::one() + _c::<f64, R>(2.0) * b)).powi(2)
+}
+
+/// Jacobi elliptic `cd` function with argument `u * K`.
+fn cde<R: Real + Copy>(u: Complex<R>, k: R) -> Complex<R> {
+    descend(ComplexField::cos(u * R::frac_pi_2()), k)
+}
+
+/// Jacobi elliptic `sn` function with argument `u * K`.
+fn sne<R: Real + Copy>(u: Complex<R>, k: R) -> Complex<R> {
+    descend(ComplexField::sin(u * R::frac_pi_2()), k)
+}
+
+fn descend<R: Real + Copy>(w: Complex<R>, k: R) -> Complex<R> {
+    let one = Complex::new(R::one(), R::zero());
+    landen(k).iter().rev().fold(w, |w, v| w * (R::one() + *v) / (one + w * w * *v))
+}
+
+/// Inverse of [sne], in units of `K`.
+fn asne<R: Real + Copy>(w: Complex<R>, k: R) -> Complex<R> {
+    let one = Complex::new(R::one(), R::zero());
+    let v = landen(k);
+    let mut w = w;
+    let mut previous = k;
+    for v in v.iter() {
+        w = w / (one + ComplexField::sqrt(one - w * w * previous * previous)) * (_c::<f64, R>(2.0) / (R::one() + *v));
+        previous = *v;
+    }
+    ComplexField::asin(w) * (_c::<f64, R>(2.0) / R::pi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::FrequencyResponse;
+
+    /// Gain of the cascade in dB at `frequency` in Hertz.
+    fn gain_db<const N: usize>(filter: &CascadeFilter<f64, N>, frequency: f64) -> f64 {
+        let f = frequency / filter.sample_rate;
+        filter.get_kernels().iter().map(|k| k.magnitude_dB(f)).sum()
+    }
+
+    #[test]
+    fn butterworth_slopes() {
+        for (order, slope) in [(2, -12.0), (4, -24.0), (8, -48.0)] {
+            let mut lowpass = CascadeFilter::<f64, 4>::new(48e3);
+            lowpass.set(Curve::Lowpass, Response::Butterworth, 100.0, 0.5_f64.sqrt(), order, 48e3);
+            assert!((gain_db(&lowpass, 100.0) + 3.01).abs() < 0.01, "order {}", order);
+            let octave = gain_db(&lowpass, 1600.0) - gain_db(&lowpass, 800.0);
+            assert!((octave - slope).abs() < 0.5, "order {}: {} dB/oct", order, octave);
+
+            let mut highpass = CascadeFilter::<f64, 4>::new(48e3);
+            highpass.set(Curve::Highpass, Response::Butterworth, 2000.0, 0.5_f64.sqrt(), order, 48e3);
+            assert!((gain_db(&highpass, 2000.0) + 3.01).abs() < 0.01, "order {}", order);
+            let octave = gain_db(&highpass, 62.5) - gain_db(&highpass, 125.0);
+            assert!((octave - slope).abs() < 0.5, "order {}: {} dB/oct", order, octave);
+        }
+    }
+
+    /// The lowest and highest gain in dB between `start` and `stop` Hertz.
+    fn gain_range<const N: usize>(filter: &CascadeFilter<f64, N>, start: f64, stop: f64) -> (f64, f64) {
+        crate::response::log_sweep(start, stop, 4000)
+            .map(|f| gain_db(filter, f))
+            .fold((f64::MAX, f64::MIN), |(low, high), g| (low.min(g), high.max(g)))
+    }
+
+    #[test]
+    fn chebyshev_i_ripple_and_slope() {
+        for order in [4, 8] {
+            let mut lowpass = CascadeFilter::<f64, 4>::new(48e3);
+            lowpass.set(Curve::Lowpass, Response::ChebyshevI { ripple: 1.0 }, 100.0, 1.0, order, 48e3);
+            // The passband ripples between 0 and -1dB, down to -1dB at its edge.
+            let (low, high) = gain_range(&lowpass, 5.0, 100.0);
+            assert!((low + 1.0).abs() < 1e-6 && high <= 1e-9 && high > -1e-3, "order {}: {} to {}", order, low, high);
+            assert!((gain_db(&lowpass, 100.0) + 1.0).abs() < 1e-6, "order {}", order);
+            // Steeper than a Butterworth of the same order above the edge, 6dB/oct per pole far from it.
+            let mut butterworth = lowpass;
+            butterworth.set_response(Response::Butterworth);
+            assert!(gain_db(&lowpass, 200.0) < gain_db(&butterworth, 200.0) - 6.0, "order {}", order);
+            let octave = gain_db(&lowpass, 3200.0) - gain_db(&lowpass, 1600.0);
+            assert!((octave + 6.0 * order as f64).abs() < 1.0, "order {}: {} dB/oct", order, octave);
+
+            let mut highpass = CascadeFilter::<f64, 4>::new(48e3);
+            highpass.set(Curve::Highpass, Response::ChebyshevI { ripple: 1.0 }, 2000.0, 1.0, order, 48e3);
+            let (low, high) = gain_range(&highpass, 2000.0, 23000.0);
+            assert!((low + 1.0).abs() < 1e-6 && high <= 1e-9 && high > -1e-3, "order {}: {} to {}", order, low, high);
+        }
+    }
+
+    #[test]
+    fn chebyshev_ii_stopband() {
+        for order in [4, 8] {
+            let mut lowpass = CascadeFilter::<f64, 4>::new(48e3);
+            lowpass.set(Curve::Lowpass, Response::ChebyshevII { attenuation: 40.0 }, 1000.0, 1.0, order, 48e3);
+            // At least 40dB down from the stopband edge on, reaching -40dB between the zeros.
+            let (_, high) = gain_range(&lowpass, 1000.0, 23900.0);
+            assert!((high + 40.0).abs() < 1e-6, "order {}: {}", order, high);
+            assert!((gain_db(&lowpass, 1000.0) + 40.0).abs() < 1e-6, "order {}", order);
+            // A flat passband.
+            let (low, high) = gain_range(&lowpass, 5.0, 100.0);
+            assert!(low > -1e-3 && high < 1e-9, "order {}: {} to {}", order, low, high);
+
+            let mut highpass = CascadeFilter::<f64, 4>::new(48e3);
+            highpass.set(Curve::Highpass, Response::ChebyshevII { attenuation: 40.0 }, 1000.0, 1.0, order, 48e3);
+            let (_, high) = gain_range(&highpass, 5.0, 1000.0);
+            assert!((high + 40.0).abs() < 1e-6, "order {}: {}", order, high);
+            let (low, high) = gain_range(&highpass, 10e3, 20e3);
+            assert!(low > -1e-3 && high < 1e-9, "order {}: {} to {}", order, low, high);
+        }
+    }
+
+    #[test]
+    fn elliptic_ripple_and_stopband() {
+        let (ripple, attenuation, frequency, sample_rate) = (0.5, 60.0, 1000.0, 48e3);
+        for order in [4, 8] {
+            let mut lowpass = CascadeFilter::<f64, 4>::new(sample_rate);
+            lowpass.set(Curve::Lowpass, Response::Elliptic { ripple, attenuation }, frequency, 1.0, order, sample_rate);
+            let (low, high) = gain_range(&lowpass, 5.0, frequency);
+            assert!((low + ripple).abs() < 1e-6 && high <= 1e-9 && high > -1e-3, "order {}: {} to {}", order, low, high);
+            // The stopband starts at the passband edge over the selectivity, through the warping.
+            let k = ellipdeg(order, ripple_epsilon(ripple) / ripple_epsilon(attenuation));
+            let omega = (core::f64::consts::PI * frequency / sample_rate).tan() / k;
+            let stopband = omega.atan() / core::f64::consts::PI * sample_rate;
+            let (_, high) = gain_range(&lowpass, stopband, 23900.0);
+            assert!(high < -attenuation + 1e-3 && high > -attenuation - 0.01, "order {}: {} from {} Hz", order, high, stopband);
+            // Right below the stopband edge, the attenuation is not reached yet.
+            assert!(gain_db(&lowpass, 0.97 * stopband) > -attenuation, "order {}", order);
+        }
+    }
+
+    #[test]
+    fn set_order_redesigns() {
+        let mut filter = CascadeFilter::<f64, 4>::new(48e3);
+        filter.set(Curve::Lowpass, Response::Butterworth, 100.0, 0.5_f64.sqrt(), 4, 48e3);
+        filter.set_order(8);
+        assert_eq!(filter.get_order(), 8);
+        let octave = gain_db(&filter, 1600.0) - gain_db(&filter, 800.0);
+        assert!((octave + 48.0).abs() < 0.5, "{} dB/oct", octave);
+    }
+
+    #[test]
+    fn copy_design_matches_set() {
+        let mut set = CascadeFilter::<f64, 4>::new(48e3);
+        set.set(Curve::Bandpass, Response::Butterworth, 500.0, 4.0, 4, 48e3);
+        let mut copied = set;
+        let mut design = CascadeFilter::<f64, 4>::new(96e3);
+        design.set(Curve::Lowpass, Response::Butterworth, 2e3, 0.5_f64.sqrt(), 8, 48e3);
+        let input: Vec<f64> = (0..2000).map(|n| ((n * 7919) % 1000) as f64 / 500.0 - 1.0).collect();
+        for x in input[..1000].iter() {
+            assert_eq!(set.filter(*x), copied.filter(*x));
+        }
+        set.set(Curve::Lowpass, Response::Butterworth, 2e3, 0.5_f64.sqrt(), 8, 48e3);
+        copied.copy_design(&design);
+        assert_eq!(copied.get_order(), 8);
+        for x in input[1000..].iter() {
+            assert_eq!(set.filter(*x), copied.filter(*x));
+        }
+    }
+}
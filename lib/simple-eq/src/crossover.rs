@@ -0,0 +1,314 @@
+#![allow(clippy::just_underscores_and_digits)]
+//! Linkwitz-Riley crossovers, and band splitters built from them.
+//!
+//! A Linkwitz-Riley lowpass/highpass pair is a squared Butterworth response. The two
+//! outputs are in phase at every frequency, and they sum to an allpass, so a signal
+//! that is split and summed again keeps a flat magnitude response.
+use crate::cascade::{bilinear, prototype, quadratic, Response};
+use crate::design::*;
+use crate::error::Error;
+use core::convert::TryFrom;
+use crate::kernel::*;
+use nalgebra::{convert as _c, RealField as Real, Vector3 as Vec3};
+
+/// The slope of a crossover.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Crossover {
+    /// 12dB/octave. The highpass output is inverted so that the bands sum flat.
+    LR2,
+    /// 24dB/octave.
+    LR4,
+    /// 48dB/octave.
+    LR8,
+}
+
+impl Crossover {
+    /// The order of the Butterworth filter that is squared.
+    fn butterworth_order(self) -> usize {
+        match self {
+            Crossover::LR2 => 1,
+            Crossover::LR4 => 2,
+            Crossover::LR8 => 4,
+        }
+    }
+}
+
+impl TryFrom<i32> for Crossover {
+    type Error = Error;
+
+    fn try_from(i: i32) -> Result<Crossover, Error> {
+        match i {
+            0 => Ok(Crossover::LR2),
+            1 => Ok(Crossover::LR4),
+            2 => Ok(Crossover::LR8),
+            _ => Err(Error::UnknownVariant),
+        }
+    }
+}
+
+/// The digital denominators of the Butterworth filter of a crossover, one per conjugate pair.
+fn butterworth<R: Real + Copy>(crossover: Crossover, frequency: R, sample_rate: R) -> ([Vec3<R>; 2], usize) {
+    let _2: R = _c(2.0);
+    let omega = _2 * (R::pi() * normalize_frequency(frequency, sample_rate)).tan();
+    let order = crossover.butterworth_order();
+    let mut den = [Vec3::new(R::one(), R::zero(), R::zero()); 2];
+    let pairs = order.div_ceil(2);
+    for (i, d) in den.iter_mut().take(pairs).enumerate() {
+        let (pole, _) = prototype(Response::Butterworth, order, i);
+        let pole = pole * omega;
+        *d = quadratic(bilinear(pole), bilinear(pole.conj()));
+    }
+    (den, pairs)
+}
+
+/// A two way Linkwitz-Riley crossover.
+#[derive(Copy, Clone, Debug)]
+pub struct LinkwitzRiley<R: Real> {
+    lowpass: [Kernel<R>; 4],
+    highpass: [Kernel<R>; 4],
+    sections: usize,
+    crossover: Crossover,
+    frequency: R,
+    sample_rate: R,
+}
+
+impl<R: Real + Copy> LinkwitzRiley<R> {
+    /// Construct a new crossover at a quarter of the Nyquist frequency.
+    pub fn new(crossover: Crossover, sample_rate: R) -> Self {
+        let mut self_ = Self {
+            lowpass: [Kernel::new(); 4],
+            highpass: [Kernel::new(); 4],
+            sections: 0,
+            crossover,
+            frequency: sample_rate / _c(8.0),
+            sample_rate,
+        };
+        self_.update();
+        self_
+    }
+
+    #[inline]
+    pub fn set(&mut self, crossover: Crossover, frequency: R, sample_rate: R) {
+        self.crossover = crossover;
+        self.frequency = frequency;
+        self.sample_rate = sample_rate;
+        self.update();
+    }
+
+    /// Set the slope of the crossover.
+    #[inline]
+    pub fn set_crossover(&mut self, crossover: Crossover) {
+        self.crossover = crossover;
+        self.update();
+    }
+
+    /// Set the crossover frequency, where both outputs are 6dB down.
+    #[inline]
+    pub fn set_frequency(&mut self, freq_hz: R) {
+        self.frequency = freq_hz;
+        self.update();
+    }
+
+    /// Change the sample rate of the crossover.
+    #[inline]
+    pub fn set_sample_rate(&mut self, sample_rate: R) {
+        self.sample_rate = sample_rate;
+        self.update();
+    }
+
+    pub fn get_crossover(&self) -> Crossover {
+        self.crossover
+    }
+
+    pub fn get_frequency(&self) -> R {
+        self.frequency
+    }
+
+    /// Zero the state of the crossover.
+    pub fn reset(&mut self) {
+        for k in self.lowpass.iter_mut().chain(self.highpass.iter_mut()) {
+            k.reset();
+        }
+    }
+
+    /// Split a sample into its low and high parts.
+    #[inline]
+    pub fn split(&mut self, x: R) -> (R, R) {
+        let low = self.lowpass[..self.sections].iter_mut().fold(x, |x, k| k.eval(x));
+        let high = self.highpass[..self.sections].iter_mut().fold(x, |x, k| k.eval(x));
+        (low, high)
+    }
+
+    fn update(&mut self) {
+        let _4: R = _c(4.0);
+        let (den, pairs) = butterworth(self.crossover, self.frequency, self.sample_rate);
+        // The first order Butterworth squared is a single section, the others are used twice.
+        let (sections, sign) = match self.crossover {
+            Crossover::LR2 => (1, -R::one()),
+            _ => (2 * pairs, R::one()),
+        };
+        for idx in 0..sections {
+            let den = den[idx % pairs];
+            let (dc, nyquist) = (den[0] + den[1] + den[2], den[0] - den[1] + den[2]);
+            let lowpass = Vec3::new(R::one(), _c(2.0), R::one()) * (dc / _4);
+            let highpass = Vec3::new(R::one(), _c(-2.0), R::one()) * (sign * nyquist / _4);
+            self.lowpass[idx].set(lowpass, den);
+            self.highpass[idx].set(highpass, den);
+        }
+        self.sections = sections;
+    }
+}
+
+/// The allpass a [LinkwitzRiley] crossover sums to. Used to keep the phase of the
+/// other bands of a [BandSplit] aligned.
+#[derive(Copy, Clone, Debug)]
+pub struct Allpass<R: Real> {
+    kernel: [Kernel<R>; 2],
+    sections: usize,
+}
+
+impl<R: Real + Copy> Default for Allpass<R> {
+    fn default() -> Self {
+        Self {
+            kernel: [Kernel::new(); 2],
+            sections: 0,
+        }
+    }
+}
+
+impl<R: Real + Copy> Allpass<R> {
+    /// Construct a new allpass. Default's to pass-through
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Match the allpass to a crossover. The state is kept.
+    pub fn set(&mut self, crossover: Crossover, frequency: R, sample_rate: R) {
+        let (den, pairs) = butterworth(crossover, frequency, sample_rate);
+        for (k, den) in self.kernel.iter_mut().zip(den.iter()).take(pairs) {
+            let (num, den) = match crossover {
+                // A single real pole b, (-b + z^-1) / (1 - b z^-1).
+                Crossover::LR2 => {
+                    let b = -den[1] / _c(2.0);
+                    (Vec3::new(-b, R::one(), R::zero()), Vec3::new(R::one(), -b, R::zero()))
+                }
+                _ => (Vec3::new(den[2], den[1], den[0]), *den),
+            };
+            k.set(num, den);
+        }
+        self.sections = pairs;
+    }
+
+    /// Zero the state of the allpass.
+    pub fn reset(&mut self) {
+        for k in self.kernel.iter_mut() {
+            k.reset();
+        }
+    }
+
+    #[inline]
+    pub fn filter(&mut self, x: R) -> R {
+        self.kernel[..self.sections].iter_mut().fold(x, |x, k| k.eval(x))
+    }
+}
+
+/// Split a signal into `N + 1` bands at `N` crossover frequencies, from the lowest to
+/// the highest. Every band is phase aligned with the others, so their sum is an allpass.
+#[derive(Copy, Clone, Debug)]
+pub struct BandSplit<R: Real, const N: usize> {
+    crossover: [LinkwitzRiley<R>; N],
+    compensation: [[Allpass<R>; N]; N],
+}
+
+impl<R: Real + Copy, const N: usize> BandSplit<R, N> {
+    pub fn new(crossover: Crossover, sample_rate: R) -> Self {
+        let mut self_ = Self {
+            crossover: [LinkwitzRiley::new(crossover, sample_rate); N],
+            compensation: [[Allpass::new(); N]; N],
+        };
+        for idx in 0..N {
+            self_.update(idx);
+        }
+        self_
+    }
+
+    /// Set every crossover at once. `frequencies` should be sorted in ascending order.
+    pub fn set(&mut self, crossover: Crossover, frequencies: [R; N], sample_rate: R) {
+        for (idx, frequency) in frequencies.iter().enumerate() {
+            self.crossover[idx].set(crossover, *frequency, sample_rate);
+            self.update(idx);
+        }
+    }
+
+    /// Set the slope of every crossover.
+    pub fn set_crossover(&mut self, crossover: Crossover) {
+        for idx in 0..N {
+            self.crossover[idx].set_crossover(crossover);
+            self.update(idx);
+        }
+    }
+
+    /// Set the frequency of a single crossover.
+    pub fn set_frequency(&mut self, idx: usize, freq_hz: R) {
+        self.crossover[idx].set_frequency(freq_hz);
+        self.update(idx);
+    }
+
+    /// Change the sample rate of every crossover.
+    pub fn set_sample_rate(&mut self, sample_rate: R) {
+        for idx in 0..N {
+            self.crossover[idx].set_sample_rate(sample_rate);
+            self.update(idx);
+        }
+    }
+
+    pub fn get_crossover(&self, idx: usize) -> &LinkwitzRiley<R> {
+        &self.crossover[idx]
+    }
+
+    /// Zero the state of every band.
+    pub fn reset(&mut self) {
+        for c in self.crossover.iter_mut() {
+            c.reset();
+        }
+        for a in self.compensation.iter_mut().flatten() {
+            a.reset();
+        }
+    }
+
+    /// Split a sample into `bands`, which should hold `N + 1` values. With fewer, the bands
+    /// that do not fit are summed into the last one, and extra values are zeroed.
+    #[inline]
+    pub fn split(&mut self, x: R, bands: &mut [R]) {
+        let Some(last) = bands.len().checked_sub(1) else {
+            return;
+        };
+        bands.fill(R::zero());
+        let mut rest = x;
+        for (idx, crossover) in self.crossover.iter_mut().enumerate() {
+            let (low, high) = crossover.split(rest);
+            // The band below crossover `idx` still has to go through the allpass of every crossover above it.
+            bands[idx.min(last)] += self.compensation[idx][idx + 1..].iter_mut().fold(low, |x, a| a.filter(x));
+            rest = high;
+        }
+        bands[N.min(last)] += rest;
+    }
+
+    /// Like [BandSplit::split], but fails unless `bands` holds `N + 1` values.
+    #[inline]
+    pub fn try_split(&mut self, x: R, bands: &mut [R]) -> Result<(), Error> {
+        if bands.len() != N + 1 {
+            return Err(Error::InvalidBandCount);
+        }
+        self.split(x, bands);
+        Ok(())
+    }
+
+    fn update(&mut self, idx: usize) {
+        let c = &self.crossover[idx];
+        let (crossover, frequency, sample_rate) = (c.crossover, c.frequency, c.sample_rate);
+        for band in self.compensation[..idx].iter_mut() {
+            band[idx].set(crossover, frequency, sample_rate);
+        }
+    }
+}
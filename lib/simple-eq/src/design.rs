@@ -3,23 +3,91 @@
 //! design parameters.
 //!
 
-use nalgebra::{convert as _c, Matrix3, RealField as Real, Vector3 as Vec3};
-use core::convert::From;
+use nalgebra::{convert as _c, convert_unchecked, Complex, ComplexField, Matrix3, RealField as Real, Vector3 as Vec3};
+use crate::error::Error;
+use core::convert::TryFrom;
+use core::fmt;
+use core::str::FromStr;
+
+/// Lowest normalized frequency (1/samples) a frequency is clamped to.
+pub const MIN_FREQUENCY: f64 = 1.0e-5;
+/// Highest normalized frequency (1/samples) a frequency is clamped to, just below Nyquist.
+pub const MAX_FREQUENCY: f64 = 0.499;
+/// Lowest resonance a design is clamped to.
+pub const MIN_RESONANCE: f64 = 1.0e-3;
 
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Curve {
     Lowpass,
     Highpass,
+    /// Bandpass with a constant 0dB peak, the skirts narrow as the resonance rises.
     Bandpass,
     Notch,
     Peak,
+    /// Second order shelf of `gain` below the frequency, 0dB above it, like the shelves of
+    /// the Audio EQ Cookbook and of Room EQ Wizard.
     Lowshelf,
+    /// Second order shelf of `gain` above the frequency, 0dB below it.
     Highshelf,
+    /// Bandpass with constant skirts, the peak gain is equal to the resonance.
+    BandpassConstantSkirt,
+    /// Second order allpass, the phase turns by 360 degrees around the frequency.
+    Allpass,
+    /// First order allpass, the phase turns by 180 degrees around the frequency.
+    AllpassFirstOrder,
+    /// First order tilt around the frequency, from `1 / sqrt(gain)` at DC to `sqrt(gain)` at
+    /// high frequencies. The resonance is unused.
+    Tilt,
+    /// A bandpass of `gain` added to the input. Unlike the peak, the bandwidth does not
+    /// change with the gain, and a low resonance flattens the top of the band.
+    Bandshelf,
 }
 
-impl From<i32> for Curve {
-    fn from(i: i32) -> Curve {
-        match i {
+impl Curve {
+    /// The name of the curve, as parsed by [FromStr].
+    pub fn name(&self) -> &'static str {
+        match self {
+            Curve::Lowpass => "lowpass",
+            Curve::Highpass => "highpass",
+            Curve::Bandpass => "bandpass",
+            Curve::Notch => "notch",
+            Curve::Peak => "peak",
+            Curve::Lowshelf => "lowshelf",
+            Curve::Highshelf => "highshelf",
+            Curve::BandpassConstantSkirt => "bandpass_constant_skirt",
+            Curve::Allpass => "allpass",
+            Curve::AllpassFirstOrder => "allpass_first_order",
+            Curve::Tilt => "tilt",
+            Curve::Bandshelf => "bandshelf",
+        }
+    }
+}
+
+impl fmt::Display for Curve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Curve {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Curve, Error> {
+        (0..)
+            .map_while(|i| Curve::try_from(i).ok())
+            .find(|c| c.name().eq_ignore_ascii_case(s))
+            .ok_or(Error::UnknownVariant)
+    }
+}
+
+/// The index of a curve, in the order of the declaration. This replaces the former
+/// `From<i32>`, which panicked on an unknown index.
+impl TryFrom<i32> for Curve {
+    type Error = Error;
+
+    fn try_from(i: i32) -> Result<Curve, Error> {
+        Ok(match i {
             0 => Curve::Lowpass,
             1 => Curve::Highpass,
             2 => Curve::Bandpass,
@@ -27,17 +95,51 @@ impl From<i32> for Curve {
             4 => Curve::Peak,
             5 => Curve::Lowshelf,
             6 => Curve::Highshelf,
-            _ => panic!("invalid argument"),
+            7 => Curve::BandpassConstantSkirt,
+            8 => Curve::Allpass,
+            9 => Curve::AllpassFirstOrder,
+            10 => Curve::Tilt,
+            11 => Curve::Bandshelf,
+            _ => return Err(Error::UnknownVariant),
+        })
+    }
+}
+
+/// How the analog prototype of a [Design] is mapped to the digital domain.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Method {
+    /// Bilinear transform prewarped at the critical frequency. The response is
+    /// cramped towards Nyquist, a peak or bandpass close to it gets narrower.
+    Bilinear,
+    /// Poles matched through the impulse invariance and magnitude matched to the
+    /// analog prototype at DC, the critical frequency and Nyquist, after M. Vicanek,
+    /// "Matched Second Order Digital Filters".
+    Matched,
+}
+
+impl TryFrom<i32> for Method {
+    type Error = Error;
+
+    fn try_from(i: i32) -> Result<Method, Error> {
+        match i {
+            0 => Ok(Method::Bilinear),
+            1 => Ok(Method::Matched),
+            _ => Err(Error::UnknownVariant),
         }
     }
 }
 
+/// The parameters of a filter band, with its frequency normalized (1/samples). With the
+/// `serde` feature, a design can be saved as JSON or any other serde format.
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Design<R: Real> {
     pub curve: Curve,
     pub frequency: R,
     pub resonance: R,
     pub gain: R,
+    pub method: Method,
 }
 
 impl<R: Real + Copy> Default for Design<R> {
@@ -45,8 +147,9 @@ impl<R: Real + Copy> Default for Design<R> {
         Self {
             curve: Curve::Peak,
             frequency: _c(1.0 / 24.0),
-            resonance: _c(0.5f64.sqrt()),
+            resonance: _c(core::f64::consts::FRAC_1_SQRT_2),
             gain: _c(0.0),
+            method: Method::Bilinear,
         }
     }
 }
@@ -54,48 +157,142 @@ impl<R: Real + Copy> Default for Design<R> {
 impl<R: Real + Copy> Design<R> {
     /// Compute the dgital transfer function.
     pub fn digital_xfer_fn(&self) -> (Vec3<R>, Vec3<R>) {
-        let (a_num, a_den) = self.analog_xfer_fn();
-        let (t_num, t_den) = (trans_quad(a_num), trans_quad(a_den));
-        let scale = t_den[0];
-        (t_num / scale, t_den / scale)
+        match self.method {
+            Method::Bilinear => {
+                let (a_num, a_den) = self.analog_xfer_fn();
+                let (t_num, t_den) = if a_den[0] == R::zero() {
+                    (trans_linear(a_num), trans_linear(a_den))
+                } else {
+                    (trans_quad(a_num), trans_quad(a_den))
+                };
+                let scale = t_den[0];
+                (t_num / scale, t_den / scale)
+            }
+            Method::Matched => {
+                // The numerator cancels terms of the order of the squared magnitudes, f32
+                // loses the whole response of a low and narrow band there. The coefficients
+                // are computed in f64 whatever the sample type.
+                let f64 = |x: R| convert_unchecked::<R, f64>(x);
+                let design = Design {
+                    curve: self.curve,
+                    frequency: f64(self.frequency),
+                    resonance: f64(self.resonance),
+                    gain: f64(self.gain),
+                    method: self.method,
+                };
+                let (num, den) = design.matched_xfer_fn();
+                (num.map(_c), den.map(_c))
+            }
+        }
+    }
+
+    /// Compute the continuous time transfer function of the filter, prewarped for
+    /// the bilinear transform.
+    pub fn analog_xfer_fn(&self) -> (Vec3<R>, Vec3<R>) {
+        self.prototype(prewarp(self.frequency))
     }
 
-    /// Compute the continuous time transfer function of the filter
+    /// The response of the analog prototype, without prewarping, at a normalized frequency.
+    /// This is the response the digital filter approximates.
+    pub fn analog_response(&self, frequency: R) -> Complex<R> {
+        let (a_num, a_den) = self.prototype(R::two_pi() * self.frequency);
+        let omega = R::two_pi() * frequency;
+        eval_analog(&a_num, omega) / eval_analog(&a_den, omega)
+    }
+
+    fn matched_xfer_fn(&self) -> (Vec3<R>, Vec3<R>) {
+        let (_0_5, _1, _4) = (_c::<f64, R>(0.5), R::one(), _c::<f64, R>(4.0));
+        let omega_c = R::two_pi() * self.frequency;
+        let (a_num, a_den) = self.prototype(omega_c);
+        let analog = |omega: R| (eval_analog(&a_num, omega) / eval_analog(&a_den, omega)).modulus_squared();
+
+        if a_den[0] == R::zero() {
+            // A single real pole, the magnitude is matched at DC and Nyquist.
+            let pole = (-a_den[2] / a_den[1]).exp();
+            let num = match self.curve {
+                Curve::AllpassFirstOrder => Vec3::new(-pole, _1, R::zero()),
+                _ => {
+                    let (g0, g1) = (analog(R::zero()).sqrt() * (_1 - pole), analog(R::pi()).sqrt() * (_1 + pole));
+                    Vec3::new(_0_5 * (g0 + g1), _0_5 * (g0 - g1), R::zero())
+                }
+            };
+            return (num, Vec3::new(_1, -pole, R::zero()));
+        }
+
+        // The poles of the prototype, through z = e^s.
+        let half = Complex::new(-a_den[1] / (_c::<f64, R>(2.0) * a_den[0]), R::zero());
+        let delta = ComplexField::sqrt(half * half - Complex::new(a_den[2] / a_den[0], R::zero()));
+        let (z1, z2) = ((half + delta).exp(), (half - delta).exp());
+        let den = Vec3::new(_1, -(z1 + z2).re, (z1 * z2).re);
+
+        // Squared magnitudes are linear in phi0 = cos^2(w/2), phi1 = sin^2(w/2) and phi2 = 4 phi0 phi1.
+        let phi1 = (_0_5 * omega_c).sin().powi(2);
+        let phi0 = _1 - phi1;
+        let phi2 = _4 * phi0 * phi1;
+        let a0 = (den[0] + den[1] + den[2]).powi(2);
+        let a1 = (den[0] - den[1] + den[2]).powi(2);
+        let a2 = -_4 * den[2];
+        let b0 = analog(R::zero()) * a0;
+        let b1 = analog(R::pi()) * a1;
+        let bc = analog(omega_c) * (a0 * phi0 + a1 * phi1 + a2 * phi2);
+
+        let num = match self.curve {
+            // The double zero at DC and the zeros of the notch on the unit circle are kept.
+            Curve::Highpass => Vec3::new(_1, _c(-2.0), _1) * (bc.sqrt() / (_4 * phi1)),
+            Curve::Notch => Vec3::new(_1, _c::<f64, R>(-2.0) * omega_c.cos(), _1) * (b0.sqrt() / (_4 * phi1)),
+            Curve::Allpass => Vec3::new(den[2], den[1], den[0]),
+            _ => {
+                // Minimum phase numerator with these squared magnitudes.
+                let b2 = (bc - b0 * phi0 - b1 * phi1) / phi2;
+                let (sqrt0, sqrt1) = (b0.sqrt(), b1.sqrt());
+                let w = _0_5 * (sqrt0 + sqrt1);
+                let num0 = _0_5 * (w + (w * w + b2).max(R::zero()).sqrt());
+                Vec3::new(num0, _0_5 * (sqrt0 - sqrt1), w - num0)
+            }
+        };
+        (num, den)
+    }
+
+    /// The continuous time transfer function for a critical frequency of `omega_c`, as the
+    /// coefficients of `s^2`, `s` and `1`.
     #[rustfmt::skip]
-    pub fn analog_xfer_fn (&self) -> (Vec3<R>, Vec3<R>) {
-        let omega_c = prewarp(self.frequency);
+    fn prototype(&self, omega_c: R) -> (Vec3<R>, Vec3<R>) {
         let scale   = self.gain.sqrt();
-        let den     = [_c(1.0), omega_c / self.resonance, omega_c * omega_c];
+        let resonance = self.resonance.max(_c(MIN_RESONANCE));
+        let den     = [_c(1.0), omega_c / resonance, omega_c * omega_c];
         let (num, den) = match self.curve {
             Curve::Lowpass  => {
                 ([_c(0.0), _c(0.0), omega_c * omega_c], den)
             },
             Curve::Highpass => ([_c(1.0), _c(0.0), _c(0.0)], den),
-            Curve::Bandpass => ([_c(0.0), omega_c / self.resonance, _c(0.0)], den),
+            Curve::Bandpass => ([_c(0.0), omega_c / resonance, _c(0.0)], den),
+            Curve::BandpassConstantSkirt => ([_c(0.0), omega_c, _c(0.0)], den),
             Curve::Notch    => ([_c(1.0), _c(0.0), omega_c * omega_c], den),
+            Curve::Allpass  => ([_c(1.0), -omega_c / resonance, omega_c * omega_c], den),
+            Curve::AllpassFirstOrder => ([_c(0.0), _c(-1.0), omega_c], [_c(0.0), _c(1.0), omega_c]),
+            Curve::Tilt     => ([_c(0.0), scale, omega_c], [_c(0.0), _c(1.0), omega_c * scale]),
+            Curve::Bandshelf => ([_c(1.0), self.gain * omega_c / resonance, omega_c * omega_c], den),
             Curve::Peak     => (
-                [_c(1.0), omega_c * scale / self.resonance, omega_c * omega_c],
-                [_c(1.0), omega_c / (self.resonance * scale), omega_c * omega_c],
+                [_c(1.0), omega_c * scale / resonance, omega_c * omega_c],
+                [_c(1.0), omega_c / (resonance * scale), omega_c * omega_c],
             ),
             Curve::Highshelf => {
-                let (mut num, mut den) = (
-                    [scale, omega_c * scale.sqrt() / self.resonance, omega_c * omega_c],
-                    [_c(1.0), omega_c * scale.sqrt() / self.resonance, omega_c * omega_c * scale],
+                let (mut num, den) = (
+                    [scale, omega_c * scale.sqrt() / resonance, omega_c * omega_c],
+                    [_c(1.0), omega_c * scale.sqrt() / resonance, omega_c * omega_c * scale],
                 );
-                for i in 0..3 {
-                    num[i] *= scale;
-                    den[i] *= scale;
+                for n in num.iter_mut() {
+                    *n *= scale;
                 }
                 (num, den)
             }
             Curve::Lowshelf => {
-                let (mut num, mut den) = (
-                    [_c(1.0), omega_c * scale.sqrt() / self.resonance, omega_c * omega_c * scale],
-                    [scale, omega_c * scale.sqrt() / self.resonance, omega_c * omega_c],
+                let (mut num, den) = (
+                    [_c(1.0), omega_c * scale.sqrt() / resonance, omega_c * omega_c * scale],
+                    [scale, omega_c * scale.sqrt() / resonance, omega_c * omega_c],
                 );
-                for i in 0..3 {
-                    num[i] *= scale;
-                    den[i] *= scale;
+                for n in num.iter_mut() {
+                    *n *= scale;
                 }
                 (num, den)
             }
@@ -106,18 +303,55 @@ impl<R: Real + Copy> Design<R> {
 }
 
 /// Normalize a frequency in Hertz (1/s) to its discrete time equivalent (1/samples) given
-/// the system's sample rate. Will panic if you try and normalize a frequency past Nyquist.
+/// the system's sample rate. The result is clamped between [MIN_FREQUENCY] and [MAX_FREQUENCY].
 #[inline]
 pub fn normalize_frequency<R: Real + Copy>(frequency: R, sample_rate: R) -> R {
-    assert!(frequency < (sample_rate / _c(2.0)));
-    frequency / sample_rate
+    (frequency / sample_rate).max(_c(MIN_FREQUENCY)).min(_c(MAX_FREQUENCY))
+}
+
+/// Like [normalize_frequency], but fails when the frequency is not between 0 and Nyquist.
+#[inline]
+pub fn try_normalize_frequency<R: Real + Copy>(frequency: R, sample_rate: R) -> Result<R, Error> {
+    let normalized = frequency / sample_rate;
+    if !sample_rate.is_finite() || sample_rate <= R::zero() {
+        Err(Error::InvalidSampleRate)
+    } else if normalized > R::zero() && normalized < _c(0.5) {
+        Ok(normalized)
+    } else {
+        Err(Error::FrequencyOutOfRange)
+    }
+}
+
+/// Fails when the resonance is not strictly positive.
+#[inline]
+pub fn check_resonance<R: Real + Copy>(resonance: R) -> Result<R, Error> {
+    if resonance > R::zero() {
+        Ok(resonance)
+    } else {
+        Err(Error::InvalidResonance)
+    }
 }
 
 #[inline]
 fn prewarp<R: Real + Copy>(normalized_freq: R) -> R {
-    //let prewarped = 4.0 * (normalized_frequency * 0.5 * std::f64::consts::PI).tan();
-    let (_4, _0_5, pi) = (_c::<f64, R>(4.0), _c::<f64, R>(0.5), R::pi());
-    _4 * (normalized_freq * _0_5 * pi).tan()
+    // trans_quad maps s = 4 (1 - z^-1) / (1 + z^-1), so w maps to 4 tan(w / 2).
+    let (_4, pi) = (_c::<f64, R>(4.0), R::pi());
+    _4 * (normalized_freq * pi).tan()
+}
+
+/// The bilinear transform of a first order polynomial `Q[1] s + Q[2]`, without the
+/// `(1 + z^-1)` factor [trans_quad] would add to both the numerator and denominator.
+#[inline]
+#[allow(non_snake_case)]
+fn trans_linear<R: Real + Copy>(Q: Vec3<R>) -> Vec3<R> {
+    let _4 = _c::<f64, R>(4.0);
+    Vec3::new(_4 * Q[1] + Q[2], Q[2] - _4 * Q[1], R::zero())
+}
+
+/// Evaluate the analog polynomial `c[0] s^2 + c[1] s + c[2]` at `s = j omega`.
+#[inline]
+fn eval_analog<R: Real + Copy>(c: &Vec3<R>, omega: R) -> Complex<R> {
+    Complex::new(c[2] - c[0] * omega * omega, c[1] * omega)
 }
 
 #[inline] 
@@ -136,3 +370,140 @@ fn trans_quad<R: Real + Copy>(Q: Vec3<R>) -> Vec3<R> {
     );
     X * TQ 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::FrequencyResponse;
+    use core::f64::consts::{FRAC_1_SQRT_2, FRAC_PI_2, PI};
+
+    const FREQUENCIES: [f64; 5] = [0.01, 0.1, 0.25, 0.4, 0.45];
+
+    fn design(curve: Curve, frequency: f64, resonance: f64, gain: f64, method: Method) -> Design<f64> {
+        Design { curve, frequency, resonance, gain, method }
+    }
+
+    /// The frequency of the largest magnitude, by golden section search in `low..high`.
+    fn peak_frequency(design: &Design<f64>, mut low: f64, mut high: f64) -> f64 {
+        let ratio = (5f64.sqrt() - 1.0) / 2.0;
+        while high - low > 1e-12 {
+            let (a, b) = (high - ratio * (high - low), low + ratio * (high - low));
+            if design.magnitude(a) < design.magnitude(b) {
+                low = a;
+            } else {
+                high = b;
+            }
+        }
+        0.5 * (low + high)
+    }
+
+    #[test]
+    fn bilinear_half_power_point() {
+        // Pins the prewarp at 4 tan(pi f): up to Nyquist the -3dB point stays at the frequency.
+        for frequency in FREQUENCIES {
+            let lowpass = design(Curve::Lowpass, frequency, FRAC_1_SQRT_2, 1.0, Method::Bilinear);
+            assert!((lowpass.magnitude_dB(frequency) + 3.0103).abs() < 1e-3, "lowpass at {}", frequency);
+            let highpass = design(Curve::Highpass, frequency, FRAC_1_SQRT_2, 1.0, Method::Bilinear);
+            assert!((highpass.magnitude_dB(frequency) + 3.0103).abs() < 1e-3, "highpass at {}", frequency);
+        }
+    }
+
+    #[test]
+    fn bilinear_peak_frequency() {
+        for frequency in FREQUENCIES {
+            let peak = design(Curve::Peak, frequency, 2.0, 4.0, Method::Bilinear);
+            let found = peak_frequency(&peak, 0.5 * frequency, (1.5 * frequency).min(0.5));
+            assert!((found - frequency).abs() < 1e-6 * frequency.max(0.01), "peak at {} found at {}", frequency, found);
+            assert!((peak.magnitude(frequency) - 4.0).abs() < 1e-9);
+
+            let bandpass = design(Curve::Bandpass, frequency, 4.0, 1.0, Method::Bilinear);
+            let found = peak_frequency(&bandpass, 0.5 * frequency, (1.5 * frequency).min(0.5));
+            assert!((found - frequency).abs() < 1e-6 * frequency.max(0.01), "bandpass at {} found at {}", frequency, found);
+        }
+    }
+
+    #[test]
+    fn bilinear_follows_the_analog_prototype() {
+        // Far below Nyquist the warping is negligible.
+        for curve in [Curve::Lowpass, Curve::Highpass, Curve::Peak, Curve::Bandpass, Curve::Lowshelf] {
+            let design = design(curve, 0.001, 2.0, 2.0, Method::Bilinear);
+            for frequency in crate::response::log_sweep(1e-4, 0.01, 20) {
+                let (digital, analog) = (design.magnitude(frequency), design.analog_response(frequency).modulus());
+                assert!((digital / analog - 1.0).abs() < 1e-3, "{:?} at {}", curve, frequency);
+            }
+        }
+        // Close to Nyquist, the magnitudes only agree at the frequency and DC.
+        let peak = design(Curve::Peak, 0.4, 2.0, 4.0, Method::Bilinear);
+        assert!((peak.magnitude(0.4) - peak.analog_response(0.4).modulus()).abs() < 1e-9);
+        assert!((peak.magnitude(1e-6) - peak.analog_response(1e-6).modulus()).abs() < 1e-6);
+        assert!((peak.magnitude(0.48) - peak.analog_response(0.48).modulus()).abs() > 0.1);
+    }
+
+    #[test]
+    fn matched_follows_the_analog_prototype_near_nyquist() {
+        for curve in [Curve::Lowpass, Curve::Peak, Curve::Bandpass] {
+            for frequency in [0.3, 0.4, 0.45] {
+                let design = design(curve, frequency, 2.0, 4.0, Method::Matched);
+                for f in [1e-6, frequency, 0.5] {
+                    let (digital, analog) = (design.magnitude(f), design.analog_response(f).modulus());
+                    assert!((digital - analog).abs() < 1e-6 * analog.max(1.0), "{:?} at {}: {} {} at {}", curve, frequency, digital, analog, f);
+                }
+                // Above the frequency, closer to the prototype than the bilinear transform.
+                let bilinear = Design { method: Method::Bilinear, ..design };
+                for f in [0.5 * (frequency + 0.5), 0.49] {
+                    let analog = design.analog_response(f).modulus().log10();
+                    let matched = (design.magnitude(f).log10() - analog).abs();
+                    let warped = (bilinear.magnitude(f).log10() - analog).abs();
+                    assert!(matched < warped, "{:?} at {}: {} {} at {}", curve, frequency, matched, warped, f);
+                }
+            }
+        }
+    }
+
+    /// The phase from DC to `frequency`, unwrapped.
+    fn phase_turn(design: &Design<f64>, frequency: f64) -> f64 {
+        let steps = 10000;
+        (0..steps)
+            .map(|n| frequency * n as f64 / steps as f64)
+            .map(|f| (design.response(f + frequency / steps as f64) / design.response(f)).argument())
+            .sum()
+    }
+
+    #[test]
+    fn allpasses_are_flat_and_turn_the_phase() {
+        for method in [Method::Bilinear, Method::Matched] {
+            for frequency in FREQUENCIES {
+                let allpass = design(Curve::Allpass, frequency, 2.0, 1.0, method);
+                let first_order = design(Curve::AllpassFirstOrder, frequency, 1.0, 1.0, method);
+                for f in crate::response::log_sweep(1e-4, 0.5, 50) {
+                    assert!((allpass.magnitude(f) - 1.0).abs() < 1e-9, "{:?} allpass at {}: {}", method, frequency, f);
+                    assert!((first_order.magnitude(f) - 1.0).abs() < 1e-9, "{:?} first order at {}: {}", method, frequency, f);
+                }
+                let (turn, first_order_turn) = (phase_turn(&allpass, 0.5), phase_turn(&first_order, 0.5));
+                assert!((turn + 2.0 * PI).abs() < 1e-6, "{:?} allpass at {}: {}", method, frequency, turn.to_degrees());
+                assert!((first_order_turn + PI).abs() < 1e-6, "{:?} first order at {}: {}", method, frequency, first_order_turn.to_degrees());
+                if method == Method::Bilinear {
+                    // Half of the turn is at the frequency.
+                    assert!((phase_turn(&allpass, frequency) + PI).abs() < 1e-6, "allpass at {}", frequency);
+                    assert!((phase_turn(&first_order, frequency) + FRAC_PI_2).abs() < 1e-6, "first order at {}", frequency);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tilt_and_bandshelf_gains() {
+        let gain = 10f64.powf(6.0 / 20.0);
+        for method in [Method::Bilinear, Method::Matched] {
+            let tilt = design(Curve::Tilt, 0.001, 1.0, gain, method);
+            assert!((tilt.magnitude_dB(1e-6) + 3.0).abs() < 0.01, "{:?} tilt at DC: {}", method, tilt.magnitude_dB(1e-6));
+            assert!((tilt.magnitude_dB(0.5) - 3.0).abs() < 0.01, "{:?} tilt at Nyquist: {}", method, tilt.magnitude_dB(0.5));
+            assert!(tilt.magnitude_dB(0.001).abs() < 0.01, "{:?} tilt at the frequency: {}", method, tilt.magnitude_dB(0.001));
+            for frequency in FREQUENCIES {
+                let bandshelf = design(Curve::Bandshelf, frequency, 2.0, gain, method);
+                assert!((bandshelf.magnitude_dB(frequency) - 6.0).abs() < 1e-6, "{:?} bandshelf at {}: {}", method, frequency, bandshelf.magnitude_dB(frequency));
+                assert!(bandshelf.magnitude_dB(1e-6).abs() < 1e-3, "{:?} bandshelf at {}: {} at DC", method, frequency, bandshelf.magnitude_dB(1e-6));
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Errors returned by the fallible (`try_`) variants of the setters.
+//!
+//! The infallible setters never panic on out of range parameters, they clamp them instead:
+//!
+//! * frequencies are clamped between [MIN_FREQUENCY](crate::design::MIN_FREQUENCY) and
+//!   [MAX_FREQUENCY](crate::design::MAX_FREQUENCY) times the sample rate, just below Nyquist,
+//! * resonances are clamped above [MIN_RESONANCE](crate::design::MIN_RESONANCE),
+//! * the order of a [CascadeFilter](crate::cascade::CascadeFilter) is clamped between 2 and `2 * N`,
+//! * a [CascadeFilter](crate::cascade::CascadeFilter) keeps its curve when given one it does
+//!   not support,
+//! * [Link::MidSide](crate::Link::MidSide) falls back to [Link::Independent](crate::Link::Independent)
+//!   unless the equalizer has two channels,
+//! * [BandSplit::split](crate::crossover::BandSplit::split) folds the bands that do not fit
+//!   into the last one, and zeroes the extra ones.
+//!
+//! Use the `try_` variants to detect those cases, for instance to mute a band that
+//! would sit above Nyquist at the current sample rate.
+use core::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The frequency is not between 0 and Nyquist.
+    FrequencyOutOfRange,
+    /// The resonance is not strictly positive.
+    InvalidResonance,
+    /// The sample rate is not strictly positive.
+    InvalidSampleRate,
+    /// The order is not supported by the filter.
+    InvalidOrder,
+    /// The curve is not supported by the filter.
+    UnsupportedCurve,
+    /// No variant matches the index or the name.
+    UnknownVariant,
+    /// The text could not be parsed.
+    InvalidSyntax,
+    /// There are more filters than bands.
+    TooManyBands,
+    /// Mid/side processing needs two channels.
+    UnsupportedLink,
+    /// The number of bands does not match the band split.
+    InvalidBandCount,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Error::FrequencyOutOfRange => "frequency out of range",
+            Error::InvalidResonance => "resonance must be positive",
+            Error::InvalidSampleRate => "sample rate must be positive",
+            Error::InvalidOrder => "order not supported",
+            Error::UnsupportedCurve => "curve not supported",
+            Error::UnknownVariant => "unknown variant",
+            Error::InvalidSyntax => "invalid syntax",
+            Error::TooManyBands => "too many filters for the bands",
+            Error::UnsupportedLink => "mid/side processing needs two channels",
+            Error::InvalidBandCount => "a band split at N frequencies has N + 1 bands",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cascade::{CascadeFilter, Response};
+    use crate::crossover::{BandSplit, Crossover};
+    use crate::design::{Curve, MAX_FREQUENCY, MIN_FREQUENCY};
+    use crate::filter::Filter;
+    use crate::{Equalizer, Link};
+    use core::convert::TryFrom;
+
+    #[test]
+    fn frequencies_and_resonances_are_clamped() {
+        let mut filter = Filter::new(48e3);
+        filter.set(Curve::Peak, 30e3, 0.0, 2.0, 48e3);
+        assert_eq!(filter.get_design().frequency, MAX_FREQUENCY);
+        filter.set(Curve::Peak, -1.0, 1.0, 2.0, 48e3);
+        assert_eq!(filter.get_design().frequency, MIN_FREQUENCY);
+        assert!(filter.filter(1.0).is_finite());
+
+        assert_eq!(filter.try_set(Curve::Peak, 30e3, 1.0, 2.0, 48e3), Err(Error::FrequencyOutOfRange));
+        assert_eq!(filter.try_set(Curve::Peak, 1e3, 0.0, 2.0, 48e3), Err(Error::InvalidResonance));
+        assert_eq!(filter.try_set(Curve::Peak, 1e3, 1.0, 2.0, 0.0), Err(Error::InvalidSampleRate));
+        assert_eq!(filter.get_design().frequency, MIN_FREQUENCY, "untouched on failure");
+
+        let mut eq = Equalizer::<f64>::new(48e3);
+        eq.set(0, Curve::Peak, 1e3, -1.0, 2.0);
+        assert!(eq.process(1.0).is_finite());
+    }
+
+    #[test]
+    fn cascade_order_and_curve() {
+        let mut cascade = CascadeFilter::<f64, 4>::new(48e3);
+        cascade.set_order(20);
+        assert_eq!(cascade.get_order(), 8);
+        cascade.set_order(0);
+        assert_eq!(cascade.get_order(), 2);
+        cascade.set_order(7);
+        assert_eq!(cascade.get_order(), 6);
+
+        cascade.set(Curve::Highpass, Response::Butterworth, 1e3, 1.0, 4, 48e3);
+        cascade.set_curve(Curve::Peak);
+        cascade.set(Curve::Notch, Response::Butterworth, 2e3, 1.0, 4, 48e3);
+        // Still a highpass, at the new frequency.
+        let mut cascade_highpass = CascadeFilter::<f64, 4>::new(48e3);
+        cascade_highpass.set(Curve::Highpass, Response::Butterworth, 2e3, 1.0, 4, 48e3);
+        for _ in 0..64 {
+            assert_eq!(cascade.filter(1.0), cascade_highpass.filter(1.0));
+        }
+
+        assert_eq!(cascade.try_set_curve(Curve::Peak), Err(Error::UnsupportedCurve));
+        assert_eq!(cascade.try_set(Curve::Lowpass, Response::Butterworth, 1e3, 1.0, 10, 48e3), Err(Error::InvalidOrder));
+        assert_eq!(cascade.try_set(Curve::Lowpass, Response::Butterworth, 1e3, 1.0, 3, 48e3), Err(Error::InvalidOrder));
+        assert_eq!(cascade.get_order(), 4, "untouched on failure");
+        assert_eq!(cascade.try_set(Curve::Tilt, Response::Butterworth, 1e3, 1.0, 4, 48e3), Err(Error::UnsupportedCurve));
+        assert_eq!(cascade.try_set(Curve::Lowpass, Response::Butterworth, 1e3, 1.0, 4, 48e3), Ok(()));
+    }
+
+    #[test]
+    fn mid_side_needs_two_channels() {
+        let eq = Equalizer::<f64, 4, 3>::with_link(48e3, Link::MidSide);
+        assert_eq!(eq.get_link(), Link::Independent);
+        assert_eq!(Equalizer::<f64, 4, 3>::try_with_link(48e3, Link::MidSide).err(), Some(Error::UnsupportedLink));
+
+        let mut eq = Equalizer::<f64, 4, 1>::with_link(48e3, Link::Linked);
+        eq.set_link(Link::MidSide);
+        assert_eq!(eq.get_link(), Link::Independent);
+        eq.set_link(Link::Linked);
+        assert_eq!(eq.try_set_link(Link::MidSide), Err(Error::UnsupportedLink));
+        assert_eq!(eq.get_link(), Link::Linked, "untouched on failure");
+
+        let mut eq = Equalizer::<f64, 4, 2>::with_link(48e3, Link::Linked);
+        assert_eq!(eq.try_set_link(Link::MidSide), Ok(()));
+        assert_eq!(eq.get_link(), Link::MidSide);
+    }
+
+    #[test]
+    fn split_folds_the_bands_that_do_not_fit() {
+        let input: Vec<f64> = (0..256).map(|n| (n as f64 * 0.37).sin()).collect();
+        let mut reference = BandSplit::<f64, 2>::new(Crossover::LR4, 48e3);
+        let (mut short, mut long) = (reference, reference);
+        for x in input {
+            let mut bands = [0.0; 3];
+            reference.split(x, &mut bands);
+
+            let mut fewer = [1.0; 2];
+            short.split(x, &mut fewer);
+            assert_eq!(fewer, [bands[0], bands[1] + bands[2]]);
+
+            let mut more = [1.0; 5];
+            long.split(x, &mut more);
+            assert_eq!(more, [bands[0], bands[1], bands[2], 0.0, 0.0]);
+
+            reference.split(x, &mut []);
+        }
+        assert_eq!(reference.try_split(0.0, &mut [0.0; 2]), Err(Error::InvalidBandCount));
+        assert_eq!(reference.try_split(0.0, &mut [0.0; 3]), Ok(()));
+    }
+
+    #[test]
+    fn unknown_variants() {
+        assert_eq!(Curve::try_from(12).err(), Some(Error::UnknownVariant));
+        assert!(matches!(Curve::try_from(4), Ok(Curve::Peak)));
+        assert_eq!(Crossover::try_from(-1).err(), Some(Error::UnknownVariant));
+        assert_eq!("shelf".parse::<Curve>().err(), Some(Error::UnknownVariant));
+    }
+}
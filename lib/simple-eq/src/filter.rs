@@ -1,11 +1,28 @@
 use crate::design::*;
+use crate::error::Error;
 use crate::kernel::*;
-use nalgebra::{convert as _c, RealField as Real, Vector2 as Vec2};
+use crate::response::*;
+use crate::svf::*;
+use nalgebra::{convert as _c, Complex, RealField as Real, Vector2 as Vec2};
+
+/// The structure a [Filter] is evaluated with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Topology {
+    /// The state-space [Kernel]. Its coefficients jump on every change of the design.
+    StateSpace,
+    /// The zero delay feedback [SvfKernel], stable under modulation and smoothed. It always
+    /// follows the bilinear design, whatever the method of the design is.
+    Tpt,
+}
 
 /// A single filter band.
 #[derive(Copy, Clone, Debug)]
 pub struct Filter<R: Real> {
     kernel: Kernel<R>,
+    svf: SvfKernel<R>,
+    topology: Topology,
+    /// Glide time of the [Topology::Tpt] parameters in seconds.
+    smoothing: R,
     design: Design<R>,
     sample_rate: R,
 }
@@ -18,11 +35,15 @@ impl<R: Real + Copy> Filter<R> {
             gain: _c(0.0),
             frequency: _c(0.1),
             resonance: _c(1.0),
+            method: Method::Bilinear,
         };
         let kernel = Kernel::new();
         let mut self_ = Self {
             design,
             kernel,
+            svf: SvfKernel::new(),
+            topology: Topology::StateSpace,
+            smoothing: R::zero(),
             sample_rate,
         };
         self_.update();
@@ -36,16 +57,48 @@ impl<R: Real + Copy> Filter<R> {
             gain,
             resonance,
             curve,
+            method: self.design.method,
         };
-        self.sample_rate = sample_rate;
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.update_smoothing();
+        }
         self.update();
     }
 
+    /// Like [Filter::set], but fails instead of clamping out of range parameters.
+    /// The filter is left untouched on failure.
+    #[inline]
+    pub fn try_set(&mut self, curve: Curve, frequency: R, resonance: R, gain: R, sample_rate: R) -> Result<(), Error> {
+        try_normalize_frequency(frequency, sample_rate)?;
+        check_resonance(resonance)?;
+        self.set(curve, frequency, resonance, gain, sample_rate);
+        Ok(())
+    }
+
+    /// Take the design of `other`, keeping the state of this filter, so that a design can be
+    /// computed ahead, e.g. on another thread. The [Topology::Tpt] parameters glide to it as
+    /// after [Filter::set]. The topology and the smoothing are taken too.
+    #[inline]
+    pub fn copy_design(&mut self, other: &Self) {
+        self.design = other.design;
+        self.sample_rate = other.sample_rate;
+        self.topology = other.topology;
+        self.smoothing = other.smoothing;
+        self.kernel.copy_coefficients(&other.kernel);
+        self.svf.copy_target(&other.svf);
+    }
+
     /// Get a copy of the filter's current design parameters.
     pub fn get_design(&self) -> Design<R> {
         self.design
     }
 
+    /// The sample rate the filter was designed at.
+    pub fn get_sample_rate(&self) -> R {
+        self.sample_rate
+    }
+
     /// Get a copy of the current filter state.
     #[inline]
     pub fn get_state(&self) -> Vec2<R> {
@@ -59,6 +112,13 @@ impl<R: Real + Copy> Filter<R> {
         self.update();
     }
 
+    /// Set how the filter is mapped from its analog prototype.
+    #[inline]
+    pub fn set_method(&mut self, method: Method) {
+        self.design.method = method;
+        self.update();
+    }
+
     /// Set the critical frequency of the filter.
     #[inline]
     pub fn set_frequency(&mut self, freq_hz: R) {
@@ -66,6 +126,14 @@ impl<R: Real + Copy> Filter<R> {
         self.update();
     }
 
+    /// Like [Filter::set_frequency], but fails instead of clamping past Nyquist.
+    #[inline]
+    pub fn try_set_frequency(&mut self, freq_hz: R) -> Result<(), Error> {
+        self.design.frequency = try_normalize_frequency(freq_hz, self.sample_rate)?;
+        self.update();
+        Ok(())
+    }
+
     /// set the gain of the filter. Meaningless for some filter curves.
     #[allow(non_snake_case)]
     #[inline]
@@ -85,23 +153,56 @@ impl<R: Real + Copy> Filter<R> {
     #[inline]
     pub fn set_sample_rate(&mut self, sample_rate: R) {
         self.sample_rate = sample_rate;
+        self.update_smoothing();
         self.update();
     }
 
+    /// Select the structure the filter is evaluated with. Both are kept up to date, but
+    /// only the selected one holds the state of the signal.
+    #[inline]
+    pub fn set_topology(&mut self, topology: Topology) {
+        self.topology = topology;
+    }
+
+    pub fn get_topology(&self) -> Topology {
+        self.topology
+    }
+
+    /// Set the time it takes the [Topology::Tpt] parameters to glide to a new design, in
+    /// seconds. Zero disables the smoothing. The time is kept across sample rate changes.
+    pub fn set_smoothing(&mut self, time: R) {
+        self.smoothing = time;
+        self.update_smoothing();
+    }
+
+    fn update_smoothing(&mut self) {
+        let samples = self.smoothing * self.sample_rate;
+        self.svf.set_smoothing(if samples > R::one() {
+            R::one() - (-R::one() / samples).exp()
+        } else {
+            R::one()
+        });
+    }
+
     /// Zero the state of the filter.
     pub fn reset(&mut self) {
         self.kernel.reset();
+        self.svf.reset();
     }
 
     #[inline]
     fn update(&mut self) {
         let (num, den) = self.design.digital_xfer_fn();
         self.kernel.set(num, den);
+        self.svf.set(&self.design);
     }
 
     #[inline]
     pub fn filter(&mut self, x: R) -> R {
-        self.kernel.eval(x)
+        match self.topology {
+            Topology::StateSpace => self.kernel.eval(x),
+            Topology::Tpt => self.svf.eval(x),
+        }
     }
 
     #[inline]
@@ -111,3 +212,241 @@ impl<R: Real + Copy> Filter<R> {
         }
     }
 }
+
+impl<R: Real + Copy> FrequencyResponse<R> for Filter<R> {
+    /// The response at `frequency` in Hertz.
+    fn response(&self, frequency: R) -> Complex<R> {
+        match self.topology {
+            Topology::StateSpace => self.kernel.response(frequency / self.sample_rate),
+            Topology::Tpt => self.bilinear_design().response(frequency / self.sample_rate),
+        }
+    }
+
+    fn group_delay(&self, frequency: R) -> R {
+        match self.topology {
+            Topology::StateSpace => self.kernel.group_delay(frequency / self.sample_rate),
+            Topology::Tpt => self.bilinear_design().group_delay(frequency / self.sample_rate),
+        }
+    }
+}
+
+impl<R: Real + Copy> Filter<R> {
+    /// The design the [Topology::Tpt] structure follows.
+    fn bilinear_design(&self) -> Design<R> {
+        Design {
+            method: Method::Bilinear,
+            ..self.design
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn noise(len: usize) -> impl Iterator<Item = f64> {
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        (0..len).map(move |_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+        })
+    }
+
+    #[test]
+    fn topologies_match_in_steady_state() {
+        let curves = [
+            Curve::Lowpass,
+            Curve::Highpass,
+            Curve::Bandpass,
+            Curve::Notch,
+            Curve::Peak,
+            Curve::Lowshelf,
+            Curve::Highshelf,
+            Curve::BandpassConstantSkirt,
+            Curve::Allpass,
+            Curve::AllpassFirstOrder,
+            Curve::Tilt,
+            Curve::Bandshelf,
+        ];
+        for curve in curves {
+            for frequency in [50.0, 1e3, 15e3] {
+                let mut state_space = Filter::new(48e3);
+                state_space.set(curve, frequency, 2.0, 2.0, 48e3);
+                let mut tpt = state_space;
+                tpt.set_topology(Topology::Tpt);
+                tpt.reset();
+                for (n, x) in noise(48000).enumerate() {
+                    let (a, b) = (state_space.filter(x), tpt.filter(x));
+                    if n > 24000 {
+                        assert!((a - b).abs() < 1e-9, "{:?} at {}: {} {}", curve, frequency, a, b);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn smoothing_follows_the_sample_rate() {
+        let mut moved = Filter::new(48e3);
+        moved.set_topology(Topology::Tpt);
+        moved.set_smoothing(0.01);
+        moved.set_sample_rate(96e3);
+        let mut built = Filter::new(96e3);
+        built.set_topology(Topology::Tpt);
+        built.set_smoothing(0.01);
+
+        let mut rebuilt = Filter::new(44.1e3);
+        rebuilt.set_topology(Topology::Tpt);
+        rebuilt.set_smoothing(0.01);
+        rebuilt.set(Curve::Peak, 100.0, 1.0, 1.0, 96e3);
+        built.set(Curve::Peak, 100.0, 1.0, 1.0, 96e3);
+        moved.set(Curve::Peak, 100.0, 1.0, 1.0, 96e3);
+        built.reset();
+        moved.reset();
+        rebuilt.reset();
+
+        // A glide of 960 samples to a new design.
+        for filter in [&mut built, &mut moved, &mut rebuilt] {
+            filter.set(Curve::Peak, 1e3, 1.0, 4.0, 96e3);
+        }
+        for x in noise(2000) {
+            let y = built.filter(x);
+            assert_eq!(y, moved.filter(x));
+            assert_eq!(y, rebuilt.filter(x));
+        }
+    }
+
+    #[test]
+    fn copy_design_matches_set() {
+        for (topology, curve) in [(Topology::StateSpace, Curve::Peak), (Topology::Tpt, Curve::Peak), (Topology::Tpt, Curve::AllpassFirstOrder)] {
+            let mut set = Filter::new(48e3);
+            set.set_topology(topology);
+            set.set_smoothing(0.005);
+            set.set(Curve::Bandpass, 200.0, 4.0, 1.0, 48e3);
+            let mut copied = set;
+            let mut design = Filter::new(44.1e3);
+            design.set_topology(topology);
+            design.set_smoothing(0.005);
+            design.set(curve, 2e3, 0.7, 2.0, 48e3);
+            let mut input = noise(4000);
+            for x in input.by_ref().take(1000) {
+                assert_eq!(set.filter(x), copied.filter(x));
+            }
+            set.set(curve, 2e3, 0.7, 2.0, 48e3);
+            copied.copy_design(&design);
+            assert_eq!(copied.get_design().frequency, set.get_design().frequency);
+            for x in input {
+                assert_eq!(set.filter(x), copied.filter(x), "{:?} {:?}", topology, curve);
+            }
+        }
+    }
+
+    /// A triangle from 0 to 1 and back over `period` seconds.
+    fn triangle(t: f64, period: f64) -> f64 {
+        let phase = (t / period).fract();
+        1.0 - (2.0 * phase - 1.0).abs()
+    }
+
+    /// One second of a sine at `tone` through `curve`, its frequency swept between 20Hz and
+    /// 20kHz and its Q between 0.5 and 300, the design being set every `every` samples.
+    /// Returns the peak of the output and the peak of its second difference.
+    fn modulated(curve: Curve, topology: Topology, smoothing: f64, every: usize, tone: f64) -> (f64, f64) {
+        let sr = 48e3;
+        let mut filter = Filter::new(sr);
+        filter.set_topology(topology);
+        filter.set_smoothing(smoothing);
+        let (mut peak, mut second, mut y1, mut y2) = (0.0f64, 0.0f64, 0.0, 0.0);
+        for n in 0..48000 {
+            let t = n as f64 / sr;
+            if n % every == 0 {
+                let frequency = 20.0 * 1000f64.powf(triangle(t, 0.05));
+                let q = 0.5 * 600f64.powf(triangle(t, 0.031));
+                filter.set(curve, frequency, q, 4.0, sr);
+            }
+            let y = filter.filter((core::f64::consts::TAU * tone * t).sin());
+            assert!(y.is_finite(), "{:?} {:?} at {}", curve, topology, n);
+            if n > 1 {
+                second = second.max((y - 2.0 * y1 + y2).abs());
+            }
+            peak = peak.max(y.abs());
+            (y2, y1) = (y1, y);
+        }
+        (peak, second)
+    }
+
+    const MODULATED: [Curve; 5] = [Curve::Lowpass, Curve::Bandpass, Curve::Peak, Curve::Highpass, Curve::Notch];
+
+    #[test]
+    fn tpt_is_bounded_under_per_sample_modulation() {
+        for curve in MODULATED {
+            let (state_space, _) = modulated(curve, Topology::StateSpace, 0.0, 1, 440.0);
+            for smoothing in [0.0, 0.001] {
+                let (peak, second) = modulated(curve, Topology::Tpt, smoothing, 1, 440.0);
+                assert!(peak < 10.0 && peak < 1.1 * state_space, "{:?}: peak {} against {}", curve, peak, state_space);
+                // A 20kHz sine of amplitude 1 has a second difference of 3.7.
+                assert!(second < 2.0, "{:?}: second difference {}", curve, second);
+            }
+        }
+    }
+
+    #[test]
+    fn tpt_smooths_block_rate_automation() {
+        for curve in MODULATED {
+            let (state_space, state_space_second) = modulated(curve, Topology::StateSpace, 0.0, 64, 100.0);
+            let (peak, second) = modulated(curve, Topology::Tpt, 0.005, 64, 100.0);
+            assert!(peak < state_space, "{:?}: peak {} against {}", curve, peak, state_space);
+            assert!(second < state_space_second / 20.0, "{:?}: second difference {} against {}", curve, second, state_space_second);
+        }
+    }
+}
+
+#[cfg(test)]
+mod precision {
+    use super::*;
+
+    /// The design in direct form I, another structure for the rounding errors of f64.
+    fn direct_form(design: &Design<f64>, input: &[f64]) -> Vec<f64> {
+        let (b, a) = design.digital_xfer_fn();
+        let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+        input
+            .iter()
+            .map(|x| {
+                let y = b[0] * x + b[1] * x1 + b[2] * x2 - a[1] * y1 - a[2] * y2;
+                (x2, x1, y2, y1) = (x1, *x, y1, y);
+                y
+            })
+            .collect()
+    }
+
+    /// Power of the difference of `output` and `reference`, relative to the one of `reference`, in dB.
+    fn floor_db(output: impl Iterator<Item = f64>, reference: &[f64]) -> f64 {
+        let error: f64 = output.zip(reference).map(|(y, r)| (y - r) * (y - r)).sum();
+        let power: f64 = reference.iter().map(|r| r * r).sum();
+        10.0 * (error / power).log10()
+    }
+
+    /// White noise through a 40Hz band with a Q of 300 at 48kHz, the narrowest band of
+    /// copiremap. Its poles sit so close to the unit circle that f32 coefficients move
+    /// them by about their distance to it. Both designs keep the error below the band, the
+    /// matched one being computed in f64.
+    #[test]
+    fn noise_floor_of_a_narrow_low_band() {
+        let input: Vec<f64> = super::tests::noise(96000).collect();
+        for method in [Method::Bilinear, Method::Matched] {
+            let mut single = Filter::<f32>::new(48e3);
+            let mut double = Filter::<f64>::new(48e3);
+            single.set_method(method);
+            double.set_method(method);
+            single.set(Curve::Bandpass, 40.0, 300.0, 1.0, 48e3);
+            double.set(Curve::Bandpass, 40.0, 300.0, 1.0, 48e3);
+
+            let reference = direct_form(&double.get_design(), &input);
+            let f32_floor = floor_db(input.iter().map(|x| single.filter(*x as f32) as f64), &reference);
+            let f64_floor = floor_db(input.iter().map(|x| double.filter(*x)), &reference);
+            assert!(f32_floor > -30.0 && f32_floor < -15.0, "{:?}: f32 at {:.1} dB", method, f32_floor);
+            assert!(f64_floor < -120.0, "{:?}: f64 at {:.1} dB", method, f64_floor);
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Linear phase FIR filters, as an alternative to the minimum phase IIR kernels.
+//!
+//! The impulse response is designed by frequency sampling: the amplitude is sampled on a
+//! dense grid with zero phase, transformed back to the time domain, delayed by half the
+//! length of the filter and windowed. It is run with a uniformly partitioned overlap-save
+//! convolution, so the cost grows with the logarithm of the length instead of linearly.
+//! Every frequency is delayed by [LinearPhase::latency] samples.
+//!
+//! Needs the `fir` feature, which pulls `realfft` and the standard library.
+use crate::design::*;
+use crate::response::*;
+use nalgebra::{convert as _c, RealField as Real};
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, FftNum, RealFftPlanner, RealToComplex};
+use std::sync::Arc;
+
+/// Design `taps` coefficients (rounded up to an odd number) whose amplitude at the
+/// normalized frequency `f` (1/samples) is `amplitude(f)`, delayed by `(taps - 1) / 2`.
+/// The amplitude is real, a negative amplitude inverts the phase.
+pub fn frequency_sampling<R, F>(taps: usize, amplitude: F) -> Vec<R>
+where
+    R: Real + Copy + FftNum,
+    F: Fn(R) -> R,
+{
+    let taps = taps.max(1) | 1;
+    let size = 4 * taps.next_power_of_two();
+    let mut spectrum: Vec<Complex<R>> = (0..=size / 2)
+        .map(|k| Complex::new(amplitude(_c::<f64, R>(k as f64 / size as f64)), R::zero()))
+        .collect();
+    let mut zero_phase = vec![R::zero(); size];
+    let inverse = RealFftPlanner::<R>::new().plan_fft_inverse(size);
+    let _ = inverse.process(&mut spectrum, &mut zero_phase);
+
+    // Blackman window, centered on the delay, where it is one even for a single tap.
+    let delay = (taps - 1) / 2;
+    let scale = R::one() / _c(size as f64);
+    let last: R = _c(taps.saturating_sub(1).max(1) as f64);
+    (0..taps)
+        .map(|n| {
+            let phase = R::pi() + R::two_pi() * (_c::<f64, R>(n as f64) - _c(delay as f64)) / last;
+            let window = _c::<f64, R>(0.42) - _c::<f64, R>(0.5) * phase.cos() + _c::<f64, R>(0.08) * (phase + phase).cos();
+            zero_phase[(n + size - delay) % size] * scale * window
+        })
+        .collect()
+}
+
+/// Design `taps` coefficients with the magnitude of the designs in series, and a linear phase.
+pub fn from_designs<R: Real + Copy + FftNum>(designs: &[Design<R>], taps: usize) -> Vec<R> {
+    frequency_sampling(taps, |f| designs.iter().fold(R::one(), |m, d| m * d.magnitude(f)))
+}
+
+/// A FIR filter run with a uniformly partitioned overlap-save convolution.
+#[derive(Clone)]
+pub struct LinearPhase<R: Real + FftNum> {
+    forward: Arc<dyn RealToComplex<R>>,
+    inverse: Arc<dyn ComplexToReal<R>>,
+    scratch: Vec<Complex<R>>,
+    /// The spectrum of every partition of the impulse response.
+    partitions: Vec<Vec<Complex<R>>>,
+    /// The spectra of the last input blocks, as a ring indexed from `head`.
+    history: Vec<Vec<Complex<R>>>,
+    head: usize,
+    accumulator: Vec<Complex<R>>,
+    /// The previous and the current input block.
+    input: Vec<R>,
+    output: Vec<R>,
+    time: Vec<R>,
+    position: usize,
+    block_size: usize,
+    taps: usize,
+}
+
+impl<R: Real + Copy + FftNum> LinearPhase<R> {
+    /// Construct a new convolution of `impulse` in blocks of `block_size` samples. Smaller
+    /// blocks lower the latency, larger blocks lower the cost.
+    pub fn new(block_size: usize, impulse: &[R]) -> Self {
+        let block_size = block_size.max(1);
+        let mut planner = RealFftPlanner::<R>::new();
+        let forward = planner.plan_fft_forward(2 * block_size);
+        let inverse = planner.plan_fft_inverse(2 * block_size);
+        let scratch_len = forward.get_scratch_len().max(inverse.get_scratch_len());
+        let mut self_ = Self {
+            forward,
+            inverse,
+            scratch: vec![Complex::new(R::zero(), R::zero()); scratch_len],
+            partitions: Vec::new(),
+            history: Vec::new(),
+            head: 0,
+            accumulator: vec![Complex::new(R::zero(), R::zero()); block_size + 1],
+            input: vec![R::zero(); 2 * block_size],
+            output: vec![R::zero(); block_size],
+            time: vec![R::zero(); 2 * block_size],
+            position: 0,
+            block_size,
+            taps: 0,
+        };
+        self_.set_impulse(impulse);
+        self_
+    }
+
+    /// Replace the impulse response. The state is kept if the number of partitions does not change.
+    pub fn set_impulse(&mut self, impulse: &[R]) {
+        let (block, bins) = (self.block_size, self.block_size + 1);
+        let count = impulse.len().div_ceil(block).max(1);
+        let zero = Complex::new(R::zero(), R::zero());
+        if count != self.partitions.len() {
+            self.partitions = vec![vec![zero; bins]; count];
+            self.history = vec![vec![zero; bins]; count];
+            self.head = 0;
+        }
+        // The inverse transform is not normalized, the partitions are instead.
+        let scale = R::one() / _c((2 * block) as f64);
+        for (idx, partition) in self.partitions.iter_mut().enumerate() {
+            self.time.fill(R::zero());
+            for (t, h) in self.time.iter_mut().zip(impulse.iter().skip(idx * block).take(block)) {
+                *t = *h * scale;
+            }
+            let _ = self.forward.process_with_scratch(&mut self.time, partition, &mut self.scratch);
+        }
+        self.taps = impulse.len();
+    }
+
+    /// Exchange the impulse response with the one of `other`, keeping the state of both, so
+    /// that an impulse response can be transformed ahead, e.g. on another thread, and taken
+    /// over without allocating. When the block sizes or the numbers of partitions differ,
+    /// the whole filters are exchanged instead.
+    pub fn swap_impulse(&mut self, other: &mut Self) {
+        if self.block_size == other.block_size && self.partitions.len() == other.partitions.len() {
+            core::mem::swap(&mut self.partitions, &mut other.partitions);
+            core::mem::swap(&mut self.taps, &mut other.taps);
+        } else {
+            core::mem::swap(self, other);
+        }
+    }
+
+    /// The number of coefficients of the impulse response.
+    pub fn taps(&self) -> usize {
+        self.taps
+    }
+
+    /// The delay of the output, in samples: the block size plus the delay of a linear phase
+    /// impulse response.
+    pub fn latency(&self) -> usize {
+        self.block_size + self.taps.saturating_sub(1) / 2
+    }
+
+    /// Zero the state of the convolution.
+    pub fn reset(&mut self) {
+        for spectrum in self.history.iter_mut() {
+            spectrum.fill(Complex::new(R::zero(), R::zero()));
+        }
+        self.input.fill(R::zero());
+        self.output.fill(R::zero());
+        self.position = 0;
+    }
+
+    #[inline]
+    pub fn filter(&mut self, x: R) -> R {
+        self.input[self.block_size + self.position] = x;
+        let y = self.output[self.position];
+        self.position += 1;
+        if self.position == self.block_size {
+            self.position = 0;
+            self.process_block();
+        }
+        y
+    }
+
+    #[inline]
+    pub fn filter_buffer(&mut self, input: &mut [R]) {
+        for x in input {
+            *x = self.filter(*x);
+        }
+    }
+
+    fn process_block(&mut self) {
+        let count = self.partitions.len();
+        self.time.copy_from_slice(&self.input);
+        let _ = self.forward.process_with_scratch(&mut self.time, &mut self.history[self.head], &mut self.scratch);
+
+        // The newest block goes through the first partition, the oldest through the last.
+        self.accumulator.fill(Complex::new(R::zero(), R::zero()));
+        for (age, partition) in self.partitions.iter().enumerate() {
+            let spectrum = &self.history[(self.head + count - age) % count];
+            for ((y, x), h) in self.accumulator.iter_mut().zip(spectrum.iter()).zip(partition.iter()) {
+                *y += *x * *h;
+            }
+        }
+        self.head = (self.head + 1) % count;
+
+        // The imaginary part of the DC and Nyquist bins must be zero for the inverse transform.
+        self.accumulator[0].im = R::zero();
+        self.accumulator[self.block_size].im = R::zero();
+        let _ = self.inverse.process_with_scratch(&mut self.accumulator, &mut self.time, &mut self.scratch);
+
+        // Only the second half of the circular convolution is free of aliasing.
+        let block = self.block_size;
+        self.output.copy_from_slice(&self.time[block..]);
+        self.input.copy_within(block.., 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise(count: usize) -> impl Iterator<Item = f64> {
+        (0..count).map(|n| ((n * 7919) % 1000) as f64 / 500.0 - 1.0)
+    }
+
+    #[test]
+    fn swap_impulse_matches_set_impulse() {
+        let first = frequency_sampling::<f64, _>(255, |f| if f < 0.1 { 1.0 } else { 0.0 });
+        let second = frequency_sampling::<f64, _>(255, |f| if f > 0.2 { 1.0 } else { 0.0 });
+        let mut set = LinearPhase::new(64, &first);
+        let mut swapped = set.clone();
+        let mut ahead = LinearPhase::new(64, &second);
+        let input: Vec<f64> = noise(4000).collect();
+        for x in input[..2000].iter() {
+            assert_eq!(set.filter(*x), swapped.filter(*x));
+        }
+        set.set_impulse(&second);
+        swapped.swap_impulse(&mut ahead);
+        assert_eq!(swapped.taps(), 255);
+        for x in input[2000..].iter() {
+            assert_eq!(set.filter(*x), swapped.filter(*x));
+        }
+        // The outgoing impulse response is left in the other filter.
+        let mut expected = LinearPhase::new(64, &first);
+        let mut impulse = vec![0.0; 512];
+        impulse[0] = 1.0;
+        for x in impulse {
+            assert_eq!(expected.filter(x), ahead.filter(x));
+        }
+    }
+
+    #[test]
+    fn swap_impulse_of_another_length() {
+        let short = frequency_sampling::<f64, _>(63, |_| 1.0);
+        let long = frequency_sampling::<f64, _>(1023, |_| 1.0);
+        let mut filter = LinearPhase::new(64, &short);
+        let mut ahead = LinearPhase::new(64, &long);
+        filter.swap_impulse(&mut ahead);
+        assert_eq!(filter.taps(), 1023);
+        assert_eq!(ahead.taps(), 63);
+    }
+
+    /// The response of `taps` at the normalized frequency `f`, without its linear phase.
+    fn zero_phase(taps: &[f64], f: f64) -> Complex<f64> {
+        let delay = (taps.len() - 1) as f64 / 2.0;
+        taps.iter()
+            .enumerate()
+            .map(|(n, h)| Complex::from_polar(*h, -core::f64::consts::TAU * f * (n as f64 - delay)))
+            .sum()
+    }
+
+    fn direct(taps: &[f64], input: &[f64]) -> Vec<f64> {
+        (0..input.len())
+            .map(|n| taps.iter().zip(input[..=n].iter().rev()).map(|(h, x)| h * x).sum())
+            .collect()
+    }
+
+    #[test]
+    fn designs_reach_their_magnitude() {
+        let designs = [
+            Design { curve: Curve::Peak, frequency: 0.05, resonance: 1.0, gain: 4.0, method: Method::Bilinear },
+            Design { curve: Curve::Highshelf, frequency: 0.1, resonance: 0.7, gain: 0.5, method: Method::Bilinear },
+        ];
+        let from_designs = from_designs(&designs, 1023);
+        let lowpass = |f: f64| 1.0 / (1.0 + (f / 0.05).powi(2));
+        let sampled = frequency_sampling(1023, lowpass);
+        for f in log_sweep(0.005, 0.49, 40) {
+            let response = zero_phase(&from_designs, f);
+            let expected: f64 = designs.iter().map(|d| d.magnitude(f)).product();
+            // The amplitude is real, the phase is all in the delay.
+            assert!(response.im.abs() < 1e-9, "from_designs at {}: {}", f, response);
+            assert!((20.0 * (response.re / expected).log10()).abs() < 0.1, "from_designs at {}: {} {}", f, response.re, expected);
+            let response = zero_phase(&sampled, f);
+            assert!(response.im.abs() < 1e-9, "frequency_sampling at {}: {}", f, response);
+            assert!((response.re - lowpass(f)).abs() < 1e-3, "frequency_sampling at {}: {} {}", f, response.re, lowpass(f));
+        }
+    }
+
+    #[test]
+    fn taps_are_symmetric() {
+        let design = Design { curve: Curve::Lowshelf, frequency: 0.01, resonance: 2.0, gain: 2.0, method: Method::Matched };
+        for count in [1, 2, 64, 255] {
+            let sampled = frequency_sampling::<f64, _>(count, |f| if f < 0.1 { 1.0 } else { 0.1 });
+            let designed = from_designs(&[design], count);
+            assert_eq!(sampled.len(), count | 1);
+            for taps in [sampled, designed] {
+                for (a, b) in taps.iter().zip(taps.iter().rev()) {
+                    assert!((a - b).abs() < 1e-12, "{} taps: {} {}", count, a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn partitioned_matches_direct_convolution() {
+        let input: Vec<f64> = noise(2000).collect();
+        for count in [1, 63, 255, 301] {
+            let taps = frequency_sampling::<f64, _>(count, |f| 1.0 / (1.0 + f * 20.0));
+            let expected = direct(&taps, &input);
+            for block in [1, 16, 64, 100] {
+                let mut filter = LinearPhase::new(block, &taps);
+                for (n, x) in input.iter().enumerate() {
+                    // The blocks are buffered, the rest of the latency is in the taps.
+                    let y = filter.filter(*x);
+                    let e = if n < block { 0.0 } else { expected[n - block] };
+                    assert!((y - e).abs() < 1e-10, "{} taps in blocks of {} at {}: {} {}", count, block, n, y, e);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn latency_is_the_delay_of_an_impulse() {
+        for count in [1, 63, 255] {
+            let taps = frequency_sampling::<f64, _>(count, |_| 1.0);
+            for block in [1, 32, 64] {
+                let mut filter = LinearPhase::new(block, &taps);
+                let output: Vec<f64> = (0..1024).map(|n| filter.filter(if n == 0 { 1.0 } else { 0.0 })).collect();
+                let peak = (0..output.len()).max_by(|a, b| output[*a].abs().total_cmp(&output[*b].abs())).unwrap();
+                assert_eq!(peak, filter.latency(), "{} taps in blocks of {}", count, block);
+                assert!((output[peak] - 1.0).abs() < 1e-9, "{} taps in blocks of {}: {}", count, block, output[peak]);
+            }
+        }
+    }
+}
@@ -43,6 +43,21 @@ impl<R: Real + Copy> Kernel<R> {
         self.C = Vec3::new(num[0], one(), zero());
     }
 
+    /// Take the coefficients of `other`, keeping the state of this kernel.
+    pub fn copy_coefficients(&mut self, other: &Self) {
+        self.A = other.A;
+        self.B = other.B;
+        self.C = other.C;
+    }
+
+    /// Get the coefficients the kernel was set with.
+    pub fn coefficients(&self) -> (Vec3<R>, Vec3<R>) {
+        let den = Vec3::new(one(), -self.A[(0, 0)], -self.A[(1, 0)]);
+        let num0 = self.C[0];
+        let num = Vec3::new(num0, self.B[0] + den[1] * num0, self.B[1] + den[2] * num0);
+        (num, den)
+    }
+
     /// Evaluate the kernel's transfer characteristics
     pub fn eval(&mut self, x: R) -> R {
         let u = Vec3::new(x, self.s[0], self.s[1]);
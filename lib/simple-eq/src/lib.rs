@@ -1,6 +1,6 @@
-#![cfg_attr(feature = "no_std", no_std)]
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
 
-//! A simple 32 band audio equalizer
+//! A simple multiband, multichannel audio equalizer
 //!
 //! Usage:
 //!
@@ -26,67 +26,216 @@
 //! println!("h = {:?};", h);
 //!
 //! ```
+//!
+//! A stereo equalizer of 4 bands, that only filters the side signal:
+//!
+//! ```rust
+//! use simple_eq::{Equalizer, Link};
+//! use simple_eq::design::Curve;
+//!
+//! let (mut left, mut right) = (vec![0.0; 128], vec![0.0; 128]);
+//! left[0] = 1.0;
+//!
+//! let mut eq = Equalizer::<f64, 4, 2>::with_link(48.0e3, Link::MidSide);
+//! eq.set_channel(1, 0, Curve::Highpass, 200.0, 0.5_f64.sqrt(), 1.0);
+//! eq.process_buffers(&mut [&mut left[..], &mut right[..]]);
+//! ```
+//!
+//! Features:
+//!
+//! * `std` (default): the standard library, for `to_apo` and `std::error::Error`.
+//! * `no_std`: the math of `libm`, for builds without the standard library and without
+//!   allocating, with `default-features = false`. The crate is `no_std` whenever `std` is
+//!   disabled, enabling both just builds with `std`. `no_std_check` builds it for
+//!   `thumbv7em-none-eabihf`.
+//! * `fir`: the linear phase filters of the `fir` module, with `realfft`. Needs the standard library.
+//! * `serde`: serialization of the [Design] of the filters.
+pub mod analysis;
+pub mod apo;
+pub mod bank;
+pub mod cascade;
+pub mod crossover;
 pub mod design;
+pub mod error;
 pub mod filter;
+#[cfg(feature = "fir")]
+pub mod fir;
+#[cfg(not(any(feature = "std", feature = "no_std")))]
+compile_error!("enable either the `std` feature or, without the standard library, `no_std`");
 pub mod kernel;
+pub mod response;
+pub mod svf;
 
-use nalgebra::{convert as _c, RealField as Real};
-const NUM_BANDS: usize = 8;
+use core::ops::Range;
+use nalgebra::{convert as _c, Complex, RealField as Real};
+/// The number of bands of an [Equalizer] when it is not specified.
+pub const NUM_BANDS: usize = 8;
 use design::*;
+pub use error::Error;
 use kernel::*;
+use response::*;
+
+/// How the channels of a multichannel [Equalizer] relate to each other.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Link {
+    /// Every channel shares the same bands.
+    Linked,
+    /// Every channel has its own bands.
+    Independent,
+    /// Stereo only. Channel 0 holds the bands of the mid signal and channel 1 the ones of
+    /// the side signal. Frames are still left/right.
+    MidSide,
+}
 
+/// An equalizer of `B` bands over `C` channels. The bands of every channel are processed
+/// in series.
 #[derive(Copy, Clone, Debug)]
-pub struct Equalizer<R: Real + Copy + Default> {
-    design: [Design<R>; NUM_BANDS],
-    kernel: [Kernel<R>; NUM_BANDS],
-    bypass: [bool; NUM_BANDS],
+pub struct Equalizer<R: Real + Copy + Default, const B: usize = NUM_BANDS, const C: usize = 1> {
+    design: [[Design<R>; B]; C],
+    kernel: [[Kernel<R>; B]; C],
+    bypass: [[bool; B]; C],
+    link: Link,
     sample_rate: R,
 }
 
 impl<R: Real + Default + Copy> Equalizer<R> {
-    /// Construct a new [Equalizer] instance
+    /// Construct a new mono [Equalizer] instance of [NUM_BANDS] bands
     pub fn new(sample_rate: R) -> Self {
+        Self::with_link(sample_rate, Link::Linked)
+    }
+}
+
+impl<R: Real + Default + Copy, const B: usize, const C: usize> Equalizer<R, B, C> {
+    /// Construct a new [Equalizer] with any number of bands and channels. [Link::MidSide]
+    /// falls back to [Link::Independent] unless there are two channels.
+    pub fn with_link(sample_rate: R, link: Link) -> Self {
         Self {
-            design: [Design::default(); NUM_BANDS],
-            kernel: [Kernel::default(); NUM_BANDS],
-            bypass: [true; NUM_BANDS],
+            design: [[Design::default(); B]; C],
+            kernel: [[Kernel::default(); B]; C],
+            bypass: [[true; B]; C],
+            link: Self::supported_link(link),
             sample_rate,
         }
     }
 
+    /// Like [Equalizer::with_link], but fails on [Link::MidSide] unless there are two channels.
+    pub fn try_with_link(sample_rate: R, link: Link) -> Result<Self, Error> {
+        Self::check_link(link)?;
+        Ok(Self::with_link(sample_rate, link))
+    }
+
+    /// Change how the channels relate to each other. Switching to [Link::Linked] copies
+    /// the bands of channel 0 to the others. [Link::MidSide] falls back to
+    /// [Link::Independent] unless there are two channels.
+    pub fn set_link(&mut self, link: Link) {
+        let link = Self::supported_link(link);
+        if link == Link::Linked {
+            for ch in 1..C {
+                self.design[ch] = self.design[0];
+                self.bypass[ch] = self.bypass[0];
+                for idx in 0..B {
+                    self.update(ch, idx);
+                }
+            }
+        }
+        self.link = link;
+    }
+
+    /// Like [Equalizer::set_link], but fails on [Link::MidSide] unless there are two channels.
+    /// The link is left untouched on failure.
+    pub fn try_set_link(&mut self, link: Link) -> Result<(), Error> {
+        Self::check_link(link)?;
+        self.set_link(link);
+        Ok(())
+    }
+
+    pub fn get_link(&self) -> Link {
+        self.link
+    }
+
+    fn check_link(link: Link) -> Result<(), Error> {
+        match link {
+            Link::MidSide if C != 2 => Err(Error::UnsupportedLink),
+            _ => Ok(()),
+        }
+    }
+
+    fn supported_link(link: Link) -> Link {
+        match Self::check_link(link) {
+            Ok(()) => link,
+            Err(_) => Link::Independent,
+        }
+    }
+
+    /// The channels a change to `channel` applies to.
+    #[inline]
+    fn channels(&self, channel: usize) -> Range<usize> {
+        match self.link {
+            Link::Linked => 0..C,
+            _ => channel..channel + 1,
+        }
+    }
+
     #[inline]
     pub fn set(&mut self, idx: usize, curve: Curve, frequency: R, resonance: R, gain: R) {
-        self.design[idx] = Design {
-            frequency: normalize_frequency(frequency, self.sample_rate),
-            gain,
-            resonance,
-            curve,
-        };
-        self.bypass[idx] = false;
-        self.update(idx);
+        for ch in 0..C {
+            self.set_channel(ch, idx, curve, frequency, resonance, gain);
+        }
+    }
+
+    /// Set a band of a single channel. With [Link::Linked] this sets every channel.
+    #[inline]
+    pub fn set_channel(&mut self, channel: usize, idx: usize, curve: Curve, frequency: R, resonance: R, gain: R) {
+        for ch in self.channels(channel) {
+            self.design[ch][idx] = Design {
+                frequency: normalize_frequency(frequency, self.sample_rate),
+                gain,
+                resonance,
+                curve,
+                method: self.design[ch][idx].method,
+            };
+            self.bypass[ch][idx] = false;
+            self.update(ch, idx);
+        }
+    }
+
+    /// Like [Equalizer::set], but fails instead of clamping out of range parameters.
+    /// The band is left untouched on failure.
+    #[inline]
+    pub fn try_set(&mut self, idx: usize, curve: Curve, frequency: R, resonance: R, gain: R) -> Result<(), Error> {
+        try_normalize_frequency(frequency, self.sample_rate)?;
+        check_resonance(resonance)?;
+        self.set(idx, curve, frequency, resonance, gain);
+        Ok(())
+    }
+
+    /// Like [Equalizer::set_channel], but fails instead of clamping out of range parameters.
+    #[inline]
+    pub fn try_set_channel(&mut self, channel: usize, idx: usize, curve: Curve, frequency: R, resonance: R, gain: R) -> Result<(), Error> {
+        try_normalize_frequency(frequency, self.sample_rate)?;
+        check_resonance(resonance)?;
+        self.set_channel(channel, idx, curve, frequency, resonance, gain);
+        Ok(())
     }
 
     /// Change the sample rate of the instance
     #[inline]
     pub fn set_sample_rate(&mut self, sample_rate: R) {
-        for idx in 0..NUM_BANDS {
-            let (k, d, _) = (
-                &mut self.kernel[idx],
-                &mut self.design[idx],
-                &mut self.bypass[idx],
-            );
-            let freq_hz: R = _c::<f64, R>(2.0) * self.sample_rate * d.frequency;
-            d.frequency = normalize_frequency(freq_hz, sample_rate);
-            self.sample_rate = sample_rate;
-            let (num, den) = d.digital_xfer_fn();
-            k.set(num, den);
+        for ch in 0..C {
+            for idx in 0..B {
+                let d = &mut self.design[ch][idx];
+                let freq_hz: R = self.sample_rate * d.frequency;
+                d.frequency = normalize_frequency(freq_hz, sample_rate);
+                self.update(ch, idx);
+            }
         }
+        self.sample_rate = sample_rate;
     }
 
     /// Bypass all filters in the EQ
     #[inline]
     pub fn bypass_all(&mut self, bypass: bool) {
-        for idx in 0..NUM_BANDS {
+        for idx in 0..B {
             self.set_bypass(idx, bypass);
         }
     }
@@ -94,67 +243,172 @@ impl<R: Real + Default + Copy> Equalizer<R> {
     /// Bypass an individal band of the EQ
     #[inline]
     pub fn set_bypass(&mut self, idx: usize, bypass: bool) {
-        self.bypass[idx] = bypass;
+        for ch in 0..C {
+            self.bypass[ch][idx] = bypass;
+        }
+    }
+
+    /// Bypass an individual band of a single channel. With [Link::Linked] this bypasses
+    /// the band of every channel.
+    #[inline]
+    pub fn set_channel_bypass(&mut self, channel: usize, idx: usize, bypass: bool) {
+        for ch in self.channels(channel) {
+            self.bypass[ch][idx] = bypass;
+        }
     }
 
     /// Set the gain of a single band of the equalizer
     #[inline]
     #[allow(non_snake_case)]
     pub fn set_gain(&mut self, idx: usize, gain_dB: R) {
-        self.design[idx].gain = gain_dB;
-        self.update(idx);
+        for ch in 0..C {
+            self.design[ch][idx].gain = gain_dB;
+            self.update(ch, idx);
+        }
     }
 
     /// Set the frequency of an individual band of the equalizer
     #[inline]
     pub fn set_frequency(&mut self, idx: usize, freq_hz: R) {
-        self.design[idx].frequency = normalize_frequency(freq_hz, self.sample_rate);
-        self.update(idx);
+        for ch in 0..C {
+            self.design[ch][idx].frequency = normalize_frequency(freq_hz, self.sample_rate);
+            self.update(ch, idx);
+        }
+    }
+
+    /// Like [Equalizer::set_frequency], but fails instead of clamping past Nyquist.
+    #[inline]
+    pub fn try_set_frequency(&mut self, idx: usize, freq_hz: R) -> Result<(), Error> {
+        try_normalize_frequency(freq_hz, self.sample_rate)?;
+        self.set_frequency(idx, freq_hz);
+        Ok(())
+    }
+
+    /// Set how a single band is mapped from its analog prototype
+    #[inline]
+    pub fn set_method(&mut self, idx: usize, method: Method) {
+        for ch in 0..C {
+            self.design[ch][idx].method = method;
+            self.update(ch, idx);
+        }
     }
 
     /// Set the resonance/Q factor of a single band
     #[inline]
     pub fn set_resonance(&mut self, idx: usize, resonance: R) {
-        self.design[idx].resonance = resonance;
-        self.update(idx);
+        for ch in 0..C {
+            self.design[ch][idx].resonance = resonance;
+            self.update(ch, idx);
+        }
     }
 
-    /// returns the bypass state of a single filter band
+    /// returns the bypass state of a single filter band, of channel 0
     #[inline]
     pub fn is_bypassed(&self, idx: usize) -> bool {
-        self.bypass[idx]
+        self.bypass[0][idx]
+    }
+
+    /// returns the bypass state of a single filter band of a single channel
+    #[inline]
+    pub fn is_channel_bypassed(&self, channel: usize, idx: usize) -> bool {
+        self.bypass[channel][idx]
     }
 
     /// Reset the state of all bands
     pub fn reset(&mut self) {
-        for k in self.kernel.iter_mut() {
+        for k in self.kernel.iter_mut().flatten() {
             k.reset();
         }
     }
 
-    /// Gets the design of a single band. Note that the frequency parameter is
+    /// Gets the design of a single band of channel 0. Note that the frequency parameter is
     /// in the units of normalized frequency (1/samples).
     pub fn get_design(&self, idx: usize) -> Design<R> {
-        self.design[idx]
+        self.design[0][idx]
+    }
+
+    /// Gets the design of a single band of a single channel.
+    pub fn get_channel_design(&self, channel: usize, idx: usize) -> Design<R> {
+        self.design[channel][idx]
     }
 
     #[inline]
-    fn update(&mut self, idx: usize) {
-        let (k, d) = (&mut self.kernel[idx], &self.design[idx]);
+    fn update(&mut self, ch: usize, idx: usize) {
+        let (k, d) = (&mut self.kernel[ch][idx], &self.design[ch][idx]);
         let (num, den) = d.digital_xfer_fn();
         k.set(num, den);
     }
 
-    /// Process a single sample of input
+    /// Process a single sample of a single channel, without mid/side conversion.
     #[inline]
-    pub fn process(&mut self, input: R) -> R {
-        self.kernel
+    fn process_channel(&mut self, ch: usize, input: R) -> R {
+        self.kernel[ch]
             .iter_mut()
-            .zip(self.bypass.iter())
+            .zip(self.bypass[ch].iter())
             .filter(|(_, b)| !*b)
             .fold(input, |x, (k, _)| k.eval(x))
     }
 
+    /// Process a frame holding one sample per channel, in place.
+    #[inline]
+    pub fn process_frame(&mut self, frame: &mut [R; C]) {
+        if self.link == Link::MidSide {
+            let half: R = _c(0.5);
+            let (mid, side) = ((frame[0] + frame[1]) * half, (frame[0] - frame[1]) * half);
+            let (mid, side) = (self.process_channel(0, mid), self.process_channel(1, side));
+            frame[0] = mid + side;
+            frame[1] = mid - side;
+            return;
+        }
+        for (ch, x) in frame.iter_mut().enumerate() {
+            *x = self.process_channel(ch, *x);
+        }
+    }
+
+    /// Process one buffer per channel. Only the length of the shortest buffer is processed.
+    pub fn process_buffers(&mut self, buffers: &mut [&mut [R]; C]) {
+        let len = buffers.iter().map(|b| b.len()).min().unwrap_or(0);
+        let mut frame = [R::zero(); C];
+        for n in 0..len {
+            for (x, buffer) in frame.iter_mut().zip(buffers.iter()) {
+                *x = buffer[n];
+            }
+            self.process_frame(&mut frame);
+            for (x, buffer) in frame.iter().zip(buffers.iter_mut()) {
+                buffer[n] = *x;
+            }
+        }
+    }
+
+    /// The response of a single channel at `frequency` in Hertz. With [Link::MidSide],
+    /// channel 0 is the mid and channel 1 the side signal.
+    pub fn channel_response(&self, channel: usize, frequency: R) -> Complex<R> {
+        let frequency = frequency / self.sample_rate;
+        self.kernel[channel]
+            .iter()
+            .zip(self.bypass[channel].iter())
+            .filter(|(_, b)| !*b)
+            .fold(Complex::new(R::one(), R::zero()), |h, (k, _)| h * k.response(frequency))
+    }
+
+    /// The group delay of a single channel at `frequency` in Hertz, in samples.
+    pub fn channel_group_delay(&self, channel: usize, frequency: R) -> R {
+        let frequency = frequency / self.sample_rate;
+        self.kernel[channel]
+            .iter()
+            .zip(self.bypass[channel].iter())
+            .filter(|(_, b)| !*b)
+            .fold(R::zero(), |t, (k, _)| t + k.group_delay(frequency))
+    }
+}
+
+impl<R: Real + Default + Copy, const B: usize> Equalizer<R, B, 1> {
+    /// Process a single sample of input
+    #[inline]
+    pub fn process(&mut self, input: R) -> R {
+        self.process_channel(0, input)
+    }
+
     /// Process a buffer of input samples
     #[inline]
     pub fn process_buffer(&mut self, input: &mut [R]) {
@@ -163,3 +417,84 @@ impl<R: Real + Default + Copy> Equalizer<R> {
         }
     }
 }
+
+impl<R: Real + Default + Copy, const B: usize> FrequencyResponse<R> for Equalizer<R, B, 1> {
+    /// The response of every band that is not bypassed, at `frequency` in Hertz.
+    fn response(&self, frequency: R) -> Complex<R> {
+        self.channel_response(0, frequency)
+    }
+
+    fn group_delay(&self, frequency: R) -> R {
+        self.channel_group_delay(0, frequency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::tests::noise;
+    use crate::filter::Filter;
+
+    const SR: f64 = 48e3;
+
+    /// Stereo noise, a different one on each channel.
+    fn stereo(len: usize) -> impl Iterator<Item = [f64; 2]> {
+        let mut noise = noise(2 * len);
+        (0..len).map(move |_| [noise.next().unwrap(), noise.next().unwrap()])
+    }
+
+    #[test]
+    fn independent_channels_have_their_own_bands() {
+        let mut eq = Equalizer::<f64, 2, 2>::with_link(SR, Link::Independent);
+        eq.set_channel(0, 0, Curve::Peak, 100.0, 2.0, 4.0);
+        eq.set_channel(1, 1, Curve::Lowpass, 1e3, 0.7, 1.0);
+        let (mut left, mut right) = (Filter::new(SR), Filter::new(SR));
+        left.set(Curve::Peak, 100.0, 2.0, 4.0, SR);
+        right.set(Curve::Lowpass, 1e3, 0.7, 1.0, SR);
+        for (n, [l, r]) in stereo(4000).enumerate() {
+            let mut frame = [l, r];
+            eq.process_frame(&mut frame);
+            assert!((frame[0] - left.filter(l)).abs() < 1e-12, "left at {}", n);
+            assert!((frame[1] - right.filter(r)).abs() < 1e-12, "right at {}", n);
+        }
+    }
+
+    #[test]
+    fn mid_side_with_the_same_bands_is_linked() {
+        let mut mid_side = Equalizer::<f64, 2, 2>::with_link(SR, Link::MidSide);
+        let mut linked = Equalizer::<f64, 2, 2>::with_link(SR, Link::Linked);
+        for (idx, curve, frequency) in [(0, Curve::Peak, 100.0), (1, Curve::Highshelf, 5e3)] {
+            linked.set(idx, curve, frequency, 2.0, 4.0);
+            for channel in 0..2 {
+                mid_side.set_channel(channel, idx, curve, frequency, 2.0, 4.0);
+            }
+        }
+        for (n, frame) in stereo(4000).enumerate() {
+            let (mut a, mut b) = (frame, frame);
+            mid_side.process_frame(&mut a);
+            linked.process_frame(&mut b);
+            assert!((a[0] - b[0]).abs() < 1e-12 && (a[1] - b[1]).abs() < 1e-12, "at {}: {:?} {:?}", n, a, b);
+        }
+    }
+
+    #[test]
+    fn side_band_leaves_mono_untouched() {
+        let mut eq = Equalizer::<f64, 2, 2>::with_link(SR, Link::MidSide);
+        eq.set_channel(1, 0, Curve::Peak, 1e3, 1.0, 8.0);
+        for x in noise(4000) {
+            let mut frame = [x, x];
+            eq.process_frame(&mut frame);
+            assert_eq!(frame, [x, x]);
+        }
+        // The band is there for the side signal.
+        let mut side = Filter::new(SR);
+        side.set(Curve::Peak, 1e3, 1.0, 8.0, SR);
+        eq.reset();
+        for (n, x) in noise(4000).enumerate() {
+            let mut frame = [x, -x];
+            eq.process_frame(&mut frame);
+            let y = side.filter(x);
+            assert!((frame[0] - y).abs() < 1e-12 && (frame[1] + y).abs() < 1e-12, "at {}: {:?} {}", n, frame, y);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Evaluation of the frequency, phase and group delay response of filters.
+//!
+//! Every type that can be evaluated implements [FrequencyResponse]. A [Design] is
+//! evaluated at normalized frequencies (1/samples), since it does not know the sample
+//! rate, while a [Filter](crate::filter::Filter) and an [Equalizer](crate::Equalizer)
+//! are evaluated at frequencies in Hertz.
+use crate::design::*;
+use crate::kernel::*;
+use nalgebra::{convert as _c, Complex, ComplexField, RealField as Real, Vector3 as Vec3};
+
+pub trait FrequencyResponse<R: Real + Copy> {
+    /// The complex response at `frequency`.
+    fn response(&self, frequency: R) -> Complex<R>;
+
+    /// The group delay at `frequency`, in samples.
+    fn group_delay(&self, frequency: R) -> R;
+
+    /// The linear gain at `frequency`.
+    fn magnitude(&self, frequency: R) -> R {
+        self.response(frequency).modulus()
+    }
+
+    /// The gain at `frequency` in decibels.
+    #[allow(non_snake_case)]
+    fn magnitude_dB(&self, frequency: R) -> R {
+        _c::<f64, R>(20.0) * self.magnitude(frequency).log10()
+    }
+
+    /// The phase at `frequency` in radians, between -pi and pi.
+    fn phase(&self, frequency: R) -> R {
+        self.response(frequency).argument()
+    }
+}
+
+/// `points` frequencies spaced logarithmically from `start` to `stop`, both included.
+pub fn log_sweep<R: Real + Copy>(start: R, stop: R, points: usize) -> impl Iterator<Item = R> {
+    let ratio = stop / start;
+    let last: R = _c(points.saturating_sub(1).max(1) as f64);
+    (0..points).map(move |i| start * ratio.powf(_c::<f64, R>(i as f64) / last))
+}
+
+/// Evaluate `c[0] + c[1] z^-1 + c[2] z^-2` and `c[1] z^-1 + 2 c[2] z^-2` at `z = e^(j 2 pi f)`.
+/// The real part of their ratio is the group delay of the polynomial.
+#[inline]
+fn eval_poly<R: Real + Copy>(c: &Vec3<R>, frequency: R) -> (Complex<R>, Complex<R>) {
+    let omega = R::two_pi() * frequency;
+    let z1 = Complex::new(omega.cos(), -omega.sin());
+    let z2 = z1 * z1;
+    let (c0, c1, c2) = (Complex::new(c[0], R::zero()), Complex::new(c[1], R::zero()), Complex::new(c[2], R::zero()));
+    (c0 + z1 * c1 + z2 * c2, z1 * c1 + z2 * c2 * _c::<f64, R>(2.0))
+}
+
+/// The response of a digital transfer function at a normalized frequency.
+pub fn xfer_response<R: Real + Copy>(num: &Vec3<R>, den: &Vec3<R>, frequency: R) -> Complex<R> {
+    eval_poly(num, frequency).0 / eval_poly(den, frequency).0
+}
+
+/// The group delay of a digital transfer function at a normalized frequency, in samples.
+pub fn xfer_group_delay<R: Real + Copy>(num: &Vec3<R>, den: &Vec3<R>, frequency: R) -> R {
+    let (n, dn) = eval_poly(num, frequency);
+    let (d, dd) = eval_poly(den, frequency);
+    (dn / n).re - (dd / d).re
+}
+
+impl<R: Real + Copy> FrequencyResponse<R> for Kernel<R> {
+    fn response(&self, frequency: R) -> Complex<R> {
+        let (num, den) = self.coefficients();
+        xfer_response(&num, &den, frequency)
+    }
+
+    fn group_delay(&self, frequency: R) -> R {
+        let (num, den) = self.coefficients();
+        xfer_group_delay(&num, &den, frequency)
+    }
+}
+
+impl<R: Real + Copy> FrequencyResponse<R> for Design<R> {
+    fn response(&self, frequency: R) -> Complex<R> {
+        let (num, den) = self.digital_xfer_fn();
+        xfer_response(&num, &den, frequency)
+    }
+
+    fn group_delay(&self, frequency: R) -> R {
+        let (num, den) = self.digital_xfer_fn();
+        xfer_group_delay(&num, &den, frequency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f64::consts::{FRAC_1_SQRT_2, FRAC_PI_2};
+
+    fn design(curve: Curve, frequency: f64, resonance: f64, gain: f64) -> Design<f64> {
+        Design { curve, frequency, resonance, gain, method: Method::Bilinear }
+    }
+
+    #[test]
+    fn butterworth_lowpass() {
+        for frequency in [0.001, 0.01, 0.1, 0.3] {
+            let lowpass = design(Curve::Lowpass, frequency, FRAC_1_SQRT_2, 1.0);
+            assert!((lowpass.magnitude_dB(frequency) + 3.0103).abs() < 1e-3, "{}", frequency);
+            assert!((lowpass.phase(frequency) + FRAC_PI_2).abs() < 1e-9, "{}", frequency);
+            assert!((lowpass.magnitude(1e-6) - 1.0).abs() < 1e-6);
+            assert!(lowpass.magnitude(0.5) < 1e-6);
+        }
+    }
+
+    #[test]
+    fn peak_gain() {
+        for frequency in [0.001, 0.01, 0.1, 0.3] {
+            for gain in [0.25, 0.5, 2.0, 4.0] {
+                let peak = design(Curve::Peak, frequency, 2.0, gain);
+                assert!((peak.magnitude(frequency) - gain).abs() < 1e-9, "{} {}", frequency, gain);
+                assert!(peak.phase(frequency).abs() < 1e-9);
+                assert!((peak.magnitude(1e-6) - 1.0).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn group_delay_is_the_phase_slope() {
+        let step = 1e-6;
+        for curve in [Curve::Lowpass, Curve::Highpass, Curve::Peak, Curve::Allpass] {
+            let design = design(curve, 0.05, 2.0, 2.0);
+            for frequency in log_sweep(0.001, 0.45, 20) {
+                let (before, after) = (design.response(frequency - step), design.response(frequency + step));
+                let slope = (after / before).argument() / (2.0 * step);
+                let expected = -slope / core::f64::consts::TAU;
+                assert!((design.group_delay(frequency) - expected).abs() < 1e-4 * expected.abs().max(1.0), "{:?} at {}", curve, frequency);
+            }
+        }
+    }
+
+    #[test]
+    fn kernel_matches_its_design() {
+        let design = design(Curve::Bandpass, 0.02, 4.0, 1.0);
+        let (num, den) = design.digital_xfer_fn();
+        let mut kernel = Kernel::new();
+        kernel.set(num, den);
+        for frequency in log_sweep(0.001, 0.49, 20) {
+            assert!((kernel.response(frequency) - design.response(frequency)).modulus() < 1e-9);
+            assert!((kernel.group_delay(frequency) - design.group_delay(frequency)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn sweep_ends() {
+        let sweep: Vec<f64> = log_sweep(20.0, 20000.0, 4).collect();
+        assert_eq!(sweep.len(), 4);
+        assert!((sweep[0] - 20.0).abs() < 1e-9 && (sweep[1] - 200.0).abs() < 1e-9 && (sweep[3] - 20000.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,124 @@
+#![allow(clippy::just_underscores_and_digits)]
+//! A zero delay feedback state variable filter, as an alternative to [Kernel](crate::kernel::Kernel).
+//!
+//! The trapezoidal integrators of the topology preserving transform keep the filter stable
+//! while its parameters change every sample, and the parameters themselves are smoothed
+//! towards their target so that automation does not cause zipper noise. The response is
+//! the one of the bilinear design. For reference, see Andrew Simper's
+//! ["Linear Trapezoidal Integrated SVF"](https://cytomic.com/files/dsp/SvfLinearTrapOptimised2.pdf).
+use crate::design::*;
+use nalgebra::{convert as _c, RealField as Real, Vector3 as Vec3};
+
+/// The parameters of the filter, in the order `g, k, m0, m1, m2`.
+type Coefficients<R> = [R; 5];
+
+#[derive(Copy, Clone, Debug)]
+pub struct SvfKernel<R: Real> {
+    current: Coefficients<R>,
+    target: Coefficients<R>,
+    first_order: bool,
+    smoothing: R,
+    ic1eq: R,
+    ic2eq: R,
+}
+
+impl<R: Real + Copy> Default for SvfKernel<R> {
+    fn default() -> Self {
+        // Only the direct path, i.e. a pass-through.
+        let pass: Coefficients<R> = [R::one(), R::one(), R::one(), R::zero(), R::zero()];
+        Self {
+            current: pass,
+            target: pass,
+            first_order: false,
+            smoothing: R::one(),
+            ic1eq: R::zero(),
+            ic2eq: R::zero(),
+        }
+    }
+}
+
+impl<R: Real + Copy> SvfKernel<R> {
+    /// Constuct a new filter kernel. Default's to pass-through
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reset the filter's state to zero and jump to the target parameters.
+    pub fn reset(&mut self) {
+        self.ic1eq = R::zero();
+        self.ic2eq = R::zero();
+        self.current = self.target;
+    }
+
+    /// Set how fast the parameters glide to their target, as the one pole coefficient
+    /// applied every sample. `1` jumps to the target immediately.
+    pub fn set_smoothing(&mut self, smoothing: R) {
+        self.smoothing = smoothing.max(R::zero()).min(R::one());
+    }
+
+    /// Set the target parameters from a design. Both the second order and first order
+    /// prototypes are supported, the method of the design is ignored.
+    pub fn set(&mut self, design: &Design<R>) {
+        let (num, den) = design.analog_xfer_fn();
+        let first_order = den[0] == R::zero();
+        if first_order != self.first_order {
+            // The states do not mean the same thing in both structures.
+            self.first_order = first_order;
+            self.target = Self::coefficients(num, den);
+            self.reset();
+        } else {
+            self.target = Self::coefficients(num, den);
+        }
+    }
+
+    /// Take the target parameters and the smoothing of `other`, as [SvfKernel::set] and
+    /// [SvfKernel::set_smoothing] would with its design.
+    pub fn copy_target(&mut self, other: &Self) {
+        self.smoothing = other.smoothing;
+        self.target = other.target;
+        if other.first_order != self.first_order {
+            self.first_order = other.first_order;
+            self.reset();
+        }
+    }
+
+    /// The SVF parameters that match the prewarped analog prototype `num / den`, as used by
+    /// the bilinear transform of [Design::digital_xfer_fn].
+    fn coefficients(num: Vec3<R>, den: Vec3<R>) -> Coefficients<R> {
+        let _4: R = _c(4.0);
+        if den[0] == R::zero() {
+            // (n1 s + n2) / (d1 s + d2) = m0 * highpass + m2 * lowpass of a one pole at d2 / d1.
+            let omega = den[2] / den[1];
+            return [omega / _4, R::one(), num[1] / den[1], R::zero(), num[2] / (den[1] * omega)];
+        }
+        let (num, den) = (num / den[0], den / den[0]);
+        let omega = den[2].sqrt();
+        let k = den[1] / omega;
+        let m0 = num[0];
+        [omega / _4, k, m0, num[1] / omega - k * m0, num[2] / (omega * omega) - m0]
+    }
+
+    /// Evaluate the kernel's transfer characteristics
+    #[inline]
+    pub fn eval(&mut self, x: R) -> R {
+        for (c, t) in self.current.iter_mut().zip(self.target.iter()) {
+            *c += (*t - *c) * self.smoothing;
+        }
+        let [g, k, m0, m1, m2] = self.current;
+        if self.first_order {
+            let v = (x - self.ic1eq) * g / (R::one() + g);
+            let low = v + self.ic1eq;
+            self.ic1eq = low + v;
+            return m0 * (x - low) + m2 * low;
+        }
+        let a1 = R::one() / (R::one() + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+        let v3 = x - self.ic2eq;
+        let v1 = a1 * self.ic1eq + a2 * v3;
+        let v2 = self.ic2eq + a2 * self.ic1eq + a3 * v3;
+        self.ic1eq = _c::<f64, R>(2.0) * v1 - self.ic1eq;
+        self.ic2eq = _c::<f64, R>(2.0) * v2 - self.ic2eq;
+        m0 * x + m1 * v1 + m2 * v2
+    }
+}
@@ -0,0 +1,38 @@
+//! The spectral engine against the 96 bands of the Normal quality, each with its pitch
+//! shifter, on the same block.
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use copiremap::bench::{Node96Bands, Spectral};
+
+const SAMPLE_RATE: f32 = 48e3;
+
+fn input(len: usize) -> Vec<f32> {
+    (0..len).map(|n| (n as f32 * 0.1).sin()).collect()
+}
+
+fn remap(c: &mut Criterion) {
+    let mut group = c.benchmark_group("remap");
+    for block in [64, 512] {
+        let input = input(block);
+        group.throughput(Throughput::Elements(block as u64));
+
+        let mut spectral = Spectral::new(SAMPLE_RATE);
+        let mut output = vec![0.0; block];
+        group.bench_with_input(BenchmarkId::new("SpectralRemap", block), &input, |b, input| {
+            b.iter(|| {
+                spectral.process(black_box(input), &mut output);
+                black_box(&output);
+            })
+        });
+
+        let mut bands = Node96Bands::new(SAMPLE_RATE, block);
+        group.bench_with_input(BenchmarkId::new("Node96", block), &input, |b, input| {
+            b.iter(|| {
+                black_box(bands.process(black_box(input)));
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, remap);
+criterion_main!(benches);
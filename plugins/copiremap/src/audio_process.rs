@@ -3,16 +3,45 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use nih_plug::audio_setup::BufferConfig;
 use nih_plug::formatters;
 use nih_plug::params::{BoolParam, EnumParam, FloatParam, IntParam, Params};
-use nih_plug::prelude::{Enum, FloatRange, IntRange};
+use nih_plug::prelude::{Enum, FloatRange, IntRange, SmoothingStyle};
 use nih_plug::util::db_to_gain;
-use simple_eq::design::Curve;
+use simple_eq::design::{Curve, Method};
+use simple_eq::filter::Topology;
 use crate::{PluginParams};
+use crate::configuration::Layout;
+use crate::quality::QualityTier;
 use crate::delay::Delay;
-use crate::filter::MyFilter;
+use crate::filter::{linear_phase_latency, MyFilter};
 use crate::gate::MyGate;
 use crate::hertz_calculator::{hz_cal_clh, hz_cal_tlh};
-use crate::key_note_midi_gen::{MidiNote, NoteModeMidi};
+use crate::oscillator::MyOscillator;
 use crate::pitch::MyPitch;
+use crate::sample::{Precision, Sample};
+
+/// Glide time of the band parameters with the smoothed SVF, in seconds.
+const BAND_SMOOTHING: f32 = 0.005;
+/// Ramp time of the gain and threshold params, in milliseconds.
+pub const PARAM_SMOOTHING_MS: f32 = 20.0;
+/// Length of the linear phase bands, in seconds.
+const LINEAR_PHASE_LENGTH: f32 = 0.1;
+/// Longest window of the pitch shifters, in milliseconds.
+const MAX_WINDOW_DURATION_MS: i32 = 100;
+
+/// Longest latency of a pitch shifter, the frame of the longest window, in samples.
+pub fn max_pitch_latency(sample_rate: f32) -> usize {
+    let frame = sample_rate as usize * MAX_WINDOW_DURATION_MS as usize / 1000;
+    frame + frame % 2
+}
+
+/// Number of coefficients of the linear phase bands.
+fn linear_phase_taps(sample_rate: f32) -> usize {
+    (sample_rate * LINEAR_PHASE_LENGTH) as usize
+}
+
+/// Longest latency of a band filter, the one of the linear phase bands, in samples.
+pub fn max_filter_latency(sample_rate: f32) -> usize {
+    linear_phase_latency(linear_phase_taps(sample_rate))
+}
 
 #[derive(Params)]
 pub struct AudioProcessParams {
@@ -31,17 +60,58 @@ pub struct AudioProcessParams {
     #[id = "resonance"]
     pub resonance: FloatParam,
 
+    #[id = "resonance_high"]
+    pub resonance_high: FloatParam,
+
+    #[id = "band_shape"]
+    pub band_shape: EnumParam<BandShape>,
+
+    #[id = "band_peak_gain"]
+    pub band_peak_gain: FloatParam,
+
+    #[id = "band_order"]
+    pub band_order: EnumParam<BandOrder>,
+
+    #[id = "band_design"]
+    pub band_design: EnumParam<BandDesign>,
+
+    #[id = "band_topology"]
+    pub band_topology: EnumParam<BandTopology>,
+
+    #[id = "band_phase"]
+    pub band_phase: EnumParam<BandPhase>,
+
     #[id = "pitch_shift"]
     pub pitch_shift: BoolParam,
 
     #[id = "pitch_shift_node"]
     pub pitch_shift_node: EnumParam<PitchShiftNode>,
 
+    #[id = "remap_engine"]
+    pub remap_engine: EnumParam<RemapEngine>,
+
+    /// Render groups of the 96 bands on worker threads, only used by [PitchShiftNode::Node96].
+    #[id = "band_threads"]
+    pub band_threads: BoolParam,
+
+    /// Pin the workers of [AudioProcessParams::band_threads] to their own cores. Off by
+    /// default, the instances of the plugin would pin their workers to the same cores.
+    #[id = "pin_band_threads"]
+    pub pin_band_threads: BoolParam,
+
     #[id = "pitch_shift_over_sampling"]
     pub pitch_shift_over_sampling: IntParam,
 
     #[id = "pitch_shift_window_duration_ms"]
     pub pitch_shift_window_duration_ms: IntParam,
+
+    /// Replaces the pitch shift node, over sampling and window duration, unless Manual.
+    #[id = "quality_tier"]
+    pub quality_tier: EnumParam<QualityTier>,
+
+    /// Part of the real time budget the Auto tier keeps the process time under.
+    #[id = "cpu_budget"]
+    pub cpu_budget: FloatParam,
     
     #[id = "in_key_gain"]
     pub in_key_gain: FloatParam,
@@ -52,10 +122,19 @@ pub struct AudioProcessParams {
     #[id = "off_key_gain"]
     pub off_key_gain: FloatParam,
 
+    #[id = "vocoder"]
+    pub vocoder: EnumParam<VocoderMode>,
+
+    #[id = "drone_shape"]
+    pub drone_shape: EnumParam<DroneShape>,
+
+    #[id = "drone_gain"]
+    pub drone_gain: FloatParam,
+
 }
 
 impl AudioProcessParams {
-    pub fn new(update_pitch_shift_over_sampling: Arc<AtomicBool>, update_pitch_shift_window_duration_ms: Arc<AtomicBool>, update_pitch_shift_and_after_bandpass: Arc<AtomicBool>, update_bpf_center_hz: Arc<AtomicBool>, set_pitch_shift_12_node: Arc<AtomicBool>) -> Self {
+    pub fn new(update_pitch_shift_and_after_bandpass: Arc<AtomicBool>, update_bpf_center_hz: Arc<AtomicBool>) -> Self {
         Self {
             threshold: FloatParam::new(
                 "Threshold",
@@ -65,6 +144,7 @@ impl AudioProcessParams {
                     max: db_to_gain(0.0),
                 },
             ).with_unit(" dB")
+                .with_smoother(SmoothingStyle::Logarithmic(PARAM_SMOOTHING_MS))
                 .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
                 .with_string_to_value(formatters::s2v_f32_gain_to_db()),
             threshold_flip: BoolParam::new("Threshold Flip", false),
@@ -85,6 +165,79 @@ impl AudioProcessParams {
                         })
                     }
                 ).with_step_size(0.01),
+            resonance_high: FloatParam::new("Resonance High", 50.0, FloatRange::Linear{ min: 20.0, max: 300.0 })
+                .with_callback(
+                    {
+                        let update_bpf_center_hz = update_bpf_center_hz.clone();
+                        Arc::new(move |_| {
+                            update_bpf_center_hz.store(true, Ordering::Release);
+                        })
+                    }
+                ).with_step_size(0.01),
+            band_shape: EnumParam::new("Band Shape", BandShape::ConstantPeakBandpass)
+                .with_callback(
+                    {
+                        let update_bpf_center_hz = update_bpf_center_hz.clone();
+                        Arc::new(move |_| {
+                            update_bpf_center_hz.store(true, Ordering::Release);
+                        })
+                    }
+                ),
+            band_peak_gain: FloatParam::new(
+                "Band Peak Gain",
+                db_to_gain(6.0),
+                FloatRange::Skewed {
+                    min: db_to_gain(0.0),
+                    max: db_to_gain(24.0),
+                    factor: FloatRange::gain_skew_factor(0.0, 24.0),
+                }
+            ).with_unit(" dB")
+                .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+                .with_string_to_value(formatters::s2v_f32_gain_to_db())
+                .with_callback(
+                    {
+                        let update_bpf_center_hz = update_bpf_center_hz.clone();
+                        Arc::new(move |_| {
+                            update_bpf_center_hz.store(true, Ordering::Release);
+                        })
+                    }
+                ),
+            band_order: EnumParam::new("Band Order", BandOrder::Second)
+                .with_callback(
+                    {
+                        let update_bpf_center_hz = update_bpf_center_hz.clone();
+                        Arc::new(move |_| {
+                            update_bpf_center_hz.store(true, Ordering::Release);
+                        })
+                    }
+                ),
+            band_design: EnumParam::new("Band Design", BandDesign::Matched)
+                .with_callback(
+                    {
+                        let update_bpf_center_hz = update_bpf_center_hz.clone();
+                        Arc::new(move |_| {
+                            update_bpf_center_hz.store(true, Ordering::Release);
+                        })
+                    }
+                ),
+            band_topology: EnumParam::new("Band Topology", BandTopology::StateSpace)
+                .with_callback(
+                    {
+                        let update_bpf_center_hz = update_bpf_center_hz.clone();
+                        Arc::new(move |_| {
+                            update_bpf_center_hz.store(true, Ordering::Release);
+                        })
+                    }
+                ),
+            band_phase: EnumParam::new("Band Phase", BandPhase::Minimum)
+                .with_callback(
+                    {
+                        let update_bpf_center_hz = update_bpf_center_hz.clone();
+                        Arc::new(move |_| {
+                            update_bpf_center_hz.store(true, Ordering::Release);
+                        })
+                    }
+                ),
             pitch_shift: BoolParam::new(
                 "Pitch Shift",
                 true,
@@ -94,12 +247,10 @@ impl AudioProcessParams {
                     update_pitch_shift_and_after_bandpass.store(true, Ordering::Release);
                 })
             }),
-            pitch_shift_node: EnumParam::new("Pitch Shift Node", PitchShiftNode::Node12).with_callback({
-                let set_pitch_shift_12_node = set_pitch_shift_12_node.clone();
-                Arc::new(move |_| {
-                    set_pitch_shift_12_node.store(true, Ordering::Release);
-                })
-            }),
+            pitch_shift_node: EnumParam::new("Pitch Shift Node", PitchShiftNode::Node12),
+            remap_engine: EnumParam::new("Remap Engine", RemapEngine::Bands),
+            band_threads: BoolParam::new("Multithreaded Bands", false),
+            pin_band_threads: BoolParam::new("Pin Band Threads", false),
             pitch_shift_over_sampling: IntParam::new(
                 "Pitch Shift Over Sampling",
                 1,
@@ -107,28 +258,20 @@ impl AudioProcessParams {
                     min: 1,
                     max: 8,
                 }
-            ).with_callback(
-                {
-                    Arc::new(move |_| {
-                        update_pitch_shift_over_sampling.store(true, Ordering::Release);
-                    })
-                }
             ),
             pitch_shift_window_duration_ms: IntParam::new(
                 "Pitch Shift Window Duration",
                 7,
                 IntRange::Linear {
                     min: 1,
-                    max: 100,
+                    max: MAX_WINDOW_DURATION_MS,
                 }
-            ).with_unit("ms")
-                .with_callback(
-                    {
-                        Arc::new(move |_| {
-                            update_pitch_shift_window_duration_ms.store(true, Ordering::Release);
-                        })
-                    }
-                ),
+            ).with_unit("ms"),
+            quality_tier: EnumParam::new("Quality Tier", QualityTier::Manual),
+            cpu_budget: FloatParam::new("CPU Budget", 0.7, FloatRange::Linear { min: 0.1, max: 1.0 })
+                .with_unit("%")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
             in_key_gain: FloatParam::new(
                 "In Key Gain",
                 db_to_gain(0.0),
@@ -138,6 +281,7 @@ impl AudioProcessParams {
                     factor: FloatRange::gain_skew_factor(-65.0, 12.0),
                 }
             ).with_unit(" dB")
+                .with_smoother(SmoothingStyle::Logarithmic(PARAM_SMOOTHING_MS))
                 .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
                 .with_string_to_value(formatters::s2v_f32_gain_to_db()),
             tuning_gain: FloatParam::new(
@@ -149,6 +293,7 @@ impl AudioProcessParams {
                     factor: FloatRange::gain_skew_factor(-65.0, 12.0),
                 }
             ).with_unit(" dB")
+                .with_smoother(SmoothingStyle::Logarithmic(PARAM_SMOOTHING_MS))
                 .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
                 .with_string_to_value(formatters::s2v_f32_gain_to_db()),
             off_key_gain: FloatParam::new(
@@ -160,6 +305,21 @@ impl AudioProcessParams {
                     factor: FloatRange::gain_skew_factor(-65.0, 12.0),
                 }
             ).with_unit(" dB")
+                .with_smoother(SmoothingStyle::Logarithmic(PARAM_SMOOTHING_MS))
+                .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+                .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            vocoder: EnumParam::new("Vocoder", VocoderMode::Off),
+            drone_shape: EnumParam::new("Drone Shape", DroneShape::Off),
+            drone_gain: FloatParam::new(
+                "Drone Gain",
+                db_to_gain(-12.0),
+                FloatRange::Skewed {
+                    min: db_to_gain(-65.0),
+                    max: db_to_gain(12.0),
+                    factor: FloatRange::gain_skew_factor(-65.0, 12.0),
+                }
+            ).with_unit(" dB")
+                .with_smoother(SmoothingStyle::Logarithmic(PARAM_SMOOTHING_MS))
                 .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
                 .with_string_to_value(formatters::s2v_f32_gain_to_db()),
         }
@@ -177,12 +337,154 @@ pub enum PitchShiftNode {
     Node12,
 }
 
+/// Response of each band of the bank. The peak shape only adds its boost on
+/// top of the band input, for a gentle nudge towards the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[non_exhaustive]
+pub enum BandShape {
+    /// Constant skirts, the peak gain is the resonance. Keeps the id of the former
+    /// "Bandpass", which already had this response.
+    #[id = "bandpass"]
+    #[name = "Constant Skirt Bandpass"]
+    ConstantSkirtBandpass,
+    /// A 0 dB peak, the skirts narrow as the resonance rises.
+    #[id = "constant_peak_bandpass"]
+    #[name = "Constant Peak Bandpass"]
+    ConstantPeakBandpass,
+    #[id = "peak"]
+    #[name = "Peak"]
+    Peak,
+}
+
+/// Order of the lowpass, highpass and bandpass bands. Higher orders are
+/// Butterworth cascades with steeper skirts, the other shapes stay second order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[non_exhaustive]
+pub enum BandOrder {
+    #[id = "second"]
+    #[name = "2nd"]
+    Second,
+    #[id = "fourth"]
+    #[name = "4th"]
+    Fourth,
+    #[id = "eighth"]
+    #[name = "8th"]
+    Eighth,
+}
+
+/// How the second order bands are mapped from their analog prototype. The bilinear
+/// design narrows the bands of the top octave, the matched one keeps their shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[non_exhaustive]
+pub enum BandDesign {
+    #[id = "bilinear"]
+    #[name = "Bilinear"]
+    Bilinear,
+    #[id = "matched"]
+    #[name = "Matched"]
+    Matched,
+}
+
+impl From<BandDesign> for Method {
+    fn from(design: BandDesign) -> Method {
+        match design {
+            BandDesign::Bilinear => Method::Bilinear,
+            BandDesign::Matched => Method::Matched,
+        }
+    }
+}
+
+/// Structure of the second order bands. The smoothed SVF glides to new centers and
+/// resonances instead of jumping, for automation without zipper noise, but always
+/// follows the bilinear design.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[non_exhaustive]
+pub enum BandTopology {
+    #[id = "state_space"]
+    #[name = "State Space"]
+    StateSpace,
+    #[id = "svf"]
+    #[name = "Smoothed SVF"]
+    Svf,
+}
+
+impl From<BandTopology> for Topology {
+    fn from(topology: BandTopology) -> Topology {
+        match topology {
+            BandTopology::StateSpace => Topology::StateSpace,
+            BandTopology::Svf => Topology::Tpt,
+        }
+    }
+}
+
+/// Phase of the bands. Linear phase bands stay aligned with the delayed dry signal
+/// when they are summed, at the cost of latency and CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[non_exhaustive]
+pub enum BandPhase {
+    #[id = "minimum"]
+    #[name = "Minimum"]
+    Minimum,
+    #[id = "linear"]
+    #[name = "Linear"]
+    Linear,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[non_exhaustive]
+pub enum RemapEngine {
+    #[id = "bands"]
+    #[name = "Bands"]
+    Bands,
+    #[id = "spectral"]
+    #[name = "Spectral"]
+    Spectral,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[non_exhaustive]
+pub enum VocoderMode {
+    #[id = "off"]
+    #[name = "Off"]
+    Off,
+    #[id = "input_carrier"]
+    #[name = "Input Carrier"]
+    InputCarrier,
+    #[id = "oscillator_carrier"]
+    #[name = "Oscillator Carrier"]
+    OscillatorCarrier,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[non_exhaustive]
+pub enum DroneShape {
+    #[id = "off"]
+    #[name = "Off"]
+    Off,
+    #[id = "sine"]
+    #[name = "Sine"]
+    Sine,
+    #[id = "saw"]
+    #[name = "Saw"]
+    Saw,
+}
+
 pub struct AudioProcess96 {
-    bpf: MyFilter,
+    bpf: MyFilter<Precision>,
     pub tuning: Option<MyPitch>,
     delay: Delay,
     pub(crate) gate: MyGate,
+    modulator_bpf: MyFilter<Precision>,
+    modulator: MyGate,
+    carrier: MyGate,
+    oscillator: MyOscillator,
+    drone: MyOscillator,
+    drone_envelope: MyGate,
     open: bool,
+    /// The node of the pitch shifters the band was last given.
+    node: PitchShiftNode,
+    /// The band sits above Nyquist at the current sample rate and is muted.
+    out_of_range: bool,
     pub note: u8,
     pub note_pitch: i8,
 }
@@ -194,6 +496,12 @@ impl AudioProcess96 {
         }
         self.bpf.reset();
         self.delay.reset();
+        self.modulator_bpf.reset();
+        self.modulator.reset();
+        self.carrier.reset();
+        self.oscillator.reset();
+        self.drone.reset();
+        self.drone_envelope.reset();
     }
 
     pub fn get_latency(&self) -> u32 {
@@ -209,91 +517,47 @@ impl AudioProcess96 {
         sum
     }
 
+    /// Latency of the band filter, on top of [`AudioProcess96::get_latency`].
+    pub fn get_filter_latency(&self) -> u32 {
+        self.bpf.get_latency()
+    }
+
     pub fn set_delay(&mut self, delay: u32) {
         self.delay.set_delay(delay);
     }
 
-    pub fn setup(&mut self, params: Arc<PluginParams>, note: u8, buffer_config: &BufferConfig, midi_notes: &MidiNote) {
-        let note_pitch: i8 = match params.key_note.note_mode_midi.value() {
-            NoteModeMidi::MidiWhistle | NoteModeMidi::MidiScale => midi_notes.im2t[self.note as usize],
-            _ => midi_notes.i2t[self.note as usize]
-        };
-        let mut pitch_tune_hz: f32 = 0.0;
-        let mut bandpass: f32 = 0.0;
-        self.note_pitch = note_pitch;
-        hz_cal_tlh(note, note_pitch, &mut pitch_tune_hz, &mut bandpass, params.global.hz_center.value(), params.global.hz_tuning.value(), !params.audio_process.pitch_shift.value());
-        if params.audio_process.pitch_shift_node.value() == PitchShiftNode::Node12 && note < ((params.global.low_note_off.value() as usize - 36) + 12) as u8 {
-            self.tuning = Some(MyPitch::set_window_duration_ms(params.audio_process.pitch_shift_window_duration_ms.value() as u8, buffer_config.sample_rate, params.audio_process.pitch_shift_over_sampling.value() as u8, pitch_tune_hz));
-        } else {
-            self.tuning = None;
-        }
-        if params.audio_process.pitch_shift_node.value() == PitchShiftNode::Node96 {
-            self.tuning = Some(MyPitch::set_window_duration_ms(params.audio_process.pitch_shift_window_duration_ms.value() as u8, buffer_config.sample_rate, params.audio_process.pitch_shift_over_sampling.value() as u8, pitch_tune_hz));
-        }
-        self.bpf.set(Curve::Bandpass, bandpass, params.audio_process.resonance.value(), 0.0, buffer_config.sample_rate);
+    /// Set the band up for `note` in `layout`, with a pitch shifter if the layout gives it one.
+    /// Allocates, the configurations are built on the background thread.
+    pub fn setup(&mut self, params: Arc<PluginParams>, layout: &Layout, note: u8, note_pitch: i8, buffer_config: &BufferConfig) {
+        let quality = layout.quality;
+        self.delay.set_max_delay(max_pitch_latency(buffer_config.sample_rate));
+        let mut design = DesignedBand::new(params, note, note_pitch, layout.band_phase, buffer_config.sample_rate);
+        self.tuning = layout.pitch_shifters[note as usize].then(|| MyPitch::set_window_duration_ms(quality.window_duration_ms, buffer_config.sample_rate, quality.over_sampling, design.pitch_tune_hz));
+        self.node = quality.node;
         self.note = note;
+        self.set_design(&mut design, buffer_config.sample_rate);
     }
 
-    pub fn set_pitch_shift_12_node(&mut self, params: Arc<PluginParams>, buffer_config: &BufferConfig, midi_notes: &MidiNote) {
-        let note_pitch: i8 = match params.key_note.note_mode_midi.value() {
-            NoteModeMidi::MidiWhistle | NoteModeMidi::MidiScale => midi_notes.im2t[self.note as usize],
-            _ => midi_notes.i2t[self.note as usize]
-        };
-        let mut pitch_tune_hz: f32 = 0.0;
-        let mut bandpass: f32 = 0.0;
-        self.note_pitch = note_pitch;
-        hz_cal_tlh(self.note, note_pitch, &mut pitch_tune_hz, &mut bandpass, params.global.hz_center.value(), params.global.hz_tuning.value(), !params.audio_process.pitch_shift.value());
-        if params.audio_process.pitch_shift_node.value() == PitchShiftNode::Node12 && self.note < ((params.global.low_note_off.value() as usize - 36) + 12) as u8 {
-            self.tuning = Some(MyPitch::set_window_duration_ms(params.audio_process.pitch_shift_window_duration_ms.value() as u8, buffer_config.sample_rate, params.audio_process.pitch_shift_over_sampling.value() as u8, pitch_tune_hz));
-        } else {
-            self.tuning = None;
-        }
-        if params.audio_process.pitch_shift_node.value() == PitchShiftNode::Node96 {
-            self.tuning = Some(MyPitch::set_window_duration_ms(params.audio_process.pitch_shift_window_duration_ms.value() as u8, buffer_config.sample_rate, params.audio_process.pitch_shift_over_sampling.value() as u8, pitch_tune_hz));
-        }
-    }
-
-    pub fn set_pitch_shift_and_after_bandpass(&mut self, params: Arc<PluginParams>, note_pitch: i8, buffer_config: &BufferConfig) {
-        let mut bandpass: f32 = 0.0;
-        let mut pitch_tune_hz: f32 = 0.0;
-        hz_cal_tlh(self.note, note_pitch, &mut pitch_tune_hz, &mut bandpass, params.global.hz_center.value(), params.global.hz_tuning.value(), !params.audio_process.pitch_shift.value());
-        self.bpf.set(Curve::Bandpass, bandpass, params.audio_process.resonance.value(), 0.0, buffer_config.sample_rate);
-        self.note_pitch = note_pitch;
+    /// Take the filters and the pitch of `design`, built for the note of this band. The
+    /// filters keep their state, the outgoing FIR is left in `design` to be freed elsewhere.
+    pub fn set_design(&mut self, design: &mut DesignedBand, sample_rate: f32) {
+        self.bpf.take_design(&mut design.bpf);
+        self.modulator_bpf.take_design(&mut design.modulator_bpf);
+        self.out_of_range = design.out_of_range;
+        self.set_oscillator_frequency(design.bandpass, sample_rate);
+        self.note_pitch = design.note_pitch;
         if let Some(value) = self.tuning.as_mut() {
-            value.set_pitch(pitch_tune_hz);
+            value.set_pitch(design.pitch_tune_hz);
         }
     }
-
-    pub fn set_pitch_shift_over_sampling(&mut self, params: Arc<PluginParams>) {
-        match self.tuning.as_mut() {
-            None => {}
-            Some(v) => {
-                v.set_over_sampling(params.audio_process.pitch_shift_over_sampling.value() as u8);
-            }
-        }
+    fn set_oscillator_frequency(&mut self, frequency: f32, sample_rate: f32) {
+        self.oscillator.set_frequency(frequency, sample_rate);
+        self.drone.set_frequency(frequency, sample_rate);
     }
 
-    pub fn set_pitch_shift_window_duration_ms(&mut self, params: Arc<PluginParams>, buffer_config: &BufferConfig) {
-        match self.tuning.as_mut() {
-            None => {}
-            Some(v) => {
-                *v = MyPitch::set_window_duration_ms(params.audio_process.pitch_shift_window_duration_ms.value() as u8, buffer_config.sample_rate, params.audio_process.pitch_shift_over_sampling.value() as u8, v.get_pitch());
-            }
-        }
-    }
-
-    pub fn set_bpf_center_hz(&mut self, params: Arc<PluginParams>, buffer_config: &BufferConfig, midi_notes: &MidiNote) {
-        let note_pitch: i8 = match params.key_note.note_mode_midi.value() {
-            NoteModeMidi::MidiWhistle | NoteModeMidi::MidiScale => midi_notes.im2t[self.note as usize],
-            _ => midi_notes.i2t[self.note as usize]
-        };
-        let mut center_hz: f32 = 0.0;
-        hz_cal_clh(self.note, note_pitch, &mut center_hz, params.global.hz_center.value(), !params.audio_process.pitch_shift.value());
-        self.bpf.set(Curve::Bandpass, center_hz, params.audio_process.resonance.value(), 0.0, buffer_config.sample_rate);
-    }
-
-    pub fn process(&mut self, input: f32, params: Arc<PluginParams>, audio_id: usize, input_param: f32, buffer_config: &BufferConfig, buf_size: usize) -> f32 {
-        let pitch: f32 = match params.audio_process.pitch_shift.value() && !(self.note_pitch == 0 || self.note_pitch == -128) && !!(params.audio_process.pitch_shift_node.value() == PitchShiftNode::Node12 || self.open) {
+    /// `input_param`, `threshold` and `drone_gain` are the smoothed params at this sample.
+    pub fn process(&mut self, input: f32, modulator: f32, params: &PluginParams, audio_id: usize, input_param: f32, threshold: f32, drone_gain: f32, buffer_config: &BufferConfig, buf_size: usize) -> f32 {
+        let pitch: f32 = match params.audio_process.pitch_shift.value() && !(self.note_pitch == 0 || self.note_pitch == -128) && !!(self.node == PitchShiftNode::Node12 || self.open) {
             true => match self.tuning.as_mut() {
                 None => {
                     0.0
@@ -311,33 +575,63 @@ impl AudioProcess96 {
                 }
             }
         };
-        let bpf: f32 = match params.audio_process.pitch_shift_node.value() {
+        let bpf: f32 = match self.node {
             PitchShiftNode::Node12 => {
                 pitch
             }
             PitchShiftNode::Node96 => {
                 if input_param > db_to_gain(-60.0) {
-                    self.process_bpf(pitch, audio_id, input_param, params.clone())
+                    self.process_bpf(pitch, modulator, audio_id, input_param, drone_gain, params, buf_size)
                 } else {
                     0.0
                 }
             }
         };
         let flip = params.audio_process.threshold_flip.value();
-        self.open = self.gate.update_fast_param(bpf, buffer_config, params.audio_process.threshold.value(), params.audio_process.threshold_attack.value(), params.audio_process.threshold_release.value(), buf_size, flip, audio_id).0;
+        self.open = self.gate.update_fast_param(bpf, buffer_config, threshold, params.audio_process.threshold_attack.value(), params.audio_process.threshold_release.value(), buf_size, flip, audio_id).0;
         // output = if self.note_pitch == -128 { 0.0 } else { output };
         bpf * self.gate.get_param(flip, audio_id)
     }
 
-    pub fn process_bpf(&mut self, input: f32, audio_id: usize, input_param: f32, params: Arc<PluginParams>) -> f32 {
-        if !(self.note_pitch == -128 && params.key_note.mute_off_key.value()) {self.bpf.process(input, audio_id) * input_param } else { 0.0 }
+    pub fn process_bpf(&mut self, input: f32, modulator: f32, audio_id: usize, input_param: f32, drone_gain: f32, params: &PluginParams, buf_size: usize) -> f32 {
+        if !(self.note_pitch == -128 && params.key_note.mute_off_key.value()) && !self.out_of_range {
+            let x = Precision::from_host(input);
+            let bpf = match params.audio_process.band_shape.value() {
+                // A linear phase peak already subtracts the delayed input.
+                BandShape::Peak if !self.bpf.is_linear_phase() => self.bpf.process(x, audio_id) - x,
+                _ => self.bpf.process(x, audio_id),
+            }.to_host();
+            let band = match params.audio_process.vocoder.value() {
+                VocoderMode::Off => bpf,
+                VocoderMode::InputCarrier => {
+                    let carrier = self.carrier.update_envelope(bpf, buf_size, audio_id);
+                    bpf * self.process_modulator(modulator, audio_id, buf_size) / carrier.max(db_to_gain(-60.0))
+                }
+                VocoderMode::OscillatorCarrier => {
+                    self.oscillator.process(audio_id) * self.process_modulator(modulator, audio_id, buf_size) * std::f32::consts::SQRT_2
+                }
+            };
+            band * input_param + self.process_drone(bpf, audio_id, buf_size, drone_gain, params)
+        } else { 0.0 }
     }
 
-    pub fn fn_update_pitch_shift_and_after_bandpass(params: Arc<PluginParams>, audio_process: &mut [AudioProcess96], buffer_config: &BufferConfig, note_pitch: [i8; 96]) {
-        for (i, ap) in audio_process.iter_mut().enumerate() {
-            ap.set_pitch_shift_and_after_bandpass(params.clone(), note_pitch[i], buffer_config);
+    /// Oscillator layer on in key bands, following the level of the band input.
+    fn process_drone(&mut self, bpf: f32, audio_id: usize, buf_size: usize, drone_gain: f32, params: &PluginParams) -> f32 {
+        if self.note_pitch != 0 {
+            return 0.0;
         }
-        println!("Doing {:?}", note_pitch)
+        let oscillator = match params.audio_process.drone_shape.value() {
+            DroneShape::Off => return 0.0,
+            DroneShape::Sine => self.drone.process(audio_id) * std::f32::consts::SQRT_2,
+            DroneShape::Saw => self.drone.process_saw(audio_id) * 3.0_f32.sqrt(),
+        };
+        oscillator * self.drone_envelope.update_envelope(bpf, buf_size, audio_id) * drone_gain
+    }
+
+    /// Envelope of the sidechain modulator inside this band, measured before the remap.
+    fn process_modulator(&mut self, modulator: f32, audio_id: usize, buf_size: usize) -> f32 {
+        let band = self.modulator_bpf.process(Precision::from_host(modulator), audio_id).to_host();
+        self.modulator.update_envelope(band, buf_size, audio_id)
     }
 }
 
@@ -348,10 +642,186 @@ impl Default for AudioProcess96 {
             tuning: None,
             delay: Delay::default(),
             gate: MyGate::new(),
+            modulator_bpf: MyFilter::default(),
+            modulator: MyGate::new(),
+            carrier: MyGate::new(),
+            oscillator: MyOscillator::default(),
+            drone: MyOscillator::default(),
+            drone_envelope: MyGate::new(),
             open: false,
+            node: PitchShiftNode::Node12,
+            out_of_range: false,
             note: 0,
             note_pitch: 0,
         }
     }
 
-}
\ No newline at end of file
+}
+/// The filters and the pitch of a band for a note table, designed on the background thread
+/// and handed over with [`AudioProcess96::set_design`], since a linear phase band allocates.
+pub struct DesignedBand {
+    bpf: MyFilter<Precision>,
+    modulator_bpf: MyFilter<Precision>,
+    /// The center of the band after the remap, also the frequency of its oscillators.
+    bandpass: f32,
+    pitch_tune_hz: f32,
+    note_pitch: i8,
+    out_of_range: bool,
+}
+
+impl DesignedBand {
+    pub fn new(params: Arc<PluginParams>, note: u8, note_pitch: i8, band_phase: BandPhase, sample_rate: f32) -> Self {
+        let mut pitch_tune_hz: f32 = 0.0;
+        let mut bandpass: f32 = 0.0;
+        hz_cal_tlh(note, note_pitch, &mut pitch_tune_hz, &mut bandpass, params.global.hz_center.value(), params.global.hz_tuning.value(), !params.audio_process.pitch_shift.value());
+        let resonance = Self::get_resonance(&params, note);
+        let (bpf, out_of_range) = Self::band_filter(&params, bandpass, resonance, band_phase, sample_rate);
+
+        let mut modulator_hz: f32 = 0.0;
+        hz_cal_clh(note, 0, &mut modulator_hz, params.global.hz_center.value(), true);
+        let mut modulator_bpf = MyFilter::default();
+        modulator_bpf.set_method(params.audio_process.band_design.value().into());
+        modulator_bpf.set(Curve::Bandpass, Precision::from_host(modulator_hz), Precision::from_host(resonance), 0.0, Precision::from_host(sample_rate));
+
+        Self {
+            bpf,
+            modulator_bpf,
+            bandpass,
+            pitch_tune_hz,
+            note_pitch,
+            out_of_range,
+        }
+    }
+
+    /// The filter of a band centered on `bandpass`, in any precision, and whether `bandpass`
+    /// is past Nyquist, in which case the filter is left undesigned.
+    fn band_filter<S: Sample>(params: &PluginParams, bandpass: f32, resonance: f32, band_phase: BandPhase, sample_rate: f32) -> (MyFilter<S>, bool) {
+        let (curve, gain) = match params.audio_process.band_shape.value() {
+            BandShape::ConstantSkirtBandpass => (Curve::BandpassConstantSkirt, 0.0),
+            BandShape::ConstantPeakBandpass => (Curve::Bandpass, 0.0),
+            BandShape::Peak => (Curve::Peak, params.audio_process.band_peak_gain.value()),
+        };
+        let mut bpf = MyFilter::default();
+        bpf.set_order(match params.audio_process.band_order.value() {
+            BandOrder::Second => 2,
+            BandOrder::Fourth => 4,
+            BandOrder::Eighth => 8,
+        });
+        bpf.set_method(params.audio_process.band_design.value().into());
+        let out_of_range = bpf.try_set(curve, S::from_host(bandpass), S::from_host(resonance), S::from_host(gain), S::from_host(sample_rate)).is_err();
+        bpf.set_topology(params.audio_process.band_topology.value().into(), S::from_host(BAND_SMOOTHING));
+        let taps = match band_phase {
+            BandPhase::Minimum => 0,
+            BandPhase::Linear => linear_phase_taps(sample_rate),
+        };
+        bpf.set_linear_phase(taps, params.audio_process.band_shape.value() == BandShape::Peak);
+        (bpf, out_of_range)
+    }
+
+    /// Design the 96 bands for `note_table`, the pitch of the note of every band.
+    pub fn bands(params: Arc<PluginParams>, note_table: [i8; 96], band_phase: BandPhase, sample_rate: f32) -> Vec<Self> {
+        note_table
+            .iter()
+            .enumerate()
+            .map(|(note, note_pitch)| Self::new(params.clone(), note as u8, *note_pitch, band_phase, sample_rate))
+            .collect()
+    }
+
+    /// Resonance of the band of `note`, interpolated per octave from `resonance` at the
+    /// lowest band to `resonance_high` at the highest one.
+    fn get_resonance(params: &PluginParams, note: u8) -> f32 {
+        let low = params.audio_process.resonance.value();
+        let high = params.audio_process.resonance_high.value();
+        let t = note as f32 / 95.0;
+        low.powf(1.0 - t) * high.powf(t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CoPiReMapPlugin;
+
+    /// The resonances of the lowest and of the highest band in [peak_gain].
+    const RESONANCE: f32 = 20.0;
+    const RESONANCE_HIGH: f32 = 120.0;
+
+    /// The params of [peak_gain], with [RESONANCE] and [RESONANCE_HIGH].
+    fn band_params(band_shape: BandShape) -> Arc<PluginParams> {
+        let plugin = CoPiReMapPlugin::default();
+        Arc::new(PluginParams {
+            global: plugin.params.global.clone(),
+            audio_process: Arc::new(AudioProcessParams {
+                band_shape: EnumParam::new("Band Shape", band_shape),
+                resonance: FloatParam::new("Resonance", RESONANCE, FloatRange::Linear { min: 20.0, max: 300.0 }),
+                resonance_high: FloatParam::new("Resonance High", RESONANCE_HIGH, FloatRange::Linear { min: 20.0, max: 300.0 }),
+                ..AudioProcessParams::new(Arc::new(AtomicBool::new(false)), Arc::new(AtomicBool::new(false)))
+            }),
+            key_note: plugin.params.key_note.clone(),
+        })
+    }
+
+    /// The gain of the band of `note` at its center, once settled.
+    fn peak_gain(params: Arc<PluginParams>, note: u8) -> f32 {
+        let sample_rate = 48000.0;
+        let mut band = DesignedBand::new(params, note, 0, BandPhase::Minimum, sample_rate);
+        let len = sample_rate as usize;
+        (0..len)
+            .map(|n| {
+                let x = (std::f32::consts::TAU * band.bandpass * n as f32 / sample_rate).sin();
+                (n, band.bpf.process(Precision::from_host(x), 0).to_host())
+            })
+            .filter(|(n, _)| *n >= len - len / 10)
+            .fold(0.0, |peak: f32, (_, y)| peak.max(y.abs()))
+    }
+
+    /// The default bands, which use the matched design, keep their response in f32, the
+    /// precision of a build without the `f64` feature.
+    #[test]
+    fn default_bands_in_f32() {
+        let plugin = CoPiReMapPlugin::default();
+        assert_eq!(plugin.params.audio_process.band_design.value(), BandDesign::Matched);
+        let sample_rate = 48000.0;
+        let mut seed: u32 = 1;
+        let noise: Vec<f32> = (0..sample_rate as usize)
+            .map(|_| {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                seed as f32 / u32::MAX as f32 - 0.5
+            })
+            .collect();
+        // The default resonance, and the highest one, of every note.
+        let notes = [0, 1, 12, 24, 48, 95].into_iter().flat_map(|note| [(note, DesignedBand::get_resonance(&plugin.params, note)), (note, 300.0)]);
+        for (note, resonance) in notes {
+            let band = DesignedBand::new(plugin.params.clone(), note, 0, BandPhase::Minimum, sample_rate);
+            let (mut single, _) = DesignedBand::band_filter::<f32>(&plugin.params, band.bandpass, resonance, BandPhase::Minimum, sample_rate);
+            let (mut double, _) = DesignedBand::band_filter::<f64>(&plugin.params, band.bandpass, resonance, BandPhase::Minimum, sample_rate);
+            let (mut error, mut power) = (0.0, 0.0);
+            for x in noise.iter() {
+                let reference = double.process(*x as f64, 0);
+                error += (single.process(*x, 0) as f64 - reference).powi(2);
+                power += reference * reference;
+            }
+            let floor = 10.0 * (error / power).log10();
+            assert!(floor < -20.0, "note {} at {} Hz with a Q of {}: f32 error {:.1} dB below the band", note, band.bandpass, resonance, floor);
+        }
+    }
+
+    #[test]
+    fn band_shape_peak_gains() {
+        let params = band_params(BandShape::ConstantSkirtBandpass);
+        assert_eq!(DesignedBand::get_resonance(&params, 0), RESONANCE);
+        assert_eq!(DesignedBand::get_resonance(&params, 95), RESONANCE_HIGH);
+        // The peak of a constant skirt bandpass is its resonance.
+        for (note, resonance) in [(0, RESONANCE), (95, RESONANCE_HIGH)] {
+            let peak = peak_gain(params.clone(), note);
+            assert!((peak / resonance - 1.0).abs() < 0.02, "constant skirt peak of {} at note {}, expected {}", peak, note, resonance);
+        }
+        for note in [0, 48, 95] {
+            let peak = peak_gain(band_params(BandShape::ConstantPeakBandpass), note);
+            assert!((peak - 1.0).abs() < 0.02, "constant peak of {} at note {}", peak, note);
+            let params = band_params(BandShape::Peak);
+            let (peak, expected) = (peak_gain(params.clone(), note), params.audio_process.band_peak_gain.value());
+            assert!((peak / expected - 1.0).abs() < 0.02, "peak boost of {} at note {}, expected {}", peak, note, expected);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! The two remap engines set up outside of the plugin, for the benches, which only see the
+//! public items of the crate.
+use std::sync::Arc;
+use nih_plug::prelude::*;
+use crate::audio_process::{PitchShiftNode, RemapEngine};
+use crate::configuration::{Configuration, Layout};
+use crate::key_note_midi_gen::MidiNote;
+use crate::quality::{Quality, QualityTier};
+use crate::spectral::SpectralRemap;
+use crate::{render_bands, BandInput, CoPiReMapPlugin, PluginParams, SmoothedParams};
+
+/// The default params of the plugin, and the note table they give.
+fn default_params() -> (Arc<PluginParams>, [i8; 96]) {
+    let params = CoPiReMapPlugin::default().params;
+    let mut midi_note = MidiNote::default();
+    midi_note.param_update(params.clone());
+    let note_table = midi_note.note_table(&params);
+    (params, note_table)
+}
+
+fn buffer_config(sample_rate: f32, block_size: usize) -> BufferConfig {
+    BufferConfig {
+        sample_rate,
+        min_buffer_size: None,
+        max_buffer_size: block_size as u32,
+        process_mode: ProcessMode::Realtime,
+    }
+}
+
+/// The 96 bands of the Normal quality, each with its pitch shifter, rendered one after the
+/// other like the plugin does on a single thread.
+pub struct Node96Bands {
+    params: Arc<PluginParams>,
+    buffer_config: BufferConfig,
+    configuration: Configuration,
+    smoothed: SmoothedParams,
+    modulator: Vec<f32>,
+    open: Vec<bool>,
+    outputs: Vec<Vec<f32>>,
+}
+
+impl Node96Bands {
+    pub fn new(sample_rate: f32, block_size: usize) -> Self {
+        let (params, note_table) = default_params();
+        let buffer_config = buffer_config(sample_rate, block_size);
+        let layout = Layout {
+            remap_engine: RemapEngine::Bands,
+            ..Layout::new(Quality::new(QualityTier::Normal, &params), &params)
+        };
+        debug_assert!(layout.quality.node == PitchShiftNode::Node96);
+        let configuration = Configuration::build(layout, params.clone(), &buffer_config, note_table);
+        let mut smoothed = SmoothedParams::default();
+        smoothed.allocate(block_size);
+        smoothed.next_block(&params, block_size);
+        Self {
+            params,
+            buffer_config,
+            configuration,
+            smoothed,
+            modulator: vec![0.0; block_size],
+            open: vec![true; block_size],
+            outputs: vec![vec![0.0; block_size]; 96],
+        }
+    }
+
+    /// Render a block of at most `block_size` samples of the left channel into the outputs
+    /// of the bands.
+    pub fn process(&mut self, input: &[f32]) -> &[Vec<f32>] {
+        let size = input.len();
+        let low_note = (self.params.global.low_note_off.value() as usize - 36) as u8;
+        let high_note = (self.params.global.high_note_off.value() as usize - 36) as u8;
+        let input = BandInput {
+            input,
+            modulator: &self.modulator[..size],
+            open: &self.open[..size],
+            smoothed: &self.smoothed,
+            params: &self.params,
+            buffer_config: &self.buffer_config,
+            audio_id: 0,
+            notes: low_note..=high_note,
+        };
+        render_bands(&mut self.configuration.bands, &mut self.outputs, &input);
+        &self.outputs
+    }
+}
+
+/// The spectral engine with the default params.
+pub struct Spectral {
+    spectral: SpectralRemap,
+}
+
+impl Spectral {
+    pub fn new(sample_rate: f32) -> Self {
+        let (params, note_table) = default_params();
+        let mut spectral = SpectralRemap::new(sample_rate, params.global.hz_center.value());
+        spectral.update(params, note_table);
+        Self { spectral }
+    }
+
+    /// Render a block of the left channel into `output`, one sample after the other like
+    /// the plugin does.
+    pub fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        for (y, x) in output.iter_mut().zip(input.iter()) {
+            *y = self.spectral.process(*x, 0);
+        }
+    }
+}
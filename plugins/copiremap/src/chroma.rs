@@ -0,0 +1,128 @@
+use std::f32::consts::TAU;
+use std::sync::Arc;
+use realfft::{RealFftPlanner, RealToComplex};
+use realfft::num_complex::Complex;
+
+const COMPLEX_ZERO: Complex<f32> = Complex::new(0.0, 0.0);
+
+/// Lowest and highest MIDI note taken into account when measuring the chroma.
+const LOW_NOTE: f32 = 48.0;
+const HIGH_NOTE: f32 = 108.0;
+
+/// Below this energy the sidechain is considered silent and the last scale is held.
+const SILENCE: f32 = 1.0e-7;
+
+/// Measures the pitch class energy (chroma) of a sidechain signal and turns it
+/// into the set of active notes used by [`crate::key_note_midi_gen::MidiNote`].
+pub struct Chroma {
+    fft: Arc<dyn RealToComplex<f32>>,
+    fft_scratch: Vec<Complex<f32>>,
+    fft_real: Vec<f32>,
+    fft_cplx: Vec<Complex<f32>>,
+    in_fifo: Vec<f32>,
+    windowing: Vec<f32>,
+    bin_note: Vec<i8>,
+    frame_size: usize,
+    step: usize,
+    position: usize,
+    count: usize,
+    chroma: [f32; 12],
+    pub note_keys: [bool; 12],
+}
+
+impl Chroma {
+    pub fn new(sample_rate: f32, hz_center: f32) -> Self {
+        let frame_size = ((sample_rate / 6.0) as usize).next_power_of_two();
+        let mut planner = RealFftPlanner::<f32>::new();
+        let fft = planner.plan_fft_forward(frame_size);
+        let fft_scratch = vec![COMPLEX_ZERO; fft.get_scratch_len()];
+        let mut windowing = vec![0.0; frame_size];
+        for (k, w) in windowing.iter_mut().enumerate() {
+            *w = -0.5 * (TAU * (k as f32) / frame_size as f32).cos() + 0.5;
+        }
+        let mut chroma = Self {
+            fft,
+            fft_scratch,
+            fft_real: vec![0.0; frame_size],
+            fft_cplx: vec![COMPLEX_ZERO; frame_size / 2 + 1],
+            in_fifo: vec![0.0; frame_size],
+            windowing,
+            bin_note: vec![-1; frame_size / 2 + 1],
+            frame_size,
+            step: frame_size / 4,
+            position: 0,
+            count: 0,
+            chroma: [0.0; 12],
+            note_keys: [false; 12],
+        };
+        chroma.set_hz_center(sample_rate, hz_center);
+        chroma
+    }
+
+    /// Map every FFT bin to the pitch class it belongs to, relative to `hz_center` (A4).
+    pub fn set_hz_center(&mut self, sample_rate: f32, hz_center: f32) {
+        let bin_frequencies = sample_rate / self.frame_size as f32;
+        for (k, note) in self.bin_note.iter_mut().enumerate() {
+            let hz = k as f32 * bin_frequencies;
+            let midi = if hz > 0.0 { 69.0 + 12.0 * (hz / hz_center).log2() } else { 0.0 };
+            *note = if (LOW_NOTE..=HIGH_NOTE).contains(&midi) { (midi.round() as i32).rem_euclid(12) as i8 } else { -1 };
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.in_fifo.fill(0.0);
+        self.chroma = [0.0; 12];
+        self.position = 0;
+        self.count = 0;
+    }
+
+    /// Feed one mono sidechain sample. Returns `true` when the set of active
+    /// pitch classes changed, `threshold` being relative to the loudest one.
+    pub fn process(&mut self, input: f32, threshold: f32) -> bool {
+        self.in_fifo[self.position] = input;
+        self.position = (self.position + 1) % self.frame_size;
+        self.count += 1;
+        if self.count < self.step {
+            return false;
+        }
+        self.count = 0;
+
+        for k in 0..self.frame_size {
+            let index = (self.position + k) % self.frame_size;
+            self.fft_real[k] = self.in_fifo[index] * self.windowing[k];
+        }
+        let _ = self.fft.process_with_scratch(&mut self.fft_real, &mut self.fft_cplx, &mut self.fft_scratch);
+
+        let mut energy = [0.0; 12];
+        for (bin, note) in self.fft_cplx.iter().zip(self.bin_note.iter()) {
+            if *note >= 0 {
+                energy[*note as usize] += bin.norm_sqr();
+            }
+        }
+        let scale = 1.0 / (self.frame_size * self.frame_size) as f32;
+        for (chroma, energy) in self.chroma.iter_mut().zip(energy.iter()) {
+            *chroma = 0.5 * *chroma + 0.5 * energy * scale;
+        }
+
+        let max = self.chroma.iter().cloned().fold(0.0, f32::max);
+        if max < SILENCE {
+            return false;
+        }
+        let mut note_keys = [false; 12];
+        for (key, chroma) in note_keys.iter_mut().zip(self.chroma.iter()) {
+            *key = *chroma >= max * threshold;
+        }
+        if note_keys != self.note_keys {
+            self.note_keys = note_keys;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Default for Chroma {
+    fn default() -> Self {
+        Self::new(44100.0, 440.0)
+    }
+}
@@ -0,0 +1,100 @@
+use std::sync::Arc;
+use nih_plug::audio_setup::BufferConfig;
+use crate::audio_process::{max_filter_latency, max_pitch_latency, AudioProcess96, BandPhase, PitchShiftNode, RemapEngine};
+use crate::delay::{latency_average96, latency_filter96};
+use crate::quality::Quality;
+use crate::spectral::SpectralRemap;
+use crate::PluginParams;
+
+/// Longest latency of any configuration, in samples: the spectral frames, or the longest
+/// pitch shifter window with a linear phase band after it.
+pub fn max_latency(sample_rate: f32, spectral: &SpectralRemap) -> usize {
+    (max_pitch_latency(sample_rate) + max_filter_latency(sample_rate)).max(spectral.get_latency() as usize)
+}
+
+/// What the bands of a configuration are built for. A change of layout builds a new
+/// configuration, which renders next to the current one until it is crossfaded in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layout {
+    pub quality: Quality,
+    pub remap_engine: RemapEngine,
+    pub band_phase: BandPhase,
+    /// The bands with a pitch shifter.
+    pub pitch_shifters: [bool; 96],
+}
+
+impl Layout {
+    pub fn new(quality: Quality, params: &PluginParams) -> Self {
+        let low_note = params.global.low_note_off.value() as usize - 36;
+        let mut pitch_shifters = [false; 96];
+        for (note, pitch_shifter) in pitch_shifters.iter_mut().enumerate() {
+            // With 12 nodes the lowest octave of the range is shifted, the bands above reuse it.
+            *pitch_shifter = match quality.node {
+                PitchShiftNode::Node12 => note < low_note + 12,
+                PitchShiftNode::Node96 => true,
+            };
+        }
+        Self {
+            quality,
+            remap_engine: params.audio_process.remap_engine.value(),
+            band_phase: params.audio_process.band_phase.value(),
+            pitch_shifters,
+        }
+    }
+}
+
+/// The bands of a layout, none with the spectral engine. The spectral engine and the split
+/// of the input are shared by the configurations, which read the dry signal and the bands
+/// outside of the note offs at their own latency.
+pub struct Configuration {
+    pub layout: Layout,
+    pub bands: Vec<AudioProcess96>,
+}
+
+impl Configuration {
+    /// Bands that are not set up, for a plugin that is not initialized yet.
+    pub fn new(layout: Layout) -> Self {
+        Self {
+            layout,
+            bands: (0..96).map(|_| AudioProcess96::default()).collect(),
+        }
+    }
+
+    /// Set up the bands of `layout` for `note_table`. Allocates, it runs on the background
+    /// thread except in `initialize`.
+    pub fn build(layout: Layout, params: Arc<PluginParams>, buffer_config: &BufferConfig, note_table: [i8; 96]) -> Self {
+        let mut bands: Vec<AudioProcess96> = match layout.remap_engine {
+            RemapEngine::Bands => (0..96).map(|_| AudioProcess96::default()).collect(),
+            RemapEngine::Spectral => Vec::new(),
+        };
+        for (note, ap) in bands.iter_mut().enumerate() {
+            ap.setup(params.clone(), &layout, note as u8, note_table[note], buffer_config);
+        }
+        if !bands.is_empty() {
+            // The bands without a pitch shifter wait for the ones with one.
+            let band_latency = latency_average96(&bands);
+            for ap in bands.iter_mut() {
+                ap.set_delay(band_latency);
+            }
+        }
+        Self {
+            layout,
+            bands,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for ap in self.bands.iter_mut() {
+            ap.reset();
+        }
+    }
+
+    /// The latency of the wet signal, at which the configuration reads the dry signal.
+    pub fn latency(&self, spectral: &SpectralRemap) -> u32 {
+        match self.layout.remap_engine {
+            // The bands only compensate the pitch shifters, the band filters run after them.
+            RemapEngine::Bands => latency_average96(&self.bands) + latency_filter96(&self.bands),
+            RemapEngine::Spectral => spectral.get_latency(),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+/// Length of a crossfade between the wet and the dry signal, in seconds.
+pub const CROSSFADE_TIME: f32 = 0.02;
+
+/// Linear ramp of the wet mix, from 0 (only the latency compensated dry signal) to 1.
+pub struct Crossfade {
+    mix: f32,
+    step: f32,
+    wet: bool,
+}
+
+impl Crossfade {
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.step = 1.0 / (CROSSFADE_TIME * sample_rate).max(1.0);
+    }
+
+    /// Fade towards the wet signal, or towards the dry signal.
+    pub fn set_wet(&mut self, wet: bool) {
+        self.wet = wet;
+    }
+
+    /// Jump to the target without fading.
+    pub fn reset(&mut self) {
+        self.mix = if self.wet { 1.0 } else { 0.0 };
+    }
+
+    /// Fully dry and staying so, the wet path does not need to run.
+    pub fn is_dry(&self) -> bool {
+        !self.wet && self.mix == 0.0
+    }
+
+    /// Fill `block` with the mix of the next `len` samples.
+    pub fn next_block(&mut self, block: &mut [f32], len: usize) {
+        let step = if self.wet { self.step } else { -self.step };
+        for mix in block[..len].iter_mut() {
+            self.mix = (self.mix + step).clamp(0.0, 1.0);
+            *mix = self.mix;
+        }
+    }
+}
+
+impl Default for Crossfade {
+    fn default() -> Self {
+        Self {
+            mix: 1.0,
+            step: 1.0,
+            wet: true,
+        }
+    }
+}
+
+/// Linear ramp from an outgoing configuration to an incoming one, both rendering. The
+/// incoming one first runs muted for `warm_up` samples, until its output is wet, so that
+/// the output never goes through the dry signal alone.
+pub struct Transition {
+    mix: f32,
+    step: f32,
+    warm_up: usize,
+}
+
+impl Transition {
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.step = 1.0 / (CROSSFADE_TIME * sample_rate).max(1.0);
+    }
+
+    /// Start from the outgoing configuration alone.
+    pub fn start(&mut self, warm_up: usize) {
+        self.mix = 0.0;
+        self.warm_up = warm_up;
+    }
+
+    /// The output is the incoming configuration alone, the outgoing one can be dropped.
+    pub fn is_done(&self) -> bool {
+        self.mix == 1.0
+    }
+
+    /// Fill `block` with the mix of the incoming configuration for the next `len` samples.
+    pub fn next_block(&mut self, block: &mut [f32], len: usize) {
+        for mix in block[..len].iter_mut() {
+            if self.warm_up > 0 {
+                self.warm_up -= 1;
+            } else {
+                self.mix = (self.mix + self.step).min(1.0);
+            }
+            *mix = self.mix;
+        }
+    }
+}
+
+impl Default for Transition {
+    fn default() -> Self {
+        Self {
+            mix: 1.0,
+            step: 1.0,
+            warm_up: 0,
+        }
+    }
+}
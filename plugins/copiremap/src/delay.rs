@@ -1,48 +1,257 @@
 use crate::audio_process::AudioProcess96;
+use crate::sample::Sample;
 
-pub struct Delay {
-    pub delay_samples: [Vec<f32>; 2],
+/// Delay line on a preallocated ring buffer. Fractional delays are interpolated with a
+/// third order Lagrange polynomial, or linearly below one sample.
+pub struct Delay<S: Sample = f32> {
+    buffer: [Vec<S>; 2],
+    write: [usize; 2],
+    mask: usize,
+    max_delay: usize,
+    delay: S,
+    /// The delay of the first tap of the interpolation, and the weights of the four taps.
+    base: usize,
+    taps: [S; 4],
 }
 
-impl Delay {
+impl<S: Sample> Delay<S> {
+    /// Allocate room for delays of up to `max_delay` samples and zero the state. Call it
+    /// from `initialize`, the other methods never allocate.
+    pub fn set_max_delay(&mut self, max_delay: usize) {
+        // The interpolation reads two samples past the delay.
+        let len = (max_delay + 3).next_power_of_two();
+        for buffer in self.buffer.iter_mut() {
+            buffer.clear();
+            buffer.resize(len, S::zero());
+        }
+        self.write = [0; 2];
+        self.mask = len - 1;
+        self.max_delay = len - 3;
+        self.set_fractional_delay(self.delay.min(S::from_host(self.max_delay as f32)));
+    }
+
+    /// The longest delay the buffer has room for, at least the one given to
+    /// [`Delay::set_max_delay`].
+    pub fn get_max_delay(&self) -> usize {
+        self.max_delay
+    }
 
     pub fn set_delay(&mut self, delay: u32) {
-        self.delay_samples[0].resize(delay as usize, 0.0);
-        self.delay_samples[1].resize(delay as usize, 0.0);
+        self.set_fractional_delay(S::from_host(delay as f32));
+    }
+
+    /// Set the delay in samples, between zero and [`Delay::get_max_delay`]. A longer delay
+    /// is a bug of the caller, which sized the buffer too small: it fails in debug builds
+    /// and is clamped in release builds.
+    pub fn set_fractional_delay(&mut self, delay: S) {
+        let max_delay = S::from_host(self.max_delay as f32);
+        debug_assert!(delay >= S::zero() && delay <= max_delay, "delay of {} samples out of 0 to {}", delay, self.max_delay);
+        let delay = delay.max(S::zero()).min(max_delay);
+        let whole = delay.floor();
+        let d = delay - whole;
+        let (one, two, three, six) = (S::one(), S::from_host(2.0), S::from_host(3.0), S::from_host(6.0));
+        self.delay = delay;
+        self.base = whole.to_host() as usize;
+        self.taps = if d == S::zero() {
+            [one, S::zero(), S::zero(), S::zero()]
+        } else if self.base == 0 {
+            [one - d, d, S::zero(), S::zero()]
+        } else {
+            // Centered on the taps, the fractional delay is between the second and the third one.
+            self.base -= 1;
+            let d = d + one;
+            [
+                -(d - one) * (d - two) * (d - three) / six,
+                d * (d - two) * (d - three) / two,
+                -d * (d - one) * (d - three) / two,
+                d * (d - one) * (d - two) / six,
+            ]
+        };
     }
 
     pub fn reset(&mut self) {
-        self.delay_samples[0].fill(0.0);
-        self.delay_samples[1].fill(0.0);
+        self.buffer[0].fill(S::zero());
+        self.buffer[1].fill(S::zero());
+    }
+
+    pub fn process(&mut self, input: S, audio_id: usize) -> S {
+        let buffer = &mut self.buffer[audio_id];
+        let write = self.write[audio_id];
+        buffer[write] = input;
+        self.write[audio_id] = (write + 1) & self.mask;
+        let len = self.mask + 1;
+        self.taps.iter().enumerate().fold(S::zero(), |out, (t, tap)| {
+            out + *tap * buffer[(write + len - self.base - t) & self.mask]
+        })
+    }
+
+    /// Write the next sample without reading, for lines read with [`Delay::tap`].
+    pub fn write(&mut self, input: S, audio_id: usize) {
+        let write = self.write[audio_id];
+        self.buffer[audio_id][write] = input;
+        self.write[audio_id] = (write + 1) & self.mask;
     }
 
-    pub fn process(&mut self, input: f32, audio_id: usize) -> f32 {
-        self.delay_samples[audio_id].push(input);
-        let out = *self.delay_samples[audio_id].first().unwrap();
-        self.delay_samples[audio_id].remove(0);
-        out
+    /// The sample written `delay` whole samples before the last one, so that a line can be
+    /// read at several delays. Like [`Delay::set_fractional_delay`], `delay` must fit.
+    pub fn tap(&self, delay: usize, audio_id: usize) -> S {
+        debug_assert!(delay <= self.max_delay, "delay of {} samples out of 0 to {}", delay, self.max_delay);
+        let last = self.write[audio_id] + self.mask;
+        self.buffer[audio_id][(last - delay.min(self.max_delay)) & self.mask]
     }
 
+    /// The delay rounded to whole samples.
     pub fn get_latency(&self) -> u32 {
-        self.delay_samples[0].len() as u32
+        self.delay.round().to_host() as u32
+    }
+
+    pub fn get_delay(&self) -> S {
+        self.delay
     }
 
 }
 
-impl Default for Delay {
+impl<S: Sample> Default for Delay<S> {
     fn default() -> Self {
-        const ARRAY_REPEAT_VALUE: Vec<f32> = Vec::new();
-        let mut delay_samples: [Vec<f32>; 2] = [ARRAY_REPEAT_VALUE; 2];
-        for _i in 0..1 {
-            delay_samples[0].push(0.0);
-            delay_samples[1].push(0.0);
-        }
-        Self {
-            delay_samples,
-        }
+        let mut delay = Self {
+            buffer: [Vec::new(), Vec::new()],
+            write: [0; 2],
+            mask: 0,
+            max_delay: 0,
+            delay: S::zero(),
+            base: 0,
+            taps: [S::zero(); 4],
+        };
+        delay.set_max_delay(0);
+        delay
     }
 }
 
 pub fn latency_average96(ap: &[AudioProcess96]) -> u32 {
     ap[0].get_latency()
-}
\ No newline at end of file
+}
+
+pub fn latency_filter96(ap: &[AudioProcess96]) -> u32 {
+    ap[0].get_filter_latency()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `input` through both channels of `delay`, the right one inverted.
+    fn run(delay: &mut Delay<f64>, input: &[f64]) -> Vec<f64> {
+        input.iter().map(|x| {
+            let left = delay.process(*x, 0);
+            assert_eq!(delay.process(-*x, 1), -left);
+            left
+        }).collect()
+    }
+
+    fn impulse(len: usize) -> Vec<f64> {
+        (0..len).map(|n| if n == 0 { 1.0 } else { 0.0 }).collect()
+    }
+
+    #[test]
+    fn integer_delays() {
+        let mut delay = Delay::<f64>::default();
+        delay.set_max_delay(256);
+        for samples in [0, 1, 2, 7, 100, 256] {
+            delay.reset();
+            delay.set_delay(samples);
+            assert_eq!(delay.get_latency(), samples);
+            let output = run(&mut delay, &impulse(300));
+            for (n, y) in output.iter().enumerate() {
+                assert_eq!(*y, if n == samples as usize { 1.0 } else { 0.0 }, "{} samples, at {}", samples, n);
+            }
+        }
+    }
+
+    #[test]
+    fn fractional_delays() {
+        let omega = core::f64::consts::TAU * 0.01;
+        let input: Vec<f64> = (0..400).map(|n| (omega * n as f64).sin()).collect();
+        let mut delay = Delay::<f64>::default();
+        delay.set_max_delay(64);
+        // Linear below one sample, third order Lagrange above it.
+        for (samples, tolerance) in [(0.25, 1e-3), (0.5, 1e-3), (1.5, 1e-5), (2.25, 1e-5), (10.75, 1e-5)] {
+            delay.reset();
+            delay.set_fractional_delay(samples);
+            assert_eq!(delay.get_delay(), samples);
+            let output = run(&mut delay, &input);
+            for (n, y) in output.iter().enumerate().skip(16) {
+                let expected = (omega * (n as f64 - samples)).sin();
+                assert!((y - expected).abs() < tolerance, "{} samples, at {}: {} against {}", samples, n, y, expected);
+            }
+            // The taps of the interpolation sum to one, DC goes through untouched.
+            delay.reset();
+            let dc = run(&mut delay, &[1.0; 32]);
+            assert!((dc[31] - 1.0).abs() < 1e-12, "{} samples: {}", samples, dc[31]);
+        }
+    }
+
+    #[test]
+    fn delay_change_mid_stream() {
+        let input: Vec<f64> = (0..200).map(|n| n as f64).collect();
+        let mut delay = Delay::<f64>::default();
+        delay.set_max_delay(64);
+        delay.set_delay(10);
+        let before = run(&mut delay, &input[..100]);
+        delay.set_delay(30);
+        let after = run(&mut delay, &input[100..]);
+        for (n, y) in before.iter().enumerate().skip(10) {
+            assert_eq!(*y, input[n - 10]);
+        }
+        // The history was kept, the new delay reads it straight away.
+        for (n, y) in after.iter().enumerate() {
+            assert_eq!(*y, input[100 + n - 30]);
+        }
+    }
+
+    #[test]
+    fn taps() {
+        let input: Vec<f64> = (0..100).map(|n| n as f64).collect();
+        let mut delay = Delay::<f64>::default();
+        delay.set_max_delay(64);
+        for (n, x) in input.iter().enumerate() {
+            delay.write(*x, 0);
+            delay.write(-*x, 1);
+            for samples in [0, 1, 20, 64] {
+                let expected = if n >= samples { input[n - samples] } else { 0.0 };
+                assert_eq!(delay.tap(samples, 0), expected);
+                assert_eq!(delay.tap(samples, 1), -expected);
+            }
+        }
+    }
+
+    #[test]
+    fn longest_delay() {
+        let mut delay = Delay::<f64>::default();
+        delay.set_max_delay(100);
+        assert!(delay.get_max_delay() >= 100);
+        let longest = delay.get_max_delay() as u32;
+        delay.set_delay(longest);
+        let output = run(&mut delay, &impulse(longest as usize + 8));
+        assert_eq!(output[longest as usize], 1.0);
+        assert_eq!(output.iter().sum::<f64>(), 1.0);
+        // A fractional delay right below the longest one still has its four taps.
+        delay.reset();
+        delay.set_fractional_delay(longest as f64 - 0.5);
+        let dc = run(&mut delay, &[1.0; 300]);
+        assert!((dc[299] - 1.0).abs() < 1e-12);
+        // Shrinking the buffer brings the delay down with it.
+        delay.set_max_delay(10);
+        assert_eq!(delay.get_delay(), delay.get_max_delay() as f64);
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "out of 0 to"))]
+    fn too_long_delay() {
+        let mut delay = Delay::<f64>::default();
+        delay.set_max_delay(100);
+        let longest = delay.get_max_delay();
+        // Release builds clamp it to the longest delay.
+        delay.set_delay(longest as u32 + 1);
+        assert_eq!(delay.get_latency(), longest as u32);
+    }
+}
@@ -1,60 +1,378 @@
-use simple_eq::design::{Curve, Design};
-use simple_eq::filter::Filter;
+use nalgebra::Complex;
+use nih_plug::prelude::Enum;
+use simple_eq::analysis::{half_power_points, impulse_response, step_response};
+use simple_eq::cascade::{CascadeFilter, Response};
+use simple_eq::crossover::{BandSplit, Crossover};
+use simple_eq::design::{try_normalize_frequency, Curve, Design, Method, MAX_FREQUENCY, MIN_FREQUENCY};
+use simple_eq::Error;
+use simple_eq::filter::{Filter, Topology};
+use simple_eq::fir::{frequency_sampling, LinearPhase};
+use simple_eq::response::FrequencyResponse;
+use crate::sample::Sample;
 
-pub struct MyFilter {
-    filter: [Filter<f32>; 2],
+/// Block size of the linear phase convolution, added to its latency.
+const LINEAR_PHASE_BLOCK: usize = 256;
+
+/// Latency of a band run as a linear phase FIR of `taps` coefficients, see
+/// [`MyFilter::set_linear_phase`]. The design rounds `taps` up to an odd number.
+pub fn linear_phase_latency(taps: usize) -> usize {
+    LINEAR_PHASE_BLOCK + (taps.max(1) | 1) / 2
+}
+
+#[derive(Clone)]
+pub struct MyFilter<S: Sample = f32> {
+    filter: [Filter<S>; 2],
+    cascade: [CascadeFilter<S, 4>; 2],
+    order: usize,
+    linear_phase: Option<[LinearPhase<S>; 2]>,
 }
 
-impl MyFilter {
-    pub fn set(&mut self, curve: Curve, frequency: f32, resonance: f32, gain: f32, sample_rate: f32) {
+impl<S: Sample> MyFilter<S> {
+    pub fn set(&mut self, curve: Curve, frequency: S, resonance: S, gain: S, sample_rate: S) {
         // println!("{}", frequency);
         self.filter[0].set(curve, frequency, resonance, gain, sample_rate);
         self.filter[1].set(curve, frequency, resonance, gain, sample_rate);
+        if self.is_cascade() {
+            self.cascade[0].set(curve, Response::Butterworth, frequency, resonance, self.order, sample_rate);
+            self.cascade[1].set(curve, Response::Butterworth, frequency, resonance, self.order, sample_rate);
+        }
+    }
+
+    /// Like [`MyFilter::set`], but leaves the filter untouched when `frequency` is past Nyquist.
+    pub fn try_set(&mut self, curve: Curve, frequency: S, resonance: S, gain: S, sample_rate: S) -> Result<(), Error> {
+        try_normalize_frequency(frequency, sample_rate)?;
+        self.set(curve, frequency, resonance, gain, sample_rate);
+        Ok(())
+    }
+
+    /// Set how the second order sections are designed. Cascades are always bilinear.
+    pub fn set_method(&mut self, method: Method) {
+        self.filter[0].set_method(method);
+        self.filter[1].set_method(method);
+    }
+
+    /// Select the structure of the second order sections, `smoothing` being the glide time
+    /// in seconds of the [`Topology::Tpt`] parameters. Cascades always use the state-space form.
+    pub fn set_topology(&mut self, topology: Topology, smoothing: S) {
+        for filter in self.filter.iter_mut() {
+            filter.set_topology(topology);
+            filter.set_smoothing(smoothing);
+        }
+    }
+
+    /// Request a Butterworth band of order 2, 4 or 8. Curves other than lowpass,
+    /// highpass and bandpass always use a single second order section.
+    /// Redesigns the band with its current parameters.
+    pub fn set_order(&mut self, order: usize) {
+        if self.order == order {
+            return;
+        }
+        self.order = order;
+        let design = self.filter[0].get_design();
+        let sample_rate = self.filter[0].get_sample_rate();
+        self.set(design.curve, design.frequency * sample_rate, design.resonance, design.gain, sample_rate);
+    }
+
+    /// Run the band as a linear phase FIR of `taps` coefficients with the magnitude of the
+    /// current design, or as the minimum phase IIR when `taps` is zero. With `residual` the
+    /// FIR has the magnitude minus one, so that it is already the band minus the dry input.
+    /// Call it again after every change of the design.
+    pub fn set_linear_phase(&mut self, taps: usize, residual: bool) {
+        if taps == 0 {
+            self.linear_phase = None;
+            return;
+        }
+        let offset = if residual { S::one() } else { S::zero() };
+        let impulse = frequency_sampling(taps, |f| self.normalized_magnitude(f) - offset);
+        match self.linear_phase.as_mut() {
+            Some(fir) if fir[0].taps() == impulse.len() => {
+                fir[0].set_impulse(&impulse);
+                fir[1].set_impulse(&impulse);
+            }
+            _ => {
+                self.linear_phase = Some([LinearPhase::new(LINEAR_PHASE_BLOCK, &impulse), LinearPhase::new(LINEAR_PHASE_BLOCK, &impulse)]);
+            }
+        }
+    }
+
+    /// Take the design of `design`, computed ahead on another thread, keeping the state of
+    /// this band. The FIR this band replaces is left in `design`, to be freed elsewhere.
+    pub fn take_design(&mut self, design: &mut Self) {
+        for (filter, other) in self.filter.iter_mut().zip(design.filter.iter()) {
+            filter.copy_design(other);
+        }
+        for (cascade, other) in self.cascade.iter_mut().zip(design.cascade.iter()) {
+            cascade.copy_design(other);
+        }
+        self.order = design.order;
+        match (self.linear_phase.as_mut(), design.linear_phase.as_mut()) {
+            (Some(fir), Some(other)) => {
+                fir[0].swap_impulse(&mut other[0]);
+                fir[1].swap_impulse(&mut other[1]);
+            }
+            _ => std::mem::swap(&mut self.linear_phase, &mut design.linear_phase),
+        }
+    }
+
+    pub fn is_linear_phase(&self) -> bool {
+        self.linear_phase.is_some()
+    }
+
+    /// Delay of the linear phase FIR in samples, zero for the minimum phase IIR.
+    pub fn get_latency(&self) -> u32 {
+        self.linear_phase.as_ref().map_or(0, |fir| fir[0].latency() as u32)
+    }
+
+    /// Magnitude of the IIR band at the normalized frequency `f`.
+    fn normalized_magnitude(&self, f: S) -> S {
+        if self.is_cascade() {
+            self.cascade[0].get_kernels().iter().fold(S::one(), |m, k| m * k.magnitude(f))
+        } else {
+            self.filter[0].get_design().magnitude(f)
+        }
+    }
+
+    fn is_cascade(&self) -> bool {
+        self.order > 2 && matches!(self.filter[0].get_design().curve, Curve::Lowpass | Curve::Highpass | Curve::Bandpass)
     }
     
-    pub fn get_design(&self) -> [Design<f32>; 2] {
+    pub fn get_design(&self) -> [Design<S>; 2] {
         [self.filter[0].get_design(), self.filter[1].get_design()]
     }
 
     pub fn set_curve(&mut self, curve: Curve) {
         self.filter[0].set_curve(curve);
         self.filter[1].set_curve(curve);
+        if self.is_cascade() {
+            self.cascade[0].set_curve(curve);
+            self.cascade[1].set_curve(curve);
+        }
     }
 
-    pub fn set_frequency(&mut self, freq_hz: f32) {
+    pub fn set_frequency(&mut self, freq_hz: S) {
         self.filter[0].set_frequency(freq_hz);
         self.filter[1].set_frequency(freq_hz);
+        if self.is_cascade() {
+            self.cascade[0].set_frequency(freq_hz);
+            self.cascade[1].set_frequency(freq_hz);
+        }
     }
 
-    pub fn set_gain(&mut self, gain_d_b: f32) {
+    pub fn set_gain(&mut self, gain_d_b: S) {
         self.filter[0].set_gain(gain_d_b);
         self.filter[1].set_gain(gain_d_b);
     }
 
-    pub fn set_resonance(&mut self, resonance: f32) {
+    pub fn set_resonance(&mut self, resonance: S) {
         self.filter[0].set_resonance(resonance);
         self.filter[1].set_resonance(resonance);
+        if self.is_cascade() {
+            self.cascade[0].set_resonance(resonance);
+            self.cascade[1].set_resonance(resonance);
+        }
     }
 
-    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+    pub fn set_sample_rate(&mut self, sample_rate: S) {
         self.filter[0].set_sample_rate(sample_rate);
         self.filter[1].set_sample_rate(sample_rate);
+        if self.is_cascade() {
+            self.cascade[0].set_sample_rate(sample_rate);
+            self.cascade[1].set_sample_rate(sample_rate);
+        }
     }
 
     pub fn reset(&mut self) {
         self.filter[0].reset();
         self.filter[1].reset();
+        self.cascade[0].reset();
+        self.cascade[1].reset();
+        if let Some(fir) = self.linear_phase.as_mut() {
+            fir[0].reset();
+            fir[1].reset();
+        }
+    }
+
+    pub fn process(&mut self, input: S, audio_id: usize) -> S {
+        if let Some(fir) = self.linear_phase.as_mut() {
+            fir[audio_id].filter(input)
+        } else if self.is_cascade() {
+            self.cascade[audio_id].filter(input)
+        } else {
+            self.filter[audio_id].filter(input)
+        }
+    }
+
+    /// Render the impulse response of the left channel from a reset copy of the band,
+    /// including the delay of a linear phase band. Allocates when the band is linear phase.
+    pub fn impulse_response(&self, out: &mut [S]) {
+        let mut band = self.clone();
+        band.reset();
+        impulse_response(out, |x| band.process(x, 0));
+    }
+
+    /// Render the step response of the left channel from a reset copy of the band.
+    pub fn step_response(&self, out: &mut [S]) {
+        let mut band = self.clone();
+        band.reset();
+        step_response(out, |x| band.process(x, 0));
     }
 
-    pub fn process(&mut self, input: f32, audio_id: usize) -> f32 {
-        self.filter[audio_id].filter(input)
+    /// The -3dB points of the band in Hertz, `None` where the curve has no such point.
+    pub fn half_power_points(&self) -> (Option<S>, Option<S>) {
+        let sample_rate = self.filter[0].get_sample_rate();
+        let start = sample_rate * S::from_host(MIN_FREQUENCY as f32);
+        let stop = sample_rate * S::from_host(MAX_FREQUENCY as f32);
+        half_power_points(self, start, stop)
     }
 }
 
-impl Default for MyFilter {
+impl<S: Sample> FrequencyResponse<S> for MyFilter<S> {
+    /// The response of the IIR band at `frequency` in Hertz. A linear phase band has the
+    /// same magnitude, with a constant delay instead of this phase.
+    fn response(&self, frequency: S) -> Complex<S> {
+        if self.is_cascade() {
+            let f = frequency / self.filter[0].get_sample_rate();
+            self.cascade[0].get_kernels().iter().fold(Complex::new(S::one(), S::zero()), |h, k| h * k.response(f))
+        } else {
+            self.filter[0].response(frequency)
+        }
+    }
+
+    fn group_delay(&self, frequency: S) -> S {
+        if self.is_cascade() {
+            let f = frequency / self.filter[0].get_sample_rate();
+            self.cascade[0].get_kernels().iter().fold(S::zero(), |d, k| d + k.group_delay(f))
+        } else {
+            self.filter[0].group_delay(frequency)
+        }
+    }
+}
+
+impl<S: Sample> Default for MyFilter<S> {
     fn default() -> Self {
         Self {
-            filter: [Filter::new(44100.0); 2]
+            filter: [Filter::new(S::from_host(44100.0)); 2],
+            cascade: [CascadeFilter::new(S::from_host(44100.0)); 2],
+            order: 2,
+            linear_phase: None,
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[non_exhaustive]
+pub enum CrossoverSlope {
+    #[id = "lr2"]
+    #[name = "12 dB/oct"]
+    LR2,
+    #[id = "lr4"]
+    #[name = "24 dB/oct"]
+    LR4,
+    #[id = "lr8"]
+    #[name = "48 dB/oct"]
+    LR8,
+}
+
+impl From<CrossoverSlope> for Crossover {
+    fn from(slope: CrossoverSlope) -> Crossover {
+        match slope {
+            CrossoverSlope::LR2 => Crossover::LR2,
+            CrossoverSlope::LR4 => Crossover::LR4,
+            CrossoverSlope::LR8 => Crossover::LR8,
         }
     }
-}
\ No newline at end of file
+}
+
+/// Splits the input into the band below the low note off, the band between the
+/// two note offs and the band above the high note off. The three bands sum back
+/// to the input with a flat magnitude response.
+#[derive(Clone, Copy)]
+pub struct MySplit<S: Sample = f32> {
+    split: [BandSplit<S, 2>; 2],
+}
+
+impl<S: Sample> MySplit<S> {
+    pub fn set(&mut self, crossover: Crossover, low_hz: S, high_hz: S, sample_rate: S) {
+        self.split[0].set(crossover, [low_hz, high_hz], sample_rate);
+        self.split[1].set(crossover, [low_hz, high_hz], sample_rate);
+    }
+
+    pub fn set_crossover(&mut self, crossover: Crossover) {
+        self.split[0].set_crossover(crossover);
+        self.split[1].set_crossover(crossover);
+    }
+
+    pub fn set_low_frequency(&mut self, freq_hz: S) {
+        self.split[0].set_frequency(0, freq_hz);
+        self.split[1].set_frequency(0, freq_hz);
+    }
+
+    pub fn set_high_frequency(&mut self, freq_hz: S) {
+        self.split[0].set_frequency(1, freq_hz);
+        self.split[1].set_frequency(1, freq_hz);
+    }
+
+    pub fn reset(&mut self) {
+        self.split[0].reset();
+        self.split[1].reset();
+    }
+
+    /// Returns the low, middle and high bands.
+    pub fn process(&mut self, input: S, audio_id: usize) -> [S; 3] {
+        let mut bands = [S::zero(); 3];
+        self.split[audio_id].split(input, &mut bands);
+        bands
+    }
+}
+
+impl<S: Sample> Default for MySplit<S> {
+    fn default() -> Self {
+        Self {
+            split: [BandSplit::new(Crossover::LR4, S::from_host(44100.0)); 2],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Magnitude of the response of `impulse_response` at `freq_hz`.
+    fn magnitude(impulse_response: &[f64], freq_hz: f64, sample_rate: f64) -> f64 {
+        let omega = core::f64::consts::TAU * freq_hz / sample_rate;
+        let (re, im) = impulse_response.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, h)| {
+            (re + h * (omega * n as f64).cos(), im - h * (omega * n as f64).sin())
+        });
+        re.hypot(im)
+    }
+
+    #[test]
+    fn split_sums_flat() {
+        let sample_rate = 48000.0;
+        for crossover in [CrossoverSlope::LR2, CrossoverSlope::LR4, CrossoverSlope::LR8] {
+            let mut split = MySplit::<f64>::default();
+            split.set(crossover.into(), 100.0, 2000.0, sample_rate);
+            let mut bands = [Vec::new(), Vec::new(), Vec::new()];
+            let mut sum = Vec::new();
+            for n in 0..1 << 15 {
+                let [low, mid, high] = split.process(if n == 0 { 1.0 } else { 0.0 }, 0);
+                bands[0].push(low);
+                bands[1].push(mid);
+                bands[2].push(high);
+                sum.push(low + mid + high);
+            }
+            // The low and high bands with the middle band, which the remap replaces, sum
+            // back to the input.
+            for freq_hz in (0..=40).map(|step| 20.0 * 1000f64.powf(step as f64 / 40.0)) {
+                let gain = magnitude(&sum, freq_hz, sample_rate);
+                assert!((gain - 1.0).abs() < 1e-3, "{:?} at {} Hz: {}", crossover, freq_hz, gain);
+            }
+            // Each band only keeps its own range.
+            let [low, mid, high] = bands.each_ref().map(|band| magnitude(band, 10.0, sample_rate));
+            assert!(low > 0.99 && mid < 0.05 && high < 0.01, "{:?} at 10 Hz: {} {} {}", crossover, low, mid, high);
+            let [low, mid, high] = bands.each_ref().map(|band| magnitude(band, 450.0, sample_rate));
+            assert!(low < 0.5 && mid > 0.7 && high < 0.5, "{:?} at 450 Hz: {} {} {}", crossover, low, mid, high);
+            let [low, mid, high] = bands.each_ref().map(|band| magnitude(band, 20000.0, sample_rate));
+            assert!(low < 0.01 && mid < 0.2 && high > 0.99, "{:?} at 20 kHz: {} {} {}", crossover, low, mid, high);
+        }
+    }
+}
@@ -1,38 +1,59 @@
 use nih_plug::audio_setup::BufferConfig;
+use crate::sample::Sample;
 
-pub struct MyGate {
-    pub fast: [f32; 2],
-    sum: [f32; 2],
-    pub param: [f32; 2],
+pub struct MyGate<S: Sample = f32> {
+    pub fast: [S; 2],
+    sum: [S; 2],
+    pub param: [S; 2],
     count: [u16; 2]
 }
 
-impl MyGate {
+impl<S: Sample> MyGate<S> {
     pub fn new() -> Self {
         Self {
-            fast: [0.0; 2],
-            sum: [0.0; 2],
-            param: [0.0; 2],
+            fast: [S::zero(); 2],
+            sum: [S::zero(); 2],
+            param: [S::zero(); 2],
             count: [0; 2]
         }
     }
 
-    pub fn update_fast_param(&mut self, sample: f32, buffer_config: &BufferConfig, threshold: f32, attack_ms: f32, release_ms: f32, buf_size: usize, flip: bool, audio_id: usize) -> (bool, bool) {
+    pub fn reset(&mut self) {
+        self.fast = [S::zero(); 2];
+        self.sum = [S::zero(); 2];
+        self.param = [S::zero(); 2];
+        self.count = [0; 2];
+    }
+
+    /// Accumulate `sample` and return the RMS of the last completed block of `buf_size` samples.
+    pub fn update_rms(&mut self, sample: S, buf_size: usize, audio_id: usize) -> S {
         self.sum[audio_id] += sample * sample;
-        let delta_attack = (1.0 / (attack_ms * 0.001 * buffer_config.sample_rate * buf_size as f32)).min(1.0); // Change per sample for attack
-        let delta_release = (1.0 / (release_ms * 0.001 * buffer_config.sample_rate * buf_size as f32)).min(1.0); // Change per sample for release
         self.count[audio_id] += 1;
         if self.count[audio_id] > buf_size as u16 {
             self.count[audio_id] = 0;
-            self.fast[audio_id] = (self.sum[audio_id] / buf_size as f32).sqrt();
-            self.sum[audio_id] = 0.0;
+            self.fast[audio_id] = (self.sum[audio_id] / S::from_host(buf_size as f32)).sqrt();
+            self.sum[audio_id] = S::zero();
         }
-        if self.fast[audio_id] >= threshold && self.param[audio_id] >= 1.0 {
+        self.fast[audio_id]
+    }
+
+    /// Follow the block RMS with a one block long glide, so it can be used as an envelope.
+    pub fn update_envelope(&mut self, sample: S, buf_size: usize, audio_id: usize) -> S {
+        let rms = self.update_rms(sample, buf_size, audio_id);
+        self.param[audio_id] += (rms - self.param[audio_id]) / S::from_host(buf_size as f32);
+        self.param[audio_id]
+    }
+
+    pub fn update_fast_param(&mut self, sample: S, buffer_config: &BufferConfig, threshold: S, attack_ms: f32, release_ms: f32, buf_size: usize, flip: bool, audio_id: usize) -> (bool, bool) {
+        let delta_attack = S::from_host((1.0 / (attack_ms * 0.001 * buffer_config.sample_rate * buf_size as f32)).min(1.0)); // Change per sample for attack
+        let delta_release = S::from_host((1.0 / (release_ms * 0.001 * buffer_config.sample_rate * buf_size as f32)).min(1.0)); // Change per sample for release
+        self.update_rms(sample, buf_size, audio_id);
+        if self.fast[audio_id] >= threshold && self.param[audio_id] >= S::one() {
             (!flip, flip)
         } else if self.fast[audio_id] >= threshold{
             self.param[audio_id] += delta_attack; // Increase param for attack
             (true, true)
-        } else if self.fast[audio_id] < threshold && self.param[audio_id] <= 0.0 {
+        } else if self.fast[audio_id] < threshold && self.param[audio_id] <= S::zero() {
             (flip, !flip)
         } else {
             self.param[audio_id] -= delta_release; // Decrease param for release
@@ -40,11 +61,11 @@ impl MyGate {
         }
     }
 
-    pub fn get_param(&self, flip: bool, audio_id: usize) -> f32 {
-        if flip {1.0 - self.param[audio_id]} else {self.param[audio_id]}
+    pub fn get_param(&self, flip: bool, audio_id: usize) -> S {
+        if flip {S::one() - self.param[audio_id]} else {self.param[audio_id]}
     }
 
-    pub fn get_param_inv(&self, flip: bool, audio_id: usize) -> f32 {
-        if flip {self.param[audio_id]} else {1.0 - self.param[audio_id]}
+    pub fn get_param_inv(&self, flip: bool, audio_id: usize) -> S {
+        if flip {self.param[audio_id]} else {S::one() - self.param[audio_id]}
     }
 }
\ No newline at end of file
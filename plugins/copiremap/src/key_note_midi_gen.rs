@@ -1,10 +1,10 @@
-use nih_plug::params::{BoolParam, IntParam, EnumParam, Params};
-use nih_plug::prelude::{Enum, IntRange};
+use nih_plug::formatters;
+use nih_plug::params::{BoolParam, FloatParam, IntParam, EnumParam, Params};
+use nih_plug::prelude::{Enum, FloatRange, IntRange, SmoothingStyle};
 use crate::{PluginParams};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use nih_plug::audio_setup::BufferConfig;
-use crate::audio_process::AudioProcess96;
+use crate::audio_process::PARAM_SMOOTHING_MS;
 
 #[derive(Params)]
 pub struct KeyNoteParams {
@@ -20,6 +20,9 @@ pub struct KeyNoteParams {
     #[id = "round_up"]
     pub round_up: BoolParam,
 
+    #[id = "sidechain_threshold"]
+    pub sidechain_threshold: FloatParam,
+
     #[id = "note_c"]
     pub note_c: BoolParam,
 
@@ -89,6 +92,11 @@ impl KeyNoteParams {
                     })
                 }
             ),
+            sidechain_threshold: FloatParam::new("Sidechain Threshold", 0.5, FloatRange::Linear{ min: 0.05, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(PARAM_SMOOTHING_MS))
+                .with_unit("%")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
             note_c: BoolParam::new("Note C", false)
                 .with_callback(
                 {
@@ -213,10 +221,14 @@ pub enum NoteModeMidi {
     #[id = "midi_whistle"]
     #[name = "Midi Whistle"]
     MidiWhistle,
+    #[id = "sidechain_scale"]
+    #[name = "Sidechain Scale"]
+    SidechainScale,
 }
 
 pub struct MidiNote {
     pub midi_note: [bool; 96],
+    pub sidechain_note: [bool; 12],
     pub i2t: [i8; 96],
     pub im2t: [i8; 96],
 }
@@ -225,6 +237,7 @@ impl Default for MidiNote {
     fn default() -> Self {
         Self {
             midi_note: [false; 96],
+            sidechain_note: [false; 12],
             i2t: [0; 96],
             im2t: [0; 96]
         }
@@ -233,7 +246,7 @@ impl Default for MidiNote {
 
 impl MidiNote {
 
-    pub fn update(&mut self, params: Arc<PluginParams>, audio_process: &mut [AudioProcess96], buffer_config: &BufferConfig) {
+    pub fn update(&mut self, params: Arc<PluginParams>) {
         let mut notes: [i8; 96];
         let mut note_on_keys = [false; 96];
         let mut notes_sel: [i8; 96] = [-128; 96];
@@ -258,10 +271,9 @@ impl MidiNote {
         self.find_off_key(params.clone(), &note_on_keys, &mut notes_sel);
         notes = notes_sel;
         self.i2t = notes;
-        AudioProcess96::fn_update_pitch_shift_and_after_bandpass(params, audio_process, buffer_config, notes);
     }
 
-    pub fn update_midi(&mut self, params: Arc<PluginParams>, audio_process: &mut [AudioProcess96], buffer_config: &BufferConfig) {
+    pub fn update_midi(&mut self, params: Arc<PluginParams>) {
         let mut notes: [i8; 96] = [0; 96];
         match params.key_note.note_mode_midi.value() {
             NoteModeMidi::MidiScale => {
@@ -301,10 +313,18 @@ impl MidiNote {
                 self.find_off_key(params.clone(), &self.midi_note, &mut notes_sel);
                 notes = notes_sel;
             }
+            NoteModeMidi::SidechainScale => {
+                let mut note_on_keys = [false; 96];
+                let mut notes_sel: [i8; 96] = [-128; 96];
+                for (i, note_on_key) in note_on_keys.iter_mut().enumerate() {
+                    *note_on_key = self.sidechain_note[i % 12];
+                }
+                self.find_off_key(params.clone(), &note_on_keys, &mut notes_sel);
+                notes = notes_sel;
+            }
             _ => {}
         }
         self.im2t = notes;
-        AudioProcess96::fn_update_pitch_shift_and_after_bandpass(params, audio_process, buffer_config, notes);
     }
 
     fn find_off_key(&self, params: Arc<PluginParams>, note_on_keys: &[bool; 96], notes_sel: &mut [i8; 96]) {
@@ -355,10 +375,20 @@ impl MidiNote {
         }
     }
 
-    pub fn param_update(&mut self, params: Arc<PluginParams>, audio_process: &mut [AudioProcess96], buffer_config: &BufferConfig) {
+    /// Recompute the note table of the current mode. The bands are redesigned for it by
+    /// the caller, see [`MidiNote::note_table`].
+    pub fn param_update(&mut self, params: Arc<PluginParams>) {
+        match params.key_note.note_mode_midi.value() {
+            NoteModeMidi::MidiScale | NoteModeMidi::MidiWhistle | NoteModeMidi::SidechainScale => self.update_midi(params),
+            NoteModeMidi::Scale => self.update(params),
+        }
+    }
+
+    /// The pitch of the note of every band in the current mode.
+    pub fn note_table(&self, params: &PluginParams) -> [i8; 96] {
         match params.key_note.note_mode_midi.value() {
-            NoteModeMidi::MidiScale | NoteModeMidi::MidiWhistle => self.update_midi(params, audio_process, buffer_config),
-            NoteModeMidi::Scale => self.update(params, audio_process, buffer_config),
+            NoteModeMidi::MidiWhistle | NoteModeMidi::MidiScale | NoteModeMidi::SidechainScale => self.im2t,
+            NoteModeMidi::Scale => self.i2t,
         }
     }
 
@@ -5,11 +5,25 @@ mod delay;
 mod filter;
 mod pitch;
 mod gate;
+mod chroma;
+mod oscillator;
+mod spectral;
+mod sample;
+mod crossfade;
+mod task;
+mod pool;
+mod quality;
+mod configuration;
+#[doc(hidden)]
+pub mod bench;
 
 use std::collections::HashMap;
+use std::ops::RangeInclusive;
 use std::{sync::Arc, num::NonZeroU32};
-use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering};
+use std::time::Instant;
 use atomic_float::AtomicF64;
+use crossbeam::queue::ArrayQueue;
 use nih_plug::util::db_to_gain;
 use nih_plug::{nih_export_clap, nih_export_vst3};
 use nih_plug::params::persist::PersistentField;
@@ -19,13 +33,20 @@ use nih_plug_slint::{WindowAttributes, editor::SlintEditor};
 use plugin_canvas::{LogicalSize, Event};
 use plugin_canvas::event::EventResponse;
 use slint::{SharedString, VecModel};
-use simple_eq::design::Curve;
-use crate::audio_process::{AudioProcess96, AudioProcessParams, PitchShiftNode};
-use crate::delay::{Delay, latency_average96};
-use crate::filter::MyFilter;
+use crate::chroma::Chroma;
+use crate::crossfade::{Crossfade, Transition};
+use crate::audio_process::{AudioProcess96, AudioProcessParams, BandPhase, PitchShiftNode, RemapEngine, PARAM_SMOOTHING_MS};
+use crate::configuration::{max_latency, Configuration, Layout};
+use crate::delay::Delay;
+use crate::filter::{CrossoverSlope, MySplit};
 use crate::gate::MyGate;
+use crate::sample::{Precision, Sample};
 use crate::hertz_calculator::hz_cal_clh;
 use crate::key_note_midi_gen::{KeyNoteParams, MidiNote, NoteModeMidi};
+use crate::spectral::SpectralRemap;
+use crate::task::{BandDesigns, ConfigurationRequest, DesignRequest, Task};
+use crate::pool::{available_workers, SharedSlice, WorkerPool};
+use crate::quality::{CpuBudget, Quality, QualityTier};
 
 slint::include_modules!();
 
@@ -85,6 +106,9 @@ pub struct GlobalParams {
     #[id = "high_note_off_mute"]
     pub high_note_off_mute: BoolParam,
 
+    #[id = "crossover"]
+    pub crossover: EnumParam<CrossoverSlope>,
+
     #[id = "hz_center"]
     pub hz_center: FloatParam,
 
@@ -93,7 +117,7 @@ pub struct GlobalParams {
 }
 
 impl GlobalParams {
-    fn new(update_lowpass: Arc<AtomicBool>, update_highpass: Arc<AtomicBool>, update_bpf_center_hz: Arc<AtomicBool>, update_pitch_shift_and_after_bandpass: Arc<AtomicBool>, update_gui_scale: Arc<AtomicBool>) -> Self {
+    fn new(update_lowpass: Arc<AtomicBool>, update_highpass: Arc<AtomicBool>, update_crossover: Arc<AtomicBool>, update_bpf_center_hz: Arc<AtomicBool>, update_pitch_shift_and_after_bandpass: Arc<AtomicBool>, update_gui_scale: Arc<AtomicBool>) -> Self {
         Self {
             scale_gui: FloatParam::new("Scale Gui", 1.0, FloatRange::Linear {
                 min: 0.50,
@@ -115,6 +139,7 @@ impl GlobalParams {
                 max: db_to_gain(12.0),
                 factor: FloatRange::gain_skew_factor(-24.0, 12.0),
             }).with_unit(" dB")
+                .with_smoother(SmoothingStyle::Logarithmic(PARAM_SMOOTHING_MS))
                 .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
                 .with_string_to_value(formatters::s2v_f32_gain_to_db()),
             dry_gain: FloatParam::new("Dry Gain", db_to_gain(-24.0), FloatRange::Skewed {
@@ -122,6 +147,7 @@ impl GlobalParams {
                 max: db_to_gain(6.0),
                 factor: FloatRange::gain_skew_factor(-60.0, 6.0),
             }).with_unit(" dB")
+                .with_smoother(SmoothingStyle::Logarithmic(PARAM_SMOOTHING_MS))
                 .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
                 .with_string_to_value(formatters::s2v_f32_gain_to_db()),
             lhf_gain: FloatParam::new("Low/HighPass Gain", db_to_gain(0.0), FloatRange::Skewed {
@@ -129,12 +155,14 @@ impl GlobalParams {
                 max: db_to_gain(12.0),
                 factor: FloatRange::gain_skew_factor(-48.0, 12.0),
             }).with_unit(" dB")
+                .with_smoother(SmoothingStyle::Logarithmic(PARAM_SMOOTHING_MS))
                 .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
                 .with_string_to_value(formatters::s2v_f32_gain_to_db()),
             global_threshold: FloatParam::new("Global Threshold", db_to_gain(-90.0), FloatRange::Linear {
                 min: db_to_gain(-100.0),
                 max: db_to_gain(0.0),
             }).with_unit(" dB")
+                .with_smoother(SmoothingStyle::Logarithmic(PARAM_SMOOTHING_MS))
                 .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
                 .with_string_to_value(formatters::s2v_f32_gain_to_db()),
             global_threshold_flip: BoolParam::new("Global Threshold Flip", false),
@@ -188,6 +216,15 @@ impl GlobalParams {
                 "High Note Off Mute",
                 false,
             ),
+            crossover: EnumParam::new("Crossover", CrossoverSlope::LR4)
+                .with_callback(
+                    {
+                        let update_crossover = update_crossover.clone();
+                        Arc::new(move |_| {
+                            update_crossover.store(true, Ordering::Release);
+                        })
+                    }
+                ),
             hz_center: FloatParam::new("Hz Center", 440.0, FloatRange::Linear{ min: 415.3046976, max: 466.1637615 })
                 .with_value_to_string(formatters::v2s_f32_hz_then_khz(2))
                 .with_string_to_value(formatters::s2v_f32_hz_then_khz())
@@ -218,11 +255,12 @@ pub struct PluginComponent {
     component: PluginWindow,
     param_map: HashMap<SharedString, ParamPtr>,
     latency: Arc<AtomicU32>,
+    quality_tier: Arc<AtomicU8>,
     gui_context: Arc<dyn GuiContext>,
 }
 
 impl PluginComponent {
-    fn new(params: Arc<PluginParams>, latency: Arc<AtomicU32>, gui_context: Arc<dyn GuiContext>) -> Self {
+    fn new(params: Arc<PluginParams>, latency: Arc<AtomicU32>, quality_tier: Arc<AtomicU8>, gui_context: Arc<dyn GuiContext>) -> Self {
         let component = PluginWindow::new().unwrap();
         let param_map: HashMap<SharedString, _> = params.param_map().iter()
             .map(|(name, param_ptr, _)| {
@@ -234,6 +272,7 @@ impl PluginComponent {
             component,
             param_map,
             latency,
+            quality_tier,
             gui_context
         }
     }
@@ -324,7 +363,14 @@ impl PluginComponentHandle for PluginComponent {
         &self.param_map
     }
 
-    fn on_event(&self, _event: &Event) -> EventResponse {
+    fn on_event(&self, event: &Event) -> EventResponse {
+        if let Event::Draw = event {
+            // The Auto tier moves without any param changing.
+            let tier: SharedString = QualityTier::variants()[self.quality_tier.load(Ordering::Relaxed) as usize].into();
+            if self.component.get_quality_tier() != tier {
+                self.component.set_quality_tier(tier);
+            }
+        }
         EventResponse::Ignored
     }
 
@@ -362,24 +408,310 @@ impl PluginComponentHandleParameterEvents for PluginComponent {
     }
 }
 
+/// The gain and threshold params smoothed for every sample of the current block. The
+/// channels are processed one after the other, so the smoothers are advanced once per
+/// block instead of once per sample.
+#[derive(Default)]
+struct SmoothedParams {
+    wet_gain: Vec<f32>,
+    dry_gain: Vec<f32>,
+    lhf_gain: Vec<f32>,
+    global_threshold: Vec<f32>,
+    in_key_gain: Vec<f32>,
+    tuning_gain: Vec<f32>,
+    off_key_gain: Vec<f32>,
+    threshold: Vec<f32>,
+    drone_gain: Vec<f32>,
+    /// The wet mix of the [Crossfade].
+    mix: Vec<f32>,
+    /// The mix of the incoming configuration, see [Transition].
+    transition: Vec<f32>,
+}
+
+impl SmoothedParams {
+    fn allocate(&mut self, max_buffer_size: usize) {
+        for block in self.blocks() {
+            block.resize(max_buffer_size, 0.0);
+        }
+    }
+
+    fn blocks(&mut self) -> [&mut Vec<f32>; 11] {
+        [&mut self.wet_gain, &mut self.dry_gain, &mut self.lhf_gain, &mut self.global_threshold, &mut self.in_key_gain, &mut self.tuning_gain, &mut self.off_key_gain, &mut self.threshold, &mut self.drone_gain, &mut self.mix, &mut self.transition]
+    }
+
+    /// `len` is at most the `max_buffer_size` the blocks were allocated for.
+    fn next_block(&mut self, params: &PluginParams, len: usize) {
+        params.global.wet_gain.smoothed.next_block(&mut self.wet_gain, len);
+        params.global.dry_gain.smoothed.next_block(&mut self.dry_gain, len);
+        params.global.lhf_gain.smoothed.next_block(&mut self.lhf_gain, len);
+        params.global.global_threshold.smoothed.next_block(&mut self.global_threshold, len);
+        params.audio_process.in_key_gain.smoothed.next_block(&mut self.in_key_gain, len);
+        params.audio_process.tuning_gain.smoothed.next_block(&mut self.tuning_gain, len);
+        params.audio_process.off_key_gain.smoothed.next_block(&mut self.off_key_gain, len);
+        params.audio_process.threshold.smoothed.next_block(&mut self.threshold, len);
+        params.audio_process.drone_gain.smoothed.next_block(&mut self.drone_gain, len);
+    }
+
+    /// The gain of a band at sample `n`, from the pitch of its note.
+    fn band_gain(&self, params: &PluginParams, note_pitch: i8, n: usize) -> f32 {
+        if note_pitch == 0 {
+            self.in_key_gain[n]
+        } else if note_pitch == -128 || !params.audio_process.pitch_shift.value() {
+            self.off_key_gain[n]
+        } else {
+            self.tuning_gain[n]
+        }
+    }
+}
+
+/// One channel of the block, stored so that the 96 bands can each render the whole block
+/// before it is mixed, on the worker threads or not.
+#[derive(Default)]
+struct ChannelBlock {
+    /// The input between the note offs, which the bands and the spectral engine remap.
+    mid: Vec<f32>,
+    modulator: Vec<f32>,
+    delay: Vec<f32>,
+    /// Both gates let the wet path run.
+    open: Vec<bool>,
+    /// A gate is fading, and the delayed dry signal fades with it.
+    fading: Vec<bool>,
+    gate: Vec<f32>,
+    gate_inv: Vec<f32>,
+    zero: Vec<f32>,
+    /// The output of every band, zero where the gates are closed.
+    bands: Vec<Vec<f32>>,
+}
+
+impl ChannelBlock {
+    fn allocate(&mut self, max_buffer_size: usize) {
+        for block in [&mut self.mid, &mut self.modulator, &mut self.delay, &mut self.gate, &mut self.gate_inv, &mut self.zero] {
+            block.resize(max_buffer_size, 0.0);
+        }
+        self.open.resize(max_buffer_size, false);
+        self.fading.resize(max_buffer_size, false);
+        self.bands.resize(96, Vec::new());
+        for band in self.bands.iter_mut() {
+            band.resize(max_buffer_size, 0.0);
+        }
+    }
+}
+
+/// What the bands read while rendering one channel of the block.
+struct BandInput<'a> {
+    input: &'a [f32],
+    modulator: &'a [f32],
+    open: &'a [bool],
+    smoothed: &'a SmoothedParams,
+    params: &'a PluginParams,
+    buffer_config: &'a BufferConfig,
+    audio_id: usize,
+    notes: RangeInclusive<u8>,
+}
+
+/// Render the bands in range into their outputs, each band on its own for the whole block.
+fn render_bands(bands: &mut [AudioProcess96], outputs: &mut [Vec<f32>], input: &BandInput) {
+    let size = input.input.len();
+    for (ap, output) in bands.iter_mut().zip(outputs.iter_mut()) {
+        if !input.notes.contains(&ap.note) {
+            continue;
+        }
+        for (n, out) in output[..size].iter_mut().enumerate() {
+            *out = if input.open[n] {
+                let input_param: f32 = input.smoothed.band_gain(input.params, ap.note_pitch, n);
+                ap.process(input.input[n], input.modulator[n], input.params, input.audio_id, input_param, input.smoothed.threshold[n], input.smoothed.drone_gain[n], input.buffer_config, size)
+            } else {
+                0.0
+            };
+        }
+    }
+}
+
+/// What a configuration reads and shares while rendering one channel of the block.
+struct ChannelRender<'a> {
+    input: &'a [f32],
+    audio_id: usize,
+    block: &'a mut ChannelBlock,
+    delay: &'a Delay,
+    lhf: &'a Delay,
+    spectral: &'a mut SpectralRemap,
+    pool: Option<&'a WorkerPool>,
+    smoothed: &'a SmoothedParams,
+    params: &'a PluginParams,
+    buffer_config: &'a BufferConfig,
+    /// The wet path runs, otherwise the output is the dry signal at the latency.
+    wet: bool,
+}
+
+/// Render one channel of `configuration` into `output`, from the dry signal read at the
+/// latency of the configuration.
+fn render_channel(configuration: &mut Configuration, render: &mut ChannelRender, output: &mut [f32]) {
+    let size = render.input.len();
+    let i = render.audio_id;
+    let latency = configuration.latency(render.spectral) as usize;
+    let (params, smoothed) = (render.params, render.smoothed);
+    let block = &mut *render.block;
+    for (n, delay) in block.delay[..size].iter_mut().enumerate() {
+        *delay = render.delay.tap(latency + size - 1 - n, i);
+    }
+    if !render.wet {
+        output.copy_from_slice(&block.delay[..size]);
+        return;
+    }
+    let remap_engine = configuration.layout.remap_engine;
+    let node = configuration.layout.quality.node;
+    let mut pitch: [f32; 12] = [0.0; 12];
+    let mut audio_process: f32 = 0.0;
+    let low_note = (params.global.low_note_off.value() as usize - 36) as u8;
+    let high_note = (params.global.high_note_off.value() as usize - 36) as u8;
+    // The 96 bands render the whole block before the mix, the other paths run per sample.
+    if remap_engine == RemapEngine::Bands && node == PitchShiftNode::Node96 {
+        let input = BandInput {
+            input: &block.mid[..size],
+            modulator: &block.modulator[..size],
+            open: &block.open[..size],
+            smoothed,
+            params,
+            buffer_config: render.buffer_config,
+            audio_id: i,
+            notes: low_note..=high_note,
+        };
+        match render.pool {
+            Some(pool) => {
+                let len = configuration.bands.len();
+                let per_group = len.div_ceil(pool.threads() + 1);
+                let bands = SharedSlice::new(&mut configuration.bands);
+                let outputs = SharedSlice::new(&mut block.bands);
+                pool.run(&|group| {
+                    let range = (group * per_group).min(len)..((group + 1) * per_group).min(len);
+                    // Safety: every group takes its own range of the bands and of their outputs.
+                    let (bands, outputs) = unsafe { (bands.range(range.clone()), outputs.range(range)) };
+                    render_bands(bands, outputs, &input);
+                });
+            }
+            None => render_bands(&mut configuration.bands, &mut block.bands, &input),
+        }
+    }
+    for (n, sample) in output.iter_mut().enumerate() {
+        let mid = block.mid[n];
+        let modulator = block.modulator[n];
+        let delay = block.delay[n];
+        *sample = render.input[n];
+        if block.open[n] {
+            let lhf = render.lhf.tap(latency + size - 1 - n, i);
+            match node {
+                _ if remap_engine == RemapEngine::Spectral => {
+                    audio_process = render.spectral.process(mid, i);
+                }
+                PitchShiftNode::Node12 => {
+                    let mut index = low_note as usize % 12;
+                    configuration.bands.iter_mut().filter(|ap| ap.note >= low_note && ap.note <= high_note).for_each(
+                        |ap| {
+                            if index >= 12 {
+                                index = 0;
+                            }
+                            let input_param: f32 = smoothed.band_gain(params, ap.note_pitch, n);
+                            if ap.tuning.is_some() {
+                                pitch[index] = ap.process(mid, modulator, params, i, input_param, smoothed.threshold[n], smoothed.drone_gain[n], render.buffer_config, size);
+                            }
+                            if input_param > db_to_gain(-60.0) {
+                                audio_process += ap.process_bpf(pitch[index], modulator, i, input_param, smoothed.drone_gain[n], params, size);
+                            }
+                            index += 1;
+                        }
+                    );
+                }
+                PitchShiftNode::Node96 => {
+                    // Summed in band order, whichever thread rendered the band.
+                    for (ap, band) in configuration.bands.iter().zip(block.bands.iter()) {
+                        if ap.note >= low_note && ap.note <= high_note {
+                            audio_process += band[n];
+                        }
+                    }
+                }
+            }
+            *sample = (((audio_process * smoothed.wet_gain[n]) + (delay * smoothed.dry_gain[n]) + (lhf * smoothed.lhf_gain[n])) * block.gate[n]) * block.zero[n];
+            audio_process = 0.0;
+        }
+        if block.fading[n] {
+            *sample = (delay * block.gate_inv[n]) + if block.open[n] { *sample } else { 0.0 };
+        }
+        *sample = delay + (*sample - delay) * smoothed.mix[n];
+    }
+}
+
+/// What [CoPiReMapPlugin::process_block] needs from the host, so that it also runs offline.
+trait Host {
+    fn execute_background(&mut self, task: Task);
+    fn set_latency_samples(&mut self, samples: u32);
+
+    /// Seconds spent on the block started at `start`, as measured for the CPU budget.
+    fn process_time(&self, start: Instant) -> f32 {
+        start.elapsed().as_secs_f32()
+    }
+}
+
+struct ProcessHost<'a, C>(&'a mut C);
+
+impl<C: ProcessContext<CoPiReMapPlugin>> Host for ProcessHost<'_, C> {
+    fn execute_background(&mut self, task: Task) {
+        self.0.execute_background(task);
+    }
+
+    fn set_latency_samples(&mut self, samples: u32) {
+        self.0.set_latency_samples(samples);
+    }
+}
+
 pub struct CoPiReMapPlugin {
     params: Arc<PluginParams>,
     buffer_config: BufferConfig,
+    smoothed: SmoothedParams,
+    block: ChannelBlock,
+    /// The output of the current and of the incoming configuration, for one channel.
+    outputs: [Vec<f32>; 2],
+    /// Renders groups of bands in parallel, only while the bands are multithreaded.
+    pool: Option<WorkerPool>,
+    /// Pools spawned by the background thread.
+    built_pools: Arc<ArrayQueue<WorkerPool>>,
+    /// The bands are multithreaded, and a pool was asked for.
+    band_threads: bool,
+    /// The workers of the pool asked for are pinned to their cores.
+    pin_band_threads: bool,
+    crossfade: Crossfade,
+    /// The configuration being rendered.
+    configuration: Configuration,
+    /// A configuration of another layout, rendered next to the current one while the
+    /// [Transition] crossfades to it.
+    incoming: Option<Configuration>,
+    transition: Transition,
+    /// The layout of the last configuration asked for.
+    requested_layout: Layout,
+    cpu_budget: CpuBudget,
+    /// The tier of the configuration being rendered, shown by the GUI.
+    quality_tier: Arc<AtomicU8>,
     midi_note: MidiNote,
-    audio_process96: Vec<AudioProcess96>,
-    lpf: MyFilter,
-    hpf: MyFilter,
+    chroma: Chroma,
+    spectral: SpectralRemap,
+    /// The dry signal, read by every configuration at its own latency.
     delay: Delay,
+    /// Splits the input at the note offs. The bands remap the middle band, the low and high
+    /// bands are mixed back in.
+    split: MySplit<Precision>,
+    /// The low and high bands of the split, read like [CoPiReMapPlugin::delay].
+    lhf: Delay,
     gate: MyGate,
     zero: MyGate,
+    /// The wet path did not run since the output became dry, its state is outdated.
+    stale: bool,
+    /// Samples left before the wet path, reset after being stale, is faded in.
+    resume: usize,
     update_lowpass: Arc<AtomicBool>,
     update_highpass: Arc<AtomicBool>,
+    update_crossover: Arc<AtomicBool>,
 
     update_pitch_shift_and_after_bandpass: Arc<AtomicBool>,
-    update_pitch_shift_over_sampling: Arc<AtomicBool>,
-    update_pitch_shift_window_duration_ms: Arc<AtomicBool>,
     update_bpf_center_hz: Arc<AtomicBool>,
-    set_pitch_shift_12_node: Arc<AtomicBool>,
 
     update_key_note: Arc<AtomicBool>,
     update_key_note_12: Arc<AtomicBool>,
@@ -388,68 +720,456 @@ pub struct CoPiReMapPlugin {
 
     latency: Arc<AtomicU32>,
     user_scale: Arc<AtomicF64>,
+
+    /// Configurations built by the background thread, with the generation of their request.
+    built_configurations: Arc<ArrayQueue<(u32, Configuration)>>,
+    configuration_generation: u32,
+    /// The design generation when the last configuration was asked for, its bands are
+    /// designed again if the band params changed since.
+    configuration_designs: u32,
+
+    /// Band filters designed by the background thread, with the generation of their request.
+    built_designs: Arc<ArrayQueue<(u32, BandPhase, BandDesigns)>>,
+    design_generation: u32,
+    /// The note events of the block changed the note table.
+    redesign_bands: bool,
 }
 
 impl Default for CoPiReMapPlugin {
     fn default() -> Self {
         let update_lowpass = Arc::new(AtomicBool::new(false));
         let update_highpass = Arc::new(AtomicBool::new(false));
+        let update_crossover = Arc::new(AtomicBool::new(false));
 
         let update_pitch_shift_and_after_bandpass = Arc::new(AtomicBool::new(false));
-        let update_pitch_shift_over_sampling = Arc::new(AtomicBool::new(false));
-        let update_pitch_shift_window_duration_ms = Arc::new(AtomicBool::new(false));
         let update_bpf_center_hz = Arc::new(AtomicBool::new(false));
-        let set_pitch_shift_12_node = Arc::new(AtomicBool::new(false));
 
         let update_key_note = Arc::new(AtomicBool::new(false));
         let update_key_note_12 = Arc::new(AtomicBool::new(false));
 
         let update_gui_scale = Arc::new(AtomicBool::new(false));
 
-        let mut audio_process96 = Vec::with_capacity(96);
-        for _ in 0..96 {
-            audio_process96.push(AudioProcess96::default());
-        };
-
         let latency = Arc::new(AtomicU32::new(0));
 
+        let params = Arc::new(PluginParams {
+            global: Arc::new(GlobalParams::new(update_lowpass.clone(), update_highpass.clone(), update_crossover.clone(), update_bpf_center_hz.clone(), update_pitch_shift_and_after_bandpass.clone(),  update_gui_scale.clone())),
+            audio_process: Arc::new(AudioProcessParams::new(update_pitch_shift_and_after_bandpass.clone(), update_bpf_center_hz.clone())),
+            key_note: Arc::new(KeyNoteParams::new(update_key_note.clone(), update_key_note_12.clone())),
+        });
+        let layout = Layout::new(Quality::new(QualityTier::Manual, &params), &params);
+
         Self {
-            params: Arc::new(PluginParams {
-                global: Arc::new(GlobalParams::new(update_lowpass.clone(), update_highpass.clone(), update_bpf_center_hz.clone(), update_pitch_shift_and_after_bandpass.clone(),  update_gui_scale.clone())),
-                audio_process: Arc::new(AudioProcessParams::new(update_pitch_shift_over_sampling.clone(), update_pitch_shift_window_duration_ms.clone(), update_pitch_shift_and_after_bandpass.clone(), update_bpf_center_hz.clone(), set_pitch_shift_12_node.clone())),
-                key_note: Arc::new(KeyNoteParams::new(update_key_note.clone(), update_key_note_12.clone())),
-            }),
+            params,
             buffer_config: BufferConfig {
                 sample_rate: 1.0,
                 min_buffer_size: None,
                 max_buffer_size: 0,
                 process_mode: ProcessMode::Realtime,
             },
+            smoothed: SmoothedParams::default(),
+            block: ChannelBlock::default(),
+            outputs: [Vec::new(), Vec::new()],
+            pool: None,
+            built_pools: Arc::new(ArrayQueue::new(1)),
+            band_threads: false,
+            pin_band_threads: false,
+            crossfade: Crossfade::default(),
+            configuration: Configuration::new(layout),
+            incoming: None,
+            transition: Transition::default(),
+            requested_layout: layout,
+            cpu_budget: CpuBudget::default(),
+            quality_tier: Arc::new(AtomicU8::new(QualityTier::Manual.to_index() as u8)),
             midi_note: MidiNote::default(),
-            audio_process96,
-            lpf: MyFilter::default(),
-            hpf: MyFilter::default(),
+            chroma: Chroma::default(),
+            spectral: SpectralRemap::default(),
             delay: Delay::default(),
+            split: MySplit::default(),
+            lhf: Delay::default(),
             gate: MyGate::new(),
             zero: MyGate::new(),
+            stale: false,
+            resume: 0,
             update_lowpass,
             update_highpass,
+            update_crossover,
             update_pitch_shift_and_after_bandpass,
-            update_pitch_shift_over_sampling,
-            update_pitch_shift_window_duration_ms,
             update_bpf_center_hz,
-            set_pitch_shift_12_node,
             update_key_note,
             update_key_note_12,
             update_gui_scale,
             latency,
-            user_scale: Arc::new(AtomicF64::new(1.0))
+            user_scale: Arc::new(AtomicF64::new(1.0)),
+            built_configurations: Arc::new(ArrayQueue::new(1)),
+            configuration_generation: 0,
+            configuration_designs: 0,
+            built_designs: Arc::new(ArrayQueue::new(1)),
+            design_generation: 0,
+            redesign_bands: false,
+        }
+    }
+}
+
+impl CoPiReMapPlugin {
+    /// Ask the background thread for the configuration of `layout`, built from the current
+    /// note table and params.
+    fn request_configuration(&mut self, host: &mut impl Host, layout: Layout) {
+        self.configuration_generation = self.configuration_generation.wrapping_add(1);
+        self.configuration_designs = self.design_generation;
+        self.requested_layout = layout;
+        host.execute_background(Task::BuildConfiguration(ConfigurationRequest {
+            generation: self.configuration_generation,
+            layout,
+            buffer_config: self.buffer_config,
+            note_table: self.midi_note.note_table(&self.params),
+        }));
+    }
+
+    /// Ask the background thread to design the band filters for the current note table and
+    /// params. They are swapped in without a fade, the filters keep their state.
+    fn request_band_designs(&mut self, host: &mut impl Host) {
+        self.design_generation = self.design_generation.wrapping_add(1);
+        host.execute_background(Task::DesignBands(DesignRequest {
+            generation: self.design_generation,
+            sample_rate: self.buffer_config.sample_rate,
+            band_phase: self.newest_configuration().layout.band_phase,
+            note_table: self.midi_note.note_table(&self.params),
+        }));
+    }
+
+    /// The incoming configuration, or the current one if there is none.
+    fn newest_configuration(&mut self) -> &mut Configuration {
+        self.incoming.as_mut().unwrap_or(&mut self.configuration)
+    }
+
+    /// The current configuration and the incoming one, if any.
+    fn configurations(&mut self) -> impl Iterator<Item = &mut Configuration> {
+        std::iter::once(&mut self.configuration).chain(self.incoming.as_mut())
+    }
+
+    /// Take over a built configuration, crossfading to it from the current one.
+    fn start_transition(&mut self, configuration: Configuration, render_wet: bool, host: &mut impl Host) {
+        if configuration.layout.remap_engine == RemapEngine::Spectral {
+            if self.configuration.layout.remap_engine == RemapEngine::Spectral {
+                // Both would render the one spectral engine, which does not depend on the layout.
+                self.replace_configuration(configuration, host);
+                return;
+            }
+            self.spectral.reset();
+        }
+        // Muted until its output is wet, unless the output is dry anyway.
+        let warm_up = if render_wet { configuration.latency(&self.spectral) as usize } else { 0 };
+        self.transition.start(warm_up);
+        self.incoming = Some(configuration);
+        if self.configuration_designs != self.design_generation {
+            self.request_band_designs(host);
+        }
+    }
+
+    /// Make `configuration` the current one, and report its latency.
+    fn replace_configuration(&mut self, configuration: Configuration, host: &mut impl Host) {
+        let outgoing = std::mem::replace(&mut self.configuration, configuration);
+        host.execute_background(Task::RetireConfiguration(outgoing));
+        self.quality_tier.store(self.configuration.layout.quality.tier.to_index() as u8, Ordering::Relaxed);
+        let latency = self.configuration.latency(&self.spectral);
+        self.latency.set(latency);
+        host.set_latency_samples(latency);
+    }
+
+    /// The split of the dry signal for the note offs.
+    fn split_frequencies(&self) -> (f32, f32) {
+        let mut lowpass: f32 = 0.0;
+        hz_cal_clh((self.params.global.low_note_off.value() - 36) as u8, 0, &mut lowpass, self.params.global.hz_tuning.value(), !self.params.audio_process.pitch_shift.value());
+        let mut highpass: f32 = 0.0;
+        hz_cal_clh((self.params.global.high_note_off.value() - 36) as u8, 0, &mut highpass, self.params.global.hz_tuning.value(), !self.params.audio_process.pitch_shift.value());
+        (lowpass, highpass)
+    }
+
+    /// Everything [Plugin::initialize] does, without the host.
+    fn setup(&mut self, buffer_config: &BufferConfig) {
+        self.buffer_config = *buffer_config;
+        let max_buffer_size = buffer_config.max_buffer_size as usize;
+        self.smoothed.allocate(max_buffer_size);
+        self.block.allocate(max_buffer_size);
+        for output in self.outputs.iter_mut() {
+            output.resize(max_buffer_size, 0.0);
+        }
+        self.band_threads = self.params.audio_process.band_threads.value();
+        self.pin_band_threads = self.params.audio_process.pin_band_threads.value();
+        self.pool = self.band_threads.then(|| WorkerPool::new(available_workers(), self.pin_band_threads));
+        self.crossfade.set_sample_rate(buffer_config.sample_rate);
+        self.crossfade.set_wet(!self.params.global.bypass.value());
+        self.crossfade.reset();
+        self.transition.set_sample_rate(buffer_config.sample_rate);
+        self.stale = false;
+        self.resume = 0;
+        self.chroma = Chroma::new(self.buffer_config.sample_rate, self.params.global.hz_center.value());
+        self.spectral = SpectralRemap::new(self.buffer_config.sample_rate, self.params.global.hz_center.value());
+        // The configurations read a whole block behind their latency.
+        let max_delay = max_latency(buffer_config.sample_rate, &self.spectral) + max_buffer_size;
+        self.delay.set_max_delay(max_delay);
+        self.lhf.set_max_delay(max_delay);
+        self.midi_note.param_update(self.params.clone());
+        // The configuration is built here, answers to older requests are stale.
+        self.configuration_generation = self.configuration_generation.wrapping_add(1);
+        self.design_generation = self.design_generation.wrapping_add(1);
+        self.configuration_designs = self.design_generation;
+        self.requested_layout = self.target_layout();
+        self.incoming = None;
+        self.configuration = Configuration::build(self.requested_layout, self.params.clone(), buffer_config, self.midi_note.note_table(&self.params));
+        let (lowpass, highpass) = self.split_frequencies();
+        self.split.set(self.params.global.crossover.value().into(), Precision::from_host(lowpass), Precision::from_host(highpass), Precision::from_host(self.buffer_config.sample_rate));
+        self.quality_tier.store(self.requested_layout.quality.tier.to_index() as u8, Ordering::Relaxed);
+        self.latency.set(self.configuration.latency(&self.spectral));
+    }
+
+    /// Render one block of at most `max_buffer_size` samples in place. The note events of
+    /// the block are already handled.
+    fn process_block(&mut self, channels: &mut [&mut [f32]], sidechain: Option<&[&mut [f32]]>, host: &mut impl Host) {
+        let samples = channels.first().map_or(0, |channel| channel.len());
+        let start = Instant::now();
+        let bypass = self.params.global.bypass.value();
+        if self
+            .update_gui_scale
+            .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            self.user_scale.store(self.params.global.scale_gui.value() as f64, Ordering::Release);
+
+        }
+        if self
+            .update_lowpass
+            .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            let (lowpass, _) = self.split_frequencies();
+            self.split.set_low_frequency(Precision::from_host(lowpass));
+        }
+        if self
+            .update_crossover
+            .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            self.split.set_crossover(self.params.global.crossover.value().into());
+        }
+        if self
+            .update_highpass
+            .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            let (_, highpass) = self.split_frequencies();
+            self.split.set_high_frequency(Precision::from_host(highpass));
+        }
+        // The bands are redesigned once, after all the changes of this block.
+        let mut redesign_bands = std::mem::take(&mut self.redesign_bands);
+        if self
+            .update_pitch_shift_and_after_bandpass
+            .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            redesign_bands = true;
+        }
+        if self
+            .update_bpf_center_hz
+            .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok() {
+            self.chroma.set_hz_center(self.buffer_config.sample_rate, self.params.global.hz_center.value());
+            self.spectral.set_hz_center(self.buffer_config.sample_rate, self.params.global.hz_center.value());
+            redesign_bands = true;
+        }
+        // A new scale moves the pitch of many bands at once, it is crossfaded like a new layout.
+        let mut rebuild = false;
+        if self
+            .update_key_note
+            .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            self.midi_note.param_update(self.params.clone());
+            rebuild = true;
+        }
+        if self
+            .update_key_note_12
+            .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            self.midi_note.update(self.params.clone());
+            redesign_bands = true;
+        }
+        if self.params.key_note.note_mode_midi.value() == NoteModeMidi::SidechainScale {
+            if let Some(channels) = sidechain {
+                let mut changed = false;
+                for s in 0..samples {
+                    let mono = channels.iter().map(|channel| channel[s]).sum::<f32>() / channels.len() as f32;
+                    changed |= self.chroma.process(mono, self.params.key_note.sidechain_threshold.smoothed.next());
+                }
+                if changed {
+                    self.midi_note.sidechain_note = self.chroma.note_keys;
+                    self.midi_note.param_update(self.params.clone());
+                    redesign_bands = true;
+                }
+            }
+        }
+        // The worker threads only exist while the bands are multithreaded, and are spawned
+        // again to pin or unpin them.
+        let band_threads = self.params.audio_process.band_threads.value();
+        let pin_band_threads = self.params.audio_process.pin_band_threads.value();
+        if (self.band_threads, self.pin_band_threads) != (band_threads, pin_band_threads) {
+            self.band_threads = band_threads;
+            self.pin_band_threads = pin_band_threads;
+            if let Some(pool) = self.pool.take() {
+                host.execute_background(Task::RetirePool(pool));
+            }
+            if band_threads {
+                host.execute_background(Task::BuildPool(available_workers(), pin_band_threads));
+            }
+        }
+        if let Some(pool) = self.built_pools.pop() {
+            if self.band_threads && self.pool.is_none() && pool.pinned() == self.pin_band_threads {
+                self.pool = Some(pool);
+            } else {
+                host.execute_background(Task::RetirePool(pool));
+            }
+        }
+        let layout = self.target_layout();
+        if rebuild || layout != self.requested_layout {
+            self.request_configuration(host, layout);
+        }
+        if redesign_bands {
+            self.request_band_designs(host);
+        }
+
+        // The wet path does not run while the output is dry. Before it is faded in again its
+        // state is cleared and it runs muted for its latency, so that it resumes from the
+        // current input instead of from where it stopped.
+        if !bypass && self.stale {
+            self.spectral.reset();
+            let spectral = &self.spectral;
+            let mut resume = 0;
+            for configuration in std::iter::once(&mut self.configuration).chain(self.incoming.as_mut()) {
+                configuration.reset();
+                resume = resume.max(configuration.latency(spectral) as usize);
+            }
+            self.resume = resume;
+            self.stale = false;
+        }
+        self.crossfade.set_wet(!bypass && self.resume == 0);
+        let render_wet = !self.crossfade.is_dry() || self.resume > 0;
+        self.stale |= !render_wet;
+        self.resume = self.resume.saturating_sub(samples);
+
+        // A new configuration is taken once the previous transition is over.
+        if self.incoming.is_none() {
+            if let Some((generation, configuration)) = self.built_configurations.pop() {
+                if generation == self.configuration_generation {
+                    self.start_transition(configuration, render_wet, host);
+                } else {
+                    host.execute_background(Task::RetireConfiguration(configuration));
+                }
+            }
+        }
+        if let Some((generation, band_phase, mut designs)) = self.built_designs.pop() {
+            let (sample_rate, current) = (self.buffer_config.sample_rate, generation == self.design_generation);
+            let configuration = self.newest_configuration();
+            if current && configuration.layout.band_phase == band_phase {
+                for (ap, design) in configuration.bands.iter_mut().zip(designs.iter_mut()) {
+                    ap.set_design(design, sample_rate);
+                }
+            }
+            host.execute_background(Task::RetireDesigns(designs));
+        }
+
+        // The smoothers keep moving while dry, so that the wet path resumes at their targets.
+        self.smoothed.next_block(&self.params, samples);
+        self.crossfade.next_block(&mut self.smoothed.mix, samples);
+        if self.incoming.is_some() {
+            self.transition.next_block(&mut self.smoothed.transition, samples);
+        }
+        if render_wet && std::iter::once(&self.configuration).chain(self.incoming.as_ref()).any(|configuration| configuration.layout.remap_engine == RemapEngine::Spectral) {
+            self.spectral.update(self.params.clone(), self.midi_note.note_table(&self.params));
+        }
+        let flip = self.params.global.global_threshold_flip.value();
+        // A transition renders two configurations, which says nothing about the cost of either,
+        // and offline blocks say nothing about the real time budget.
+        let measure = render_wet && self.incoming.is_none() && self.buffer_config.process_mode != ProcessMode::Offline;
+        let CoPiReMapPlugin { params, buffer_config, smoothed, block, outputs, pool, configuration, incoming, spectral, delay, split, lhf, gate, zero, .. } = self;
+        let [output, incoming_output] = outputs;
+        let low_mute = params.global.low_note_off_mute.value();
+        let high_mute = params.global.high_note_off_mute.value();
+        for (i, channel) in channels.iter_mut().enumerate() {
+            let size = channel.len();
+            // The gates, the dry signal and the split are shared by the configurations, and run
+            // even while the output is dry.
+            for (n, sample) in channel.iter().enumerate() {
+                block.modulator[n] = match sidechain {
+                    Some(sidechain) if i < sidechain.len() => sidechain[i][n],
+                    _ => 0.0,
+                };
+                let gate_zero = zero.update_fast_param(*sample, buffer_config, db_to_gain(-99.0), 0.1, 0.1, size,false, i);
+                let gate_on: (bool, bool) = gate.update_fast_param(*sample, buffer_config, smoothed.global_threshold[n], params.global.global_threshold_attack.value(), params.global.global_threshold_release.value(), size, flip, i);
+                delay.write(*sample, i);
+                let [low, mid, high] = split.process(Precision::from_host(*sample), i).map(Sample::to_host);
+                block.mid[n] = mid;
+                lhf.write(if low_mute { 0.0 } else { low } + if high_mute { 0.0 } else { high }, i);
+                block.open[n] = gate_on.0 && gate_zero.0;
+                block.fading[n] = gate_on.1 || gate_zero.1;
+                block.gate[n] = gate.get_param(flip, i);
+                block.gate_inv[n] = gate.get_param_inv(flip, i);
+                block.zero[n] = zero.get_param(false, i);
+            }
+            let mut render = ChannelRender {
+                input: channel,
+                audio_id: i,
+                block: &mut *block,
+                delay,
+                lhf,
+                spectral: &mut *spectral,
+                pool: pool.as_ref(),
+                smoothed,
+                params,
+                buffer_config,
+                wet: render_wet,
+            };
+            render_channel(configuration, &mut render, &mut output[..size]);
+            match incoming.as_mut() {
+                Some(incoming) => {
+                    render_channel(incoming, &mut render, &mut incoming_output[..size]);
+                    for (n, sample) in channel.iter_mut().enumerate() {
+                        *sample = output[n] + (incoming_output[n] - output[n]) * smoothed.transition[n];
+                    }
+                }
+                None => channel.copy_from_slice(&output[..size]),
+            }
         }
+        if self.incoming.is_some() && self.transition.is_done() {
+            if let Some(incoming) = self.incoming.take() {
+                self.replace_configuration(incoming, host);
+            }
+        }
+        // Only the blocks running the wet path of one configuration tell what the tier costs.
+        if measure && self.params.audio_process.quality_tier.value() == QualityTier::Auto {
+            self.cpu_budget.update(host.process_time(start), samples as f32 / self.buffer_config.sample_rate, self.params.audio_process.cpu_budget.value());
+        }
+    }
+
+    /// The layout the params ask for, with the quality of [CoPiReMapPlugin::target_quality].
+    fn target_layout(&self) -> Layout {
+        Layout::new(self.target_quality(), &self.params)
+    }
+
+    /// The quality the tier param asks for, in Auto mode the tier within the CPU budget.
+    fn target_quality(&self) -> Quality {
+        let tier = match self.params.audio_process.quality_tier.value() {
+            // Rendering offline has all the time it needs.
+            QualityTier::Auto if self.buffer_config.process_mode == ProcessMode::Offline => QualityTier::High,
+            QualityTier::Auto => self.cpu_budget.tier(),
+            tier => tier,
+        };
+        Quality::new(tier, &self.params)
     }
 }
 
 impl Plugin for CoPiReMapPlugin {
-    type BackgroundTask = ();
+    type BackgroundTask = Task;
     type SysExMessage = ();
 
     const NAME: &'static str = "CoPiReMap";
@@ -467,7 +1187,21 @@ impl Plugin for CoPiReMapPlugin {
             main_output_channels: NonZeroU32::new(2),
 
             ..AudioIOLayout::const_default()
-        }
+        },
+        // After the plain stereo layout, which stays the default of the hosts.
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(2),
+
+            aux_input_ports: &[new_nonzero_u32(2)],
+
+            names: PortNames {
+                aux_inputs: &["Sidechain"],
+                ..PortNames::const_default()
+            },
+
+            ..AudioIOLayout::const_default()
+        },
     ];
 
     const SAMPLE_ACCURATE_AUTOMATION: bool = true;
@@ -476,31 +1210,48 @@ impl Plugin for CoPiReMapPlugin {
         self.params.clone()
     }
 
+    fn task_executor(&mut self) -> TaskExecutor<Self> {
+        let built_configurations = self.built_configurations.clone();
+        let built_designs = self.built_designs.clone();
+        let built_pools = self.built_pools.clone();
+        let params = self.params.clone();
+        Box::new(move |task| match task {
+            Task::BuildConfiguration(request) => {
+                // An answer the audio thread has not taken yet is outdated, it is freed here.
+                built_configurations.force_push((request.generation, request.build(params.clone())));
+            }
+            Task::DesignBands(request) => {
+                built_designs.force_push((request.generation, request.band_phase, request.build(params.clone())));
+            }
+            Task::BuildPool(threads, pinned) => {
+                built_pools.force_push(WorkerPool::new(threads, pinned));
+            }
+            // Dropping a pool joins its threads.
+            Task::RetireConfiguration(_) | Task::RetireDesigns(_) | Task::RetirePool(_) => {}
+        })
+    }
+
     fn initialize(
         &mut self,
         _audio_io_layout: &AudioIOLayout,
         buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>
+        context: &mut impl InitContext<Self>
     ) -> bool
     {
-        self.buffer_config = *buffer_config;
-        let mut lowpass: f32 = 0.0;
-        hz_cal_clh((self.params.global.low_note_off.value() - 36) as u8, 0, &mut lowpass, self.params.global.hz_tuning.value(), !self.params.audio_process.pitch_shift.value());
-        self.lpf.set(Curve::Lowpass, lowpass, 1.0, 0.0, self.buffer_config.sample_rate);
-        let mut highpass: f32 = 0.0;
-        hz_cal_clh((self.params.global.high_note_off.value() - 36) as u8, 0, &mut highpass, self.params.global.hz_tuning.value(), !self.params.audio_process.pitch_shift.value());
-        self.hpf.set(Curve::Highpass, highpass, 1.0, 0.0, self.buffer_config.sample_rate);
-        for (i, audio_process) in self.audio_process96.iter_mut().enumerate() {
-            audio_process.setup(self.params.clone(), i as u8, &self.buffer_config, &self.midi_note);
-        }
-        
-        self.midi_note.param_update(self.params.clone(), &mut self.audio_process96, &self.buffer_config);
+        self.setup(buffer_config);
+        context.set_latency_samples(self.latency.load(Ordering::SeqCst));
         true
     }
 
     fn reset(&mut self) {
-        for ap in self.audio_process96.iter_mut() {
-            ap.reset();
+        self.crossfade.reset();
+        self.chroma.reset();
+        self.spectral.reset();
+        self.delay.reset();
+        self.split.reset();
+        self.lhf.reset();
+        for configuration in self.configurations() {
+            configuration.reset();
         }
     }
 
@@ -514,8 +1265,9 @@ impl Plugin for CoPiReMapPlugin {
             {
                 let params = self.params.clone();
                 let latency = self.latency.clone();
+                let quality_tier = self.quality_tier.clone();
                 move |_window, gui_context| {
-                    PluginComponent::new(params.clone(), latency.clone(), gui_context.clone())
+                    PluginComponent::new(params.clone(), latency.clone(), quality_tier.clone(), gui_context.clone())
                 }
             },
         );
@@ -525,203 +1277,49 @@ impl Plugin for CoPiReMapPlugin {
     fn process(
         &mut self,
         buffer: &mut Buffer<'_>,
-        _aux: &mut AuxiliaryBuffers<'_>,
+        aux: &mut AuxiliaryBuffers<'_>,
         context: &mut impl ProcessContext<Self>
     ) -> ProcessStatus
     {
-        match self.params.global.bypass.value() {
-            true => {
-                if self.delay.get_latency() != 0 {
-                    self.delay.set_delay(0);
-                    context.set_latency_samples(0);
-                }
-            }
-            false => {
-                let latency = latency_average96(&self.audio_process96);
-                if self.delay.get_latency() != latency {
-                    self.delay.set_delay(latency);
-                    for ap in self.audio_process96.iter_mut() {
-                        ap.set_delay(latency)
-                    }
-                    self.latency.set(latency);
-                    context.set_latency_samples(latency);
-                }
-                if self
-                    .update_gui_scale
-                    .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
-                    .is_ok()
-                {
-                    self.user_scale.store(self.params.global.scale_gui.value() as f64, Ordering::Release);
-
-                }
-                if self
-                    .update_lowpass
-                    .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
-                    .is_ok()
-                {
-                    let mut lowpass: f32 = 0.0;
-                    let low_note = self.params.global.low_note_off.value() as usize - 36;
-                    hz_cal_clh(low_note as u8, 0, &mut lowpass, self.params.global.hz_tuning.value(), !self.params.audio_process.pitch_shift.value());
-                    self.lpf.set_frequency(lowpass);
-                    self.audio_process96.iter_mut().for_each(
-                        |ap| {
-                            ap.set_pitch_shift_12_node(self.params.clone(), &self.buffer_config, &self.midi_note);
+        while let Some(event) = context.next_event() {
+            match event {
+                NoteEvent::NoteOn {
+                    timing: _timing,
+                    voice_id: _voice_id,
+                    channel: _channel,
+                    note,
+                    velocity: _velocity,
+                } => if note >= 24 || note <= 119 {
+                    self.midi_note.midi_note[note as usize - 12] = true;
+                    match self.params.key_note.note_mode_midi.value() {
+                        NoteModeMidi::MidiWhistle | NoteModeMidi::MidiScale => {
+                            self.midi_note.param_update(self.params.clone());
+                            self.redesign_bands = true;
                         }
-                    );
-                }
-                if self
-                    .update_highpass
-                    .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
-                    .is_ok()
-                {
-                    let mut highpass: f32 = 0.0;
-                    hz_cal_clh((self.params.global.high_note_off.value() - 36) as u8, 0, &mut highpass, self.params.global.hz_tuning.value(), !self.params.audio_process.pitch_shift.value());
-                    self.hpf.set_frequency(highpass);
-                }
-                if self
-                    .update_pitch_shift_and_after_bandpass
-                    .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
-                    .is_ok()
-                {
-                    let note_table: [i8; 96] = match self.params.key_note.note_mode_midi.value() {
-                        NoteModeMidi::MidiWhistle | NoteModeMidi::MidiScale => self.midi_note.im2t,
-                        _ => self.midi_note.i2t
-                    };
-                    self.update_bpf_center_hz.set(false);
-                    AudioProcess96::fn_update_pitch_shift_and_after_bandpass(self.params.clone(), &mut self.audio_process96, &self.buffer_config, note_table);
-                }
-                if self
-                    .update_bpf_center_hz
-                    .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
-                    .is_ok() {
-                    for ap in self.audio_process96.iter_mut() {
-                        ap.set_bpf_center_hz(self.params.clone(), &self.buffer_config, &self.midi_note);
-                    }
-                }
-                if self
-                    .update_pitch_shift_over_sampling
-                    .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
-                    .is_ok()
-                {
-                    for ap in self.audio_process96.iter_mut() {
-                        ap.set_pitch_shift_over_sampling(self.params.clone());
-                    }
-                }
-                if self
-                    .update_pitch_shift_window_duration_ms
-                    .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
-                    .is_ok()
-                {
-                    for ap in self.audio_process96.iter_mut() {
-                        ap.set_pitch_shift_window_duration_ms(self.params.clone(), &self.buffer_config);
-                    }
-                }
-                if self
-                    .set_pitch_shift_12_node
-                    .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
-                    .is_ok()
-                {
-                    for ap in self.audio_process96.iter_mut() {
-                        ap.set_pitch_shift_12_node(self.params.clone(), &self.buffer_config, &self.midi_note);
-                    }
-                }
-                if self
-                    .update_key_note
-                    .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
-                    .is_ok()
-                {
-                    self.midi_note.param_update(self.params.clone(), &mut self.audio_process96, &self.buffer_config);
-                }
-                if self
-                    .update_key_note_12
-                    .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
-                    .is_ok()
-                {
-                    self.midi_note.update(self.params.clone(), &mut self.audio_process96, &self.buffer_config);
-                }
-                while let Some(event) = context.next_event() {
-                    match event {
-                        NoteEvent::NoteOn {
-                            timing: _timing,
-                            voice_id: _voice_id,
-                            channel: _channel,
-                            note,
-                            velocity: _velocity,
-                        } => if note >= 24 || note <= 119 {
-                            self.midi_note.midi_note[note as usize - 12] = true;
-                            match self.params.key_note.note_mode_midi.value() {
-                                NoteModeMidi::MidiWhistle | NoteModeMidi::MidiScale => self.midi_note.param_update(self.params.clone(), &mut self.audio_process96, &self.buffer_config),
-                                _ => {}
-                            }
-                        },
-                        NoteEvent::NoteOff {
-                            timing: _timing,
-                            voice_id: _voice_id,
-                            channel: _channel,
-                            note,
-                            velocity: _velocity,
-                        } => if note >= 24 || note <= 119 {
-                            self.midi_note.midi_note[note as usize - 12] = false;
-                            match self.params.key_note.note_mode_midi.value() {
-                                NoteModeMidi::MidiWhistle | NoteModeMidi::MidiScale => self.midi_note.param_update(self.params.clone(), &mut self.audio_process96, &self.buffer_config),
-                                _ => {}
-                            }
-                        },
-                        _ => (),
+                        _ => {}
                     }
-                }
-                let mut pitch: [f32; 12] = [0.0; 12];
-                let mut audio_process: f32 = 0.0;
-                for (i, channel) in buffer.as_slice().iter_mut().enumerate() {
-                    let size = channel.len();
-                    for sample in channel.iter_mut() {
-                        let flip = self.params.global.global_threshold_flip.value();
-                        let gate_zero = self.zero.update_fast_param(*sample, &self.buffer_config, db_to_gain(-99.0), 0.1, 0.1, size,false, i);
-                        let gate_on: (bool, bool) = self.gate.update_fast_param(*sample, &self.buffer_config, self.params.global.global_threshold.value(), self.params.global.global_threshold_attack.value(), self.params.global.global_threshold_release.value(), size, flip, i);
-                        let delay = self.delay.process(*sample, i);
-                        if gate_on.0 && gate_zero.0 {
-                            let lpf_mute = match self.params.global.low_note_off_mute.value() { true => 0.0, false => self.lpf.process(delay, i) };
-                            let hpf_mute = match self.params.global.high_note_off_mute.value() { true => 0.0, false => self.hpf.process(delay, i) };
-                            match self.params.audio_process.pitch_shift_node.value() {
-                                PitchShiftNode::Node12 => {
-                                    let low_note = self.params.global.low_note_off.value() as usize - 36;
-                                    let mut index = low_note % 12;
-                                    self.audio_process96.iter_mut().filter(|ap| ap.note >= low_note as u8 && ap.note <= (self.params.global.high_note_off.value() as usize - 36) as u8).for_each(
-                                        |ap| {
-                                            if index >= 12 {
-                                                index = 0;
-                                            }
-                                            let input_param: f32 = if ap.note_pitch == 0 { self.params.audio_process.in_key_gain.value() } else if ap.note_pitch == -128 { self.params.audio_process.off_key_gain.value() } else if !self.params.audio_process.pitch_shift.value() { self.params.audio_process.off_key_gain.value() } else { self.params.audio_process.tuning_gain.value() };
-                                            if ap.tuning.is_some() {
-                                                pitch[index] = ap.process(*sample, self.params.clone(), i, input_param, &self.buffer_config, size);
-                                            }
-                                            if input_param > db_to_gain(-60.0) {
-                                                audio_process += ap.process_bpf(pitch[index], i, input_param, self.params.clone());
-                                                // println!("Work {}, {}", ii, ap.note);
-                                            }
-                                            index += 1;
-                                        }
-                                    );
-                                }
-                                PitchShiftNode::Node96 => {
-                                    self.audio_process96.iter_mut().filter(|ap| ap.note >= (self.params.global.low_note_off.value() as usize - 36) as u8 && ap.note <= (self.params.global.high_note_off.value() as usize - 36) as u8).for_each(
-                                        |ap| {
-                                            let input_param: f32 = if ap.note_pitch == 0 { self.params.audio_process.in_key_gain.value() } else if ap.note_pitch == -128 { self.params.audio_process.off_key_gain.value() } else if !self.params.audio_process.pitch_shift.value() { self.params.audio_process.off_key_gain.value() } else { self.params.audio_process.tuning_gain.value() };
-                                            audio_process += ap.process(*sample, self.params.clone(), i, input_param, &self.buffer_config, size);
-                                        }
-                                    );
-                                }
-                            }
-                            *sample = (((audio_process * self.params.global.wet_gain.value()) + (delay * self.params.global.dry_gain.value()) + ((lpf_mute + hpf_mute) * self.params.global.lhf_gain.value())) * self.gate.get_param(flip, i)) * self.zero.get_param(false, i);
-                            audio_process = 0.0;
-                        }
-                        if gate_on.1 || gate_zero.1 {
-                            *sample = (delay * self.gate.get_param_inv(flip, i)) + if gate_on.0 && gate_zero.0 { *sample } else { 0.0 };
+                },
+                NoteEvent::NoteOff {
+                    timing: _timing,
+                    voice_id: _voice_id,
+                    channel: _channel,
+                    note,
+                    velocity: _velocity,
+                } => if note >= 24 || note <= 119 {
+                    self.midi_note.midi_note[note as usize - 12] = false;
+                    match self.params.key_note.note_mode_midi.value() {
+                        NoteModeMidi::MidiWhistle | NoteModeMidi::MidiScale => {
+                            self.midi_note.param_update(self.params.clone());
+                            self.redesign_bands = true;
                         }
+                        _ => {}
                     }
-                }
+                },
+                _ => (),
             }
         }
+        let sidechain = aux.inputs.first().map(|sidechain| sidechain.as_slice_immutable());
+        self.process_block(buffer.as_slice(), sidechain, &mut ProcessHost(context));
         ProcessStatus::Normal
     }
 }
@@ -746,3 +1344,326 @@ impl Vst3Plugin for CoPiReMapPlugin {
 
 nih_export_clap!(CoPiReMapPlugin);
 nih_export_vst3!(CoPiReMapPlugin);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_process::max_filter_latency;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+    const BLOCK: usize = 256;
+
+    /// Runs the background tasks right away, where the host would run them on its own thread.
+    struct OfflineHost {
+        executor: TaskExecutor<CoPiReMapPlugin>,
+        latency: u32,
+        /// Replaces the measured time of every block.
+        process_time: Option<f32>,
+    }
+
+    impl Host for OfflineHost {
+        fn execute_background(&mut self, task: Task) {
+            (self.executor)(task);
+        }
+
+        fn set_latency_samples(&mut self, samples: u32) {
+            self.latency = samples;
+        }
+
+        fn process_time(&self, start: Instant) -> f32 {
+            self.process_time.unwrap_or_else(|| start.elapsed().as_secs_f32())
+        }
+    }
+
+    /// The params of `plugin` with the C major scale selected.
+    fn c_major(plugin: &CoPiReMapPlugin) -> Arc<PluginParams> {
+        Arc::new(PluginParams {
+            global: plugin.params.global.clone(),
+            audio_process: plugin.params.audio_process.clone(),
+            key_note: Arc::new(KeyNoteParams {
+                note_c: BoolParam::new("Note C", true),
+                note_d: BoolParam::new("Note D", true),
+                note_e: BoolParam::new("Note E", true),
+                note_f: BoolParam::new("Note F", true),
+                note_g: BoolParam::new("Note G", true),
+                note_a: BoolParam::new("Note A", true),
+                note_b: BoolParam::new("Note B", true),
+                ..KeyNoteParams::new(plugin.update_key_note.clone(), plugin.update_key_note_12.clone())
+            }),
+        })
+    }
+
+    /// The params of `plugin` with `audio_process` instead of its own.
+    fn with_audio_process(plugin: &CoPiReMapPlugin, audio_process: AudioProcessParams) -> Arc<PluginParams> {
+        let params = Arc::new(PluginParams {
+            global: plugin.params.global.clone(),
+            audio_process: Arc::new(audio_process),
+            key_note: plugin.params.key_note.clone(),
+        });
+        reset_smoothers(&params);
+        params
+    }
+
+    /// The wrapper resets the smoothers to the values of their params.
+    fn reset_smoothers(params: &PluginParams) {
+        let (global, audio_process) = (&params.global, &params.audio_process);
+        for param in [
+            &global.wet_gain,
+            &global.dry_gain,
+            &global.lhf_gain,
+            &global.global_threshold,
+            &audio_process.in_key_gain,
+            &audio_process.tuning_gain,
+            &audio_process.off_key_gain,
+            &audio_process.threshold,
+            &audio_process.drone_gain,
+            &params.key_note.sidechain_threshold,
+        ] {
+            param.smoothed.reset(param.value());
+        }
+    }
+
+    /// Initialize `plugin` with its current params, as the host does before processing.
+    fn initialize(plugin: &mut CoPiReMapPlugin) -> OfflineHost {
+        reset_smoothers(&plugin.params);
+        plugin.setup(&BufferConfig {
+            sample_rate: SAMPLE_RATE,
+            min_buffer_size: None,
+            max_buffer_size: BLOCK as u32,
+            process_mode: ProcessMode::Realtime,
+        });
+        OfflineHost {
+            executor: plugin.task_executor(),
+            latency: plugin.latency.load(Ordering::SeqCst),
+            process_time: None,
+        }
+    }
+
+    /// A sine wave of `hz` on top of an offset, so that a gain jumps even at a zero crossing.
+    fn sine(hz: f32, len: usize) -> Vec<f32> {
+        (0..len).map(|n| 0.25 + 0.25 * (std::f32::consts::TAU * hz * n as f32 / SAMPLE_RATE).sin()).collect()
+    }
+
+    /// Process `input` on both channels, in blocks as the host would, and return the left channel.
+    fn render(plugin: &mut CoPiReMapPlugin, host: &mut OfflineHost, input: &[f32]) -> Vec<f32> {
+        let mut output = Vec::with_capacity(input.len());
+        for block in input.chunks(BLOCK) {
+            let mut left = block.to_vec();
+            let mut right = block.to_vec();
+            plugin.process_block(&mut [&mut left, &mut right], None, host);
+            output.extend_from_slice(&left);
+        }
+        output
+    }
+
+    /// The largest second difference of `signal`, which a jump in its value or slope raises.
+    fn max_second_difference(signal: &[f32]) -> f32 {
+        signal.windows(3).map(|w| (w[2] - 2.0 * w[1] + w[0]).abs()).fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn step_automation_is_smooth() {
+        let mut plugin = CoPiReMapPlugin::default();
+        plugin.params = c_major(&plugin);
+        let mut host = initialize(&mut plugin);
+        let (warm_up, window) = (SAMPLE_RATE as usize / 2, SAMPLE_RATE as usize / 8);
+        let input = sine(220.0, warm_up + 4 * window);
+        render(&mut plugin, &mut host, &input[..warm_up]);
+        let before = render(&mut plugin, &mut host, &input[warm_up..warm_up + window]);
+
+        // Automation moves the smoothers to the new values, which are reached within a block or so.
+        let global = &plugin.params.global;
+        let audio_process = &plugin.params.audio_process;
+        for (param, db) in [
+            (&global.wet_gain, -6.0),
+            (&global.dry_gain, -12.0),
+            (&global.lhf_gain, -6.0),
+            (&global.global_threshold, -60.0),
+            (&audio_process.in_key_gain, 6.0),
+            (&audio_process.tuning_gain, -6.0),
+            (&audio_process.off_key_gain, -6.0),
+            (&audio_process.threshold, -60.0),
+        ] {
+            param.smoothed.set_target(SAMPLE_RATE, db_to_gain(db));
+        }
+        let after = render(&mut plugin, &mut host, &input[warm_up + window..]);
+
+        // The step window starts on the last samples before the automation.
+        let step: Vec<f32> = before[before.len() - 2..].iter().chain(after[..window].iter()).copied().collect();
+        let steady = max_second_difference(&before).max(max_second_difference(&after[window..]));
+        let jump = max_second_difference(&step);
+        assert!(jump < 2.0 * steady, "second difference of {} after the step, {} steady", jump, steady);
+    }
+
+    /// The smallest RMS over windows of `window` samples of the difference between `output`
+    /// and the input delayed by any of `latencies`. The windows are long enough to even out
+    /// the noise of the pitch shifters, and short enough to catch a fade through the dry signal.
+    fn min_distance_to_dry(output: &[f32], input: &[f32], offset: usize, latencies: &[u32], window: usize) -> f32 {
+        (0..output.len() / window)
+            .map(|w| {
+                latencies
+                    .iter()
+                    .map(|latency| {
+                        let rms = (w * window..(w + 1) * window)
+                            .map(|n| (output[n] - input[offset + n - *latency as usize]).powi(2))
+                            .sum::<f32>() / window as f32;
+                        rms.sqrt()
+                    })
+                    .fold(f32::MAX, f32::min)
+            })
+            .fold(f32::MAX, f32::min)
+    }
+
+    #[test]
+    fn switch_has_no_dry_gap() {
+        let mut plugin = CoPiReMapPlugin::default();
+        plugin.params = c_major(&plugin);
+        let mut host = initialize(&mut plugin);
+        // An off key note, which the wet path shifts away from the dry signal.
+        let (warm_up, window) = (SAMPLE_RATE as usize / 2, SAMPLE_RATE as usize / 4);
+        let input = sine(233.08, warm_up + 5 * window);
+        render(&mut plugin, &mut host, &input[..warm_up]);
+        let mut latency = plugin.latency.load(Ordering::SeqCst);
+        let steady = render(&mut plugin, &mut host, &input[warm_up..warm_up + window]);
+        let mut steady = min_distance_to_dry(&steady, &input, warm_up, &[latency], 480);
+
+        // From 12 to 96 nodes, then to linear phase bands, which have another latency.
+        for (n, audio_process) in [
+            AudioProcessParams {
+                pitch_shift_node: EnumParam::new("Pitch Shift Node", PitchShiftNode::Node96),
+                ..AudioProcessParams::new(plugin.update_pitch_shift_and_after_bandpass.clone(), plugin.update_bpf_center_hz.clone())
+            },
+            AudioProcessParams {
+                pitch_shift_node: EnumParam::new("Pitch Shift Node", PitchShiftNode::Node96),
+                band_phase: EnumParam::new("Band Phase", BandPhase::Linear),
+                ..AudioProcessParams::new(plugin.update_pitch_shift_and_after_bandpass.clone(), plugin.update_bpf_center_hz.clone())
+            },
+        ].into_iter().enumerate() {
+            let layout = plugin.configuration.layout;
+            plugin.params = with_audio_process(&plugin, audio_process);
+            host.executor = plugin.task_executor();
+            let offset = warm_up + (2 * n + 1) * window;
+            let output = render(&mut plugin, &mut host, &input[offset..offset + window]);
+            assert!(plugin.configuration.layout != layout && plugin.incoming.is_none(), "switch {} is not over", n);
+            let incoming = plugin.latency.load(Ordering::SeqCst);
+            // Either configuration is wet, whichever latency it reads the dry signal at.
+            let distance = min_distance_to_dry(&output, &input, offset, &[latency, incoming], 480);
+            let after = render(&mut plugin, &mut host, &input[offset + window..offset + 2 * window]);
+            let after = min_distance_to_dry(&after, &input, offset + window, &[incoming], 480);
+            assert!(distance > 0.5 * steady.min(after), "output within {} of the dry signal during switch {}, {} and {} steady", distance, n, steady, after);
+            (latency, steady) = (incoming, after);
+        }
+        assert_eq!(host.latency, latency);
+    }
+
+    #[test]
+    fn auto_tier_step_crossfades() {
+        let mut plugin = CoPiReMapPlugin::default();
+        plugin.params = c_major(&plugin);
+        plugin.params = with_audio_process(&plugin, AudioProcessParams {
+            quality_tier: EnumParam::new("Quality Tier", QualityTier::Auto),
+            ..AudioProcessParams::new(plugin.update_pitch_shift_and_after_bandpass.clone(), plugin.update_bpf_center_hz.clone())
+        });
+        let mut host = initialize(&mut plugin);
+        let block_time = BLOCK as f32 / SAMPLE_RATE;
+        // Every block takes ten times its duration, the load crosses any budget in a few blocks.
+        host.process_time = Some(10.0 * block_time);
+        let input = sine(233.08, SAMPLE_RATE as usize / 2);
+        let (warm_up, step) = input.split_at(16 * BLOCK);
+        // Offline, Auto renders the highest tier and leaves the measurement alone.
+        plugin.buffer_config.process_mode = ProcessMode::Offline;
+        let start_tier = plugin.cpu_budget.tier();
+        render(&mut plugin, &mut host, warm_up);
+        assert_eq!(plugin.configuration.layout.quality.tier, QualityTier::High);
+        assert_eq!(plugin.cpu_budget.tier(), start_tier, "offline blocks moved the tier");
+
+        // In real time, the measured load drops the tier down to the lowest one.
+        plugin.buffer_config.process_mode = ProcessMode::Realtime;
+        let mut tiers = vec![plugin.configuration.layout.quality.tier];
+        for block in step.chunks(BLOCK) {
+            render(&mut plugin, &mut host, block);
+            assert!(plugin.smoothed.mix[..block.len()].iter().all(|mix| *mix == 1.0), "the tier step faded to dry");
+            if let Some(incoming) = plugin.incoming.as_ref() {
+                if tiers.last() != Some(&incoming.layout.quality.tier) {
+                    tiers.push(incoming.layout.quality.tier);
+                }
+            }
+        }
+        assert_eq!(tiers, [QualityTier::High, start_tier, QualityTier::Eco], "the tier steps did not crossfade");
+        assert_eq!(plugin.configuration.layout.quality.tier, QualityTier::Eco);
+        assert_eq!(plugin.quality_tier.load(Ordering::Relaxed), QualityTier::Eco.to_index() as u8);
+    }
+
+    #[test]
+    fn reported_latency_is_the_dry_delay() {
+        let sample_rate = SAMPLE_RATE;
+        let pitch_latency = |window: usize, over_sampling: usize| {
+            let frame = window * sample_rate as usize / 1000;
+            frame - frame / over_sampling
+        };
+        let fir_latency = max_filter_latency(sample_rate) as u32;
+        for (node, over_sampling, window, band_phase, remap_engine, expected) in [
+            (PitchShiftNode::Node12, 1, 7, BandPhase::Minimum, RemapEngine::Bands, 0),
+            (PitchShiftNode::Node96, 4, 20, BandPhase::Minimum, RemapEngine::Bands, pitch_latency(20, 4) as u32),
+            (PitchShiftNode::Node96, 4, 20, BandPhase::Linear, RemapEngine::Bands, pitch_latency(20, 4) as u32 + fir_latency),
+            // The longest window with the finest over sampling and a linear phase band.
+            (PitchShiftNode::Node96, 8, 100, BandPhase::Linear, RemapEngine::Bands, pitch_latency(100, 8) as u32 + fir_latency),
+            (PitchShiftNode::Node96, 1, 7, BandPhase::Minimum, RemapEngine::Spectral, SpectralRemap::new(sample_rate, 440.0).get_latency()),
+        ] {
+            let mut plugin = CoPiReMapPlugin::default();
+            plugin.params = Arc::new(PluginParams {
+                global: Arc::new(GlobalParams {
+                    bypass: BoolParam::new("Bypass", true).make_bypass(),
+                    ..GlobalParams::new(plugin.update_lowpass.clone(), plugin.update_highpass.clone(), plugin.update_crossover.clone(), plugin.update_bpf_center_hz.clone(), plugin.update_pitch_shift_and_after_bandpass.clone(), plugin.update_gui_scale.clone())
+                }),
+                audio_process: plugin.params.audio_process.clone(),
+                key_note: plugin.params.key_note.clone(),
+            });
+            plugin.params = with_audio_process(&plugin, AudioProcessParams {
+                pitch_shift_node: EnumParam::new("Pitch Shift Node", node),
+                pitch_shift_over_sampling: IntParam::new("Pitch Shift Over Sampling", over_sampling, IntRange::Linear { min: 1, max: 8 }),
+                pitch_shift_window_duration_ms: IntParam::new("Pitch Shift Window Duration", window, IntRange::Linear { min: 1, max: 100 }),
+                band_phase: EnumParam::new("Band Phase", band_phase),
+                remap_engine: EnumParam::new("Remap Engine", remap_engine),
+                ..AudioProcessParams::new(plugin.update_pitch_shift_and_after_bandpass.clone(), plugin.update_bpf_center_hz.clone())
+            });
+            let mut host = initialize(&mut plugin);
+            assert_eq!(host.latency, expected, "{:?} {}x {} ms {:?} {:?}", node, over_sampling, window, band_phase, remap_engine);
+            assert!(expected as usize <= max_latency(sample_rate, &plugin.spectral));
+
+            // Bypassed, the output is the input delayed by the reported latency.
+            let input: Vec<f32> = (0..expected as usize + 2 * BLOCK).map(|n| if n == 0 { 1.0 } else { 0.0 }).collect();
+            let output = render(&mut plugin, &mut host, &input);
+            for (n, y) in output.iter().enumerate() {
+                assert_eq!(*y, if n == expected as usize { 1.0 } else { 0.0 }, "at {} with a latency of {}", n, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn threaded_bands_are_bit_identical() {
+        let input: Vec<f32> = sine(233.08, SAMPLE_RATE as usize / 4).iter().zip(sine(523.25, SAMPLE_RATE as usize / 4)).map(|(a, b)| a + b).collect();
+        let outputs: Vec<Vec<f32>> = [(false, false), (true, false), (true, true)].into_iter().map(|(band_threads, pinned)| {
+            let mut plugin = CoPiReMapPlugin::default();
+            plugin.params = c_major(&plugin);
+            plugin.params = with_audio_process(&plugin, AudioProcessParams {
+                pitch_shift_node: EnumParam::new("Pitch Shift Node", PitchShiftNode::Node96),
+                band_threads: BoolParam::new("Multithreaded Bands", band_threads),
+                pin_band_threads: BoolParam::new("Pin Band Threads", pinned),
+                ..AudioProcessParams::new(plugin.update_pitch_shift_and_after_bandpass.clone(), plugin.update_bpf_center_hz.clone())
+            });
+            let mut host = initialize(&mut plugin);
+            assert_eq!(plugin.pool.as_ref().map(WorkerPool::pinned), band_threads.then_some(pinned));
+            if band_threads {
+                // Workers even on a machine with a single core.
+                plugin.pool = Some(WorkerPool::new(3, pinned));
+            }
+            render(&mut plugin, &mut host, &input)
+        }).collect();
+        let bits = |output: &[f32]| output.iter().map(|y| y.to_bits()).collect::<Vec<u32>>();
+        assert!(outputs[0].iter().any(|y| *y != 0.0));
+        for output in outputs[1..].iter() {
+            assert!(bits(&outputs[0]) == bits(output), "the threaded bands differ from the serial ones");
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::f32::consts::TAU;
+
+pub struct MyOscillator {
+    phase: [f32; 2],
+    delta: f32,
+}
+
+impl MyOscillator {
+    pub fn set_frequency(&mut self, frequency: f32, sample_rate: f32) {
+        self.delta = frequency / sample_rate;
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = [0.0; 2];
+    }
+
+    pub fn process(&mut self, audio_id: usize) -> f32 {
+        let out = (TAU * self.phase[audio_id]).sin();
+        self.advance(audio_id);
+        out
+    }
+
+    /// Band limited sawtooth using a polyBLEP correction around the wrap.
+    pub fn process_saw(&mut self, audio_id: usize) -> f32 {
+        let t = self.phase[audio_id];
+        let dt = self.delta;
+        let blep = if t < dt {
+            let t = t / dt;
+            t + t - t * t - 1.0
+        } else if t > 1.0 - dt {
+            let t = (t - 1.0) / dt;
+            t * t + t + t + 1.0
+        } else {
+            0.0
+        };
+        self.advance(audio_id);
+        2.0 * t - 1.0 - blep
+    }
+
+    fn advance(&mut self, audio_id: usize) {
+        self.phase[audio_id] += self.delta;
+        if self.phase[audio_id] >= 1.0 {
+            self.phase[audio_id] -= 1.0;
+        }
+    }
+}
+
+impl Default for MyOscillator {
+    fn default() -> Self {
+        Self {
+            phase: [0.0; 2],
+            delta: 0.0,
+        }
+    }
+}
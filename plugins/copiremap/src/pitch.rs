@@ -1,7 +1,9 @@
 use pitch_shift::PitchShifter;
 
+/// Unlike the other wrappers, this one is not generic over [`crate::sample::Sample`]: the
+/// phase vocoder of `pitch_shift` only runs in f32, so the pitch shifters stay in f32.
 pub struct MyPitch {
-    pitch: [PitchShifter; 2]
+    pitch: [PitchShifter; 2],
 }
 
 impl MyPitch {
@@ -11,7 +13,7 @@ impl MyPitch {
             pitch: [
                 PitchShifter::new(window_duration_ms, sample_rate as u32, over_sampling, shift),
                 PitchShifter::new(window_duration_ms, sample_rate as u32, over_sampling, shift),
-            ]
+            ],
         }
     }
 
@@ -50,7 +52,7 @@ impl Default for MyPitch {
             pitch: [
                 PitchShifter::new(2, 100, 1, 0.0),
                 PitchShifter::new(2, 100, 1, 0.0),
-            ]
+            ],
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,236 @@
+//! Worker threads that render disjoint groups of bands of a block in parallel.
+//!
+//! The threads are spawned at `initialize`, or on the background thread when the bands
+//! become multithreaded, and are pinned to their own core on request. For every block the
+//! audio thread publishes the job, wakes the workers, renders the first group itself and
+//! spins until every group is done. Nothing is allocated or locked per block.
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Upper bound of the worker threads, on top of the audio thread.
+pub const MAX_WORKERS: usize = 7;
+/// Number of checks for a new block before a worker sleeps.
+const SPIN: usize = 1 << 12;
+
+type Job = dyn Fn(usize) + Sync + 'static;
+
+struct Shared {
+    /// The job of the current block, pointing into the stack of [`WorkerPool::run`].
+    job: AtomicPtr<&'static Job>,
+    /// Incremented for every block, the workers wait for it to change.
+    generation: AtomicUsize,
+    /// Number of workers done with the current block, panicked or not.
+    done: AtomicUsize,
+    /// A worker panicked in the current block.
+    panicked: AtomicBool,
+    quit: AtomicBool,
+}
+
+/// The workers for the cores beyond the one of the audio thread.
+pub fn available_workers() -> usize {
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+    (threads - 1).min(MAX_WORKERS)
+}
+
+pub struct WorkerPool {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+    pinned: bool,
+}
+
+impl WorkerPool {
+    /// Spawn `threads` workers. When `pinned`, the cores after the first are given to them
+    /// in order, if the system lets us pin threads. Otherwise they are left to the scheduler,
+    /// which is better when several instances of the plugin share the cores with the host.
+    pub fn new(threads: usize, pinned: bool) -> Self {
+        let shared = Arc::new(Shared {
+            job: AtomicPtr::new(std::ptr::null_mut()),
+            generation: AtomicUsize::new(0),
+            done: AtomicUsize::new(0),
+            panicked: AtomicBool::new(false),
+            quit: AtomicBool::new(false),
+        });
+        let cores = match pinned {
+            true => core_affinity::get_core_ids().unwrap_or_default(),
+            false => Vec::new(),
+        };
+        let workers = (0..threads.min(MAX_WORKERS))
+            .filter_map(|worker| {
+                let shared = shared.clone();
+                let core = cores.get(worker + 1).copied();
+                thread::Builder::new()
+                    .name(format!("copiremap-bands-{}", worker + 1))
+                    .spawn(move || {
+                        if let Some(core) = core {
+                            core_affinity::set_for_current(core);
+                        }
+                        work(&shared, worker + 1);
+                    })
+                    .ok()
+            })
+            .collect();
+        Self { shared, workers, pinned }
+    }
+
+    /// The workers were asked to be pinned to their cores.
+    pub fn pinned(&self) -> bool {
+        self.pinned
+    }
+
+    /// Number of worker threads, the groups of [`WorkerPool::run`] are one more.
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Call `job` once for every group from `0` to [`WorkerPool::threads`], the calling
+    /// thread taking group `0`, and return once all of them are done. A panic of any group
+    /// is resumed here, after the others are done.
+    pub fn run(&self, job: &(dyn Fn(usize) + Sync)) {
+        if self.workers.is_empty() {
+            job(0);
+            return;
+        }
+        // Safety: the pointer is only read by the workers before they count themselves
+        // done, and this function does not return, nor unwind, before all of them are.
+        let job: *const &(dyn Fn(usize) + Sync) = &job;
+        let job = unsafe { std::mem::transmute::<*const &(dyn Fn(usize) + Sync), *mut &'static Job>(job) };
+        self.shared.job.store(job, Ordering::Release);
+        self.shared.done.store(0, Ordering::Release);
+        self.shared.panicked.store(false, Ordering::Release);
+        self.shared.generation.fetch_add(1, Ordering::AcqRel);
+        for worker in self.workers.iter() {
+            worker.thread().unpark();
+        }
+        let own = panic::catch_unwind(AssertUnwindSafe(|| unsafe { (**job)(0) }));
+        let mut spin = 0;
+        while self.shared.done.load(Ordering::Acquire) < self.workers.len() {
+            // Let a worker sharing the core finish its group.
+            if spin < SPIN {
+                spin += 1;
+                std::hint::spin_loop();
+            } else {
+                thread::yield_now();
+            }
+        }
+        if let Err(payload) = own {
+            panic::resume_unwind(payload);
+        }
+        if self.shared.panicked.load(Ordering::Acquire) {
+            panic!("a band worker panicked");
+        }
+    }
+}
+
+fn work(shared: &Shared, group: usize) {
+    let mut seen = 0;
+    let mut spin = 0;
+    loop {
+        if shared.quit.load(Ordering::Acquire) {
+            return;
+        }
+        let generation = shared.generation.load(Ordering::Acquire);
+        if generation == seen {
+            // Spin for a little while, the next block is usually close.
+            if spin < SPIN {
+                spin += 1;
+                std::hint::spin_loop();
+            } else {
+                thread::park();
+            }
+            continue;
+        }
+        seen = generation;
+        spin = 0;
+        let job = unsafe { &**shared.job.load(Ordering::Acquire) };
+        // Counted done even when it panics, the audio thread waits for every group.
+        if panic::catch_unwind(AssertUnwindSafe(|| job(group))).is_err() {
+            shared.panicked.store(true, Ordering::Release);
+        }
+        shared.done.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+impl Default for WorkerPool {
+    fn default() -> Self {
+        Self::new(0, false)
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.shared.quit.store(true, Ordering::Release);
+        for worker in self.workers.drain(..) {
+            worker.thread().unpark();
+            let _ = worker.join();
+        }
+    }
+}
+
+/// A mutable slice shared with the workers, which each take their own range of it.
+pub struct SharedSlice<'a, T> {
+    ptr: *mut T,
+    len: usize,
+    slice: PhantomData<&'a mut [T]>,
+}
+
+unsafe impl<T: Send> Sync for SharedSlice<'_, T> {}
+
+impl<'a, T> SharedSlice<'a, T> {
+    pub fn new(slice: &'a mut [T]) -> Self {
+        Self {
+            ptr: slice.as_mut_ptr(),
+            len: slice.len(),
+            slice: PhantomData,
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The ranges taken while the others are alive must not overlap.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn range(&self, range: Range<usize>) -> &'a mut [T] {
+        assert!(range.start <= range.end && range.end <= self.len);
+        std::slice::from_raw_parts_mut(self.ptr.add(range.start), range.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run a job whose `panicking` group panics, and return whether `run` panicked once all
+    /// the groups were done.
+    fn run_panicking(pool: &WorkerPool, panicking: usize) -> bool {
+        let done = AtomicUsize::new(0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.run(&|group| {
+                if group == panicking {
+                    panic!("group {} panics", group);
+                }
+                done.fetch_add(1, Ordering::Relaxed);
+            })
+        }));
+        assert_eq!(done.load(Ordering::Relaxed), pool.threads(), "the other groups did not finish");
+        result.is_err()
+    }
+
+    #[test]
+    fn panics_are_resumed_after_every_group() {
+        for pinned in [false, true] {
+            let pool = WorkerPool::new(3, pinned);
+            assert_eq!((pool.threads(), pool.pinned()), (3, pinned));
+            assert!(run_panicking(&pool, 2), "a worker panicked");
+            assert!(run_panicking(&pool, 0), "the calling thread panicked");
+            // The workers keep serving blocks.
+            let groups = AtomicUsize::new(0);
+            pool.run(&|_| {
+                groups.fetch_add(1, Ordering::Relaxed);
+            });
+            assert_eq!(groups.load(Ordering::Relaxed), 4);
+        }
+    }
+}
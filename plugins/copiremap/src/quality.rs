@@ -0,0 +1,176 @@
+use nih_plug::prelude::Enum;
+use crate::audio_process::PitchShiftNode;
+use crate::PluginParams;
+
+/// Time constant of the measured CPU load, in seconds.
+const LOAD_TIME: f32 = 0.3;
+/// The tier is raised again once the load falls under this part of the budget.
+const RAISE_BELOW: f32 = 0.5;
+/// Time spent in a tier before it may be raised, doubled every time the tier drops.
+const MIN_HOLD_TIME: f32 = 5.0;
+const MAX_HOLD_TIME: f32 = 60.0;
+
+/// How many pitch shifters run and how fine they are. Manual uses the pitch shift params,
+/// Auto moves between the three tiers to stay within the CPU budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[non_exhaustive]
+pub enum QualityTier {
+    #[id = "manual"]
+    #[name = "Manual"]
+    Manual,
+    #[id = "auto"]
+    #[name = "Auto"]
+    Auto,
+    #[id = "eco"]
+    #[name = "Eco"]
+    Eco,
+    #[id = "normal"]
+    #[name = "Normal"]
+    Normal,
+    #[id = "high"]
+    #[name = "High"]
+    High,
+}
+
+impl QualityTier {
+    fn lower(self) -> Self {
+        match self {
+            QualityTier::High => QualityTier::Normal,
+            _ => QualityTier::Eco,
+        }
+    }
+
+    fn higher(self) -> Self {
+        match self {
+            QualityTier::Eco => QualityTier::Normal,
+            _ => QualityTier::High,
+        }
+    }
+}
+
+/// The pitch shifter settings being rendered, from the params or from a tier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quality {
+    pub tier: QualityTier,
+    pub node: PitchShiftNode,
+    pub over_sampling: u8,
+    pub window_duration_ms: u8,
+}
+
+impl Quality {
+    /// The settings of `tier`, Manual and Auto read the params.
+    pub fn new(tier: QualityTier, params: &PluginParams) -> Self {
+        match tier {
+            QualityTier::Eco => Self { tier, node: PitchShiftNode::Node12, over_sampling: 1, window_duration_ms: 5 },
+            QualityTier::Normal => Self { tier, node: PitchShiftNode::Node96, over_sampling: 2, window_duration_ms: 7 },
+            QualityTier::High => Self { tier, node: PitchShiftNode::Node96, over_sampling: 4, window_duration_ms: 20 },
+            _ => Self {
+                tier,
+                node: params.audio_process.pitch_shift_node.value(),
+                over_sampling: params.audio_process.pitch_shift_over_sampling.value() as u8,
+                window_duration_ms: params.audio_process.pitch_shift_window_duration_ms.value() as u8,
+            },
+        }
+    }
+}
+
+/// Picks the tier of the Auto mode from the time spent in `process`.
+pub struct CpuBudget {
+    tier: QualityTier,
+    /// Smoothed process time, as a part of the duration of the blocks.
+    load: f32,
+    /// Seconds left before the tier may be raised.
+    hold: f32,
+    hold_time: f32,
+}
+
+impl CpuBudget {
+    pub fn tier(&self) -> QualityTier {
+        self.tier
+    }
+
+    /// Measure a block of `block_time` seconds processed in `elapsed` seconds, and move
+    /// the tier when the load goes over `budget`, a part of the real time budget.
+    pub fn update(&mut self, elapsed: f32, block_time: f32, budget: f32) {
+        let load = elapsed / block_time.max(f32::EPSILON);
+        self.load += (load - self.load) * (1.0 - (-block_time / LOAD_TIME).exp());
+        self.hold = (self.hold - block_time).max(0.0);
+        if self.load > budget && self.tier != QualityTier::Eco {
+            self.hold_time = (self.hold_time * 2.0).min(MAX_HOLD_TIME);
+            self.set_tier(self.tier.lower());
+        } else if self.load < budget * RAISE_BELOW && self.hold == 0.0 && self.tier != QualityTier::High {
+            self.set_tier(self.tier.higher());
+        }
+    }
+
+    fn set_tier(&mut self, tier: QualityTier) {
+        self.tier = tier;
+        // The new tier starts measuring from scratch.
+        self.load = 0.0;
+        self.hold = self.hold_time;
+    }
+}
+
+impl Default for CpuBudget {
+    fn default() -> Self {
+        Self {
+            tier: QualityTier::Normal,
+            load: 0.0,
+            hold: MIN_HOLD_TIME,
+            hold_time: MIN_HOLD_TIME,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_TIME: f32 = 0.01;
+    const BUDGET: f32 = 0.5;
+
+    /// Feed `budget` blocks processed at `load` until its tier changes, and return the time it took.
+    fn time_to_change(budget: &mut CpuBudget, load: f32) -> f32 {
+        let tier = budget.tier();
+        let mut time = 0.0;
+        while budget.tier() == tier {
+            assert!(time < 2.0 * MAX_HOLD_TIME, "the tier stays {:?} at a load of {}", tier, load);
+            budget.update(load * BLOCK_TIME, BLOCK_TIME, BUDGET);
+            time += BLOCK_TIME;
+        }
+        time
+    }
+
+    fn assert_time(time: f32, expected: f32) {
+        assert!((time - expected).abs() <= 2.0 * BLOCK_TIME, "{} s instead of {} s", time, expected);
+    }
+
+    #[test]
+    fn cpu_budget_drops_holds_and_raises() {
+        let mut budget = CpuBudget::default();
+        assert_eq!(budget.tier(), QualityTier::Normal);
+
+        // Over the budget, the tier drops as soon as the smoothed load crosses it.
+        assert!(time_to_change(&mut budget, 0.9) < LOAD_TIME);
+        assert_eq!(budget.tier(), QualityTier::Eco);
+        // Under the raise threshold, it is raised once the doubled hold time is over.
+        let low = 0.5 * RAISE_BELOW * BUDGET;
+        assert_time(time_to_change(&mut budget, low), 2.0 * MIN_HOLD_TIME);
+        assert_eq!(budget.tier(), QualityTier::Normal);
+
+        // Every drop doubles the hold time again, the raises keep it.
+        assert!(time_to_change(&mut budget, 0.9) < LOAD_TIME);
+        assert_eq!(budget.tier(), QualityTier::Eco);
+        assert_time(time_to_change(&mut budget, low), 4.0 * MIN_HOLD_TIME);
+        assert_eq!(budget.tier(), QualityTier::Normal);
+        assert_time(time_to_change(&mut budget, low), 4.0 * MIN_HOLD_TIME);
+        assert_eq!(budget.tier(), QualityTier::High);
+
+        // Between the raise threshold and the budget, the tier stays.
+        let mut held = CpuBudget::default();
+        for _ in 0..(2.0 * MAX_HOLD_TIME / BLOCK_TIME) as usize {
+            held.update(0.75 * BUDGET * BLOCK_TIME, BLOCK_TIME, BUDGET);
+        }
+        assert_eq!(held.tier(), QualityTier::Normal);
+    }
+}
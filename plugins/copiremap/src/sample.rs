@@ -0,0 +1,36 @@
+use nalgebra::RealField;
+use realfft::FftNum;
+
+/// Sample type the DSP wrappers run in. The host buffers are always f32, so the
+/// wrappers are fed and read through [`Sample::from_host`] and [`Sample::to_host`].
+pub trait Sample: RealField + FftNum + Copy + Default {
+    fn from_host(x: f32) -> Self;
+    fn to_host(self) -> f32;
+}
+
+impl Sample for f32 {
+    fn from_host(x: f32) -> Self {
+        x
+    }
+
+    fn to_host(self) -> f32 {
+        self
+    }
+}
+
+impl Sample for f64 {
+    fn from_host(x: f32) -> Self {
+        x as f64
+    }
+
+    fn to_host(self) -> f32 {
+        self as f32
+    }
+}
+
+/// Precision of the band filters and of the split. The narrow bands of the lowest
+/// notes lose their shape with f32 coefficients, the `f64` feature runs them in f64.
+#[cfg(feature = "f64")]
+pub type Precision = f64;
+#[cfg(not(feature = "f64"))]
+pub type Precision = f32;
@@ -0,0 +1,239 @@
+use std::f32::consts::TAU;
+use std::sync::Arc;
+use nih_plug::util::db_to_gain;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use realfft::num_complex::Complex;
+use crate::PluginParams;
+
+const COMPLEX_ZERO: Complex<f32> = Complex::new(0.0, 0.0);
+const OVER_SAMPLING: u32 = 4;
+
+struct SpectralChannel {
+    in_fifo: Vec<f32>,
+    out_fifo: Vec<f32>,
+    output_accumulator: Vec<f32>,
+    last_phase: Vec<f32>,
+    phase_sum: Vec<f32>,
+    overlap: u32,
+}
+
+impl SpectralChannel {
+    fn new(frame_size: u32, fifo_latency: u32) -> Self {
+        let half_frame_size = (frame_size / 2 + 1) as usize;
+        Self {
+            in_fifo: vec![0.0; frame_size as usize],
+            out_fifo: vec![0.0; frame_size as usize],
+            output_accumulator: vec![0.0; 2 * frame_size as usize],
+            last_phase: vec![0.0; half_frame_size],
+            phase_sum: vec![0.0; half_frame_size],
+            overlap: fifo_latency,
+        }
+    }
+
+    fn reset(&mut self, fifo_latency: u32) {
+        self.in_fifo.fill(0.0);
+        self.out_fifo.fill(0.0);
+        self.output_accumulator.fill(0.0);
+        self.last_phase.fill(0.0);
+        self.phase_sum.fill(0.0);
+        self.overlap = fifo_latency;
+    }
+}
+
+/// Remaps every note of the 96 band range inside a single STFT instead of a
+/// bandpass and a [`pitch_shift::PitchShifter`] per band.
+///
+/// Each bin is classified to the note it belongs to, then moved by the note's
+/// `i2t`/`im2t` offset with a phase vocoder and weighted by the same in key,
+/// tuning and off key gains as the band engine. The frequency resolution is
+/// `sample_rate / frame_size`, so the lowest octaves are coarser than with bands.
+pub struct SpectralRemap {
+    forward_fft: Arc<dyn RealToComplex<f32>>,
+    inverse_fft: Arc<dyn ComplexToReal<f32>>,
+    ffft_scratch_len: usize,
+    ifft_scratch_len: usize,
+    fft_scratch: Vec<Complex<f32>>,
+    fft_real: Vec<f32>,
+    fft_cplx: Vec<Complex<f32>>,
+    windowing: Vec<f32>,
+    synthesized_frequency: Vec<f32>,
+    synthesized_magnitude: Vec<f32>,
+
+    bin_note: Vec<i8>,
+    bin_ratio: Vec<f32>,
+    bin_gain: Vec<f32>,
+
+    channels: [SpectralChannel; 2],
+
+    frame_size: u32,
+    half_frame_size: u32,
+    step: u32,
+    fifo_latency: u32,
+    expected: f32,
+}
+
+impl SpectralRemap {
+    pub fn new(sample_rate: f32, hz_center: f32) -> Self {
+        let frame_size = ((sample_rate / 12.0) as u32).next_power_of_two();
+        let half_frame_size = frame_size / 2 + 1;
+        let step = frame_size / OVER_SAMPLING;
+        let fifo_latency = frame_size - step;
+
+        let mut planner = RealFftPlanner::<f32>::new();
+        let forward_fft = planner.plan_fft_forward(frame_size as usize);
+        let inverse_fft = planner.plan_fft_inverse(frame_size as usize);
+        let ffft_scratch_len = forward_fft.get_scratch_len();
+        let ifft_scratch_len = inverse_fft.get_scratch_len();
+        let scratch_len = ffft_scratch_len.max(ifft_scratch_len);
+        let mut windowing = vec![0.0; frame_size as usize];
+        for (k, w) in windowing.iter_mut().enumerate() {
+            *w = -0.5 * (TAU * (k as f32) / frame_size as f32).cos() + 0.5;
+        }
+
+        let mut spectral = Self {
+            forward_fft,
+            inverse_fft,
+            ffft_scratch_len,
+            ifft_scratch_len,
+            fft_scratch: vec![COMPLEX_ZERO; scratch_len],
+            fft_real: vec![0.0; frame_size as usize],
+            fft_cplx: vec![COMPLEX_ZERO; half_frame_size as usize],
+            windowing,
+            synthesized_frequency: vec![0.0; half_frame_size as usize],
+            synthesized_magnitude: vec![0.0; half_frame_size as usize],
+
+            bin_note: vec![-1; half_frame_size as usize],
+            bin_ratio: vec![1.0; half_frame_size as usize],
+            bin_gain: vec![0.0; half_frame_size as usize],
+
+            channels: [SpectralChannel::new(frame_size, fifo_latency), SpectralChannel::new(frame_size, fifo_latency)],
+
+            frame_size,
+            half_frame_size,
+            step,
+            fifo_latency,
+            expected: TAU / OVER_SAMPLING as f32,
+        };
+        spectral.set_hz_center(sample_rate, hz_center);
+        spectral
+    }
+
+    /// Classify every bin to its band index (0 to 95), `-1` outside of the range.
+    pub fn set_hz_center(&mut self, sample_rate: f32, hz_center: f32) {
+        let bin_frequencies = sample_rate / self.frame_size as f32;
+        for (k, note) in self.bin_note.iter_mut().enumerate() {
+            let hz = k as f32 * bin_frequencies;
+            let band = if hz > 0.0 { (33.0 + 12.0 * (hz / hz_center).log2()).round() } else { -1.0 };
+            *note = if (0.0..96.0).contains(&band) { band as i8 } else { -1 };
+        }
+    }
+
+    /// Recompute the target ratio and gain of every bin from the note table and the gain
+    /// params, as smoothed up to the end of the current block.
+    pub fn update(&mut self, params: Arc<PluginParams>, note_table: [i8; 96]) {
+        let low_note = (params.global.low_note_off.value() - 36) as i8;
+        let high_note = (params.global.high_note_off.value() - 36) as i8;
+        let pitch_shift = params.audio_process.pitch_shift.value();
+        let mute_off_key = params.key_note.mute_off_key.value();
+        let tuning = 12.0 * (params.global.hz_center.value() / params.global.hz_tuning.value()).log2();
+        for (k, note) in self.bin_note.iter().enumerate() {
+            if *note < low_note || *note > high_note {
+                self.bin_gain[k] = 0.0;
+                continue;
+            }
+            let note_pitch = note_table[*note as usize];
+            let input_param: f32 = if note_pitch == 0 { params.audio_process.in_key_gain.smoothed.previous_value() } else if note_pitch == -128 { params.audio_process.off_key_gain.smoothed.previous_value() } else if !pitch_shift { params.audio_process.off_key_gain.smoothed.previous_value() } else { params.audio_process.tuning_gain.smoothed.previous_value() };
+            self.bin_gain[k] = if (note_pitch == -128 && mute_off_key) || input_param <= db_to_gain(-60.0) { 0.0 } else { input_param };
+            self.bin_ratio[k] = if pitch_shift && !(note_pitch == 0 || note_pitch == -128) {
+                2.0_f32.powf((note_pitch as f32 + tuning) / 12.0)
+            } else {
+                1.0
+            };
+        }
+    }
+
+    pub fn get_latency(&self) -> u32 {
+        self.fifo_latency
+    }
+
+    pub fn reset(&mut self) {
+        for channel in self.channels.iter_mut() {
+            channel.reset(self.fifo_latency);
+        }
+    }
+
+    pub fn process(&mut self, signal: f32, audio_id: usize) -> f32 {
+        let channel = &mut self.channels[audio_id];
+        channel.in_fifo[channel.overlap as usize] = signal;
+        let out = channel.out_fifo[(channel.overlap - self.fifo_latency) as usize];
+        channel.overlap += 1;
+        if channel.overlap >= self.frame_size {
+            channel.overlap = self.fifo_latency;
+            self.process_frame(audio_id);
+        }
+        out
+    }
+
+    fn process_frame(&mut self, audio_id: usize) {
+        let channel = &mut self.channels[audio_id];
+        for k in 0..self.frame_size as usize {
+            self.fft_real[k] = channel.in_fifo[k] * self.windowing[k];
+        }
+
+        let _ = self.forward_fft.process_with_scratch(
+            &mut self.fft_real,
+            &mut self.fft_cplx,
+            &mut self.fft_scratch[..self.ffft_scratch_len],
+        );
+
+        self.synthesized_magnitude.fill(0.0);
+        self.synthesized_frequency.fill(0.0);
+
+        for k in 0..self.half_frame_size as usize {
+            let (magnitude, phase) = self.fft_cplx[k].to_polar();
+            let mut delta_phase = (phase - channel.last_phase[k]) - k as f32 * self.expected;
+            channel.last_phase[k] = phase;
+            delta_phase -= TAU * (delta_phase / TAU).round();
+            if self.bin_gain[k] == 0.0 {
+                continue;
+            }
+            let index = (k as f32 * self.bin_ratio[k]).round() as usize;
+            if index < self.half_frame_size as usize {
+                let true_bin = k as f32 + delta_phase / self.expected;
+                self.synthesized_magnitude[index] += magnitude * self.bin_gain[k];
+                self.synthesized_frequency[index] = true_bin * self.bin_ratio[k];
+            }
+        }
+
+        for k in 0..self.half_frame_size as usize {
+            channel.phase_sum[k] = (channel.phase_sum[k] + self.expected * self.synthesized_frequency[k]) % TAU;
+            self.fft_cplx[k] = Complex::from_polar(self.synthesized_magnitude[k], channel.phase_sum[k]);
+        }
+        // The imaginary part of the DC and Nyquist bins must be zero for the inverse transform.
+        self.fft_cplx[0].im = 0.0;
+        self.fft_cplx[self.half_frame_size as usize - 1].im = 0.0;
+
+        let _ = self.inverse_fft.process_with_scratch(
+            &mut self.fft_cplx,
+            &mut self.fft_real,
+            &mut self.fft_scratch[..self.ifft_scratch_len],
+        );
+
+        // Hann analysis and synthesis windows overlapped 4 times sum to 1.5.
+        let acc_oversamp = 1.0 / (self.frame_size as f32 * 1.5);
+        for k in 0..self.frame_size as usize {
+            channel.output_accumulator[k] += self.windowing[k] * self.fft_real[k] * acc_oversamp;
+        }
+
+        let step = self.step as usize;
+        channel.out_fifo[..step].copy_from_slice(&channel.output_accumulator[..step]);
+        channel.output_accumulator.copy_within(step..(step + self.frame_size as usize), 0);
+        channel.in_fifo.copy_within(step..(step + self.fifo_latency as usize), 0);
+    }
+}
+
+impl Default for SpectralRemap {
+    fn default() -> Self {
+        Self::new(44100.0, 440.0)
+    }
+}
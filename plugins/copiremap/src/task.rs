@@ -0,0 +1,60 @@
+use std::sync::Arc;
+use nih_plug::audio_setup::BufferConfig;
+use crate::audio_process::{BandPhase, DesignedBand};
+use crate::configuration::{Configuration, Layout};
+use crate::pool::WorkerPool;
+use crate::PluginParams;
+
+/// The filters of the 96 bands, see [`DesignedBand`].
+pub type BandDesigns = Vec<DesignedBand>;
+
+/// Work that allocates, run by the background thread instead of the audio thread.
+// The requests are not boxed, so that sending one does not allocate either.
+#[allow(clippy::large_enum_variant)]
+pub enum Task {
+    /// Build the bands of a new layout, to be crossfaded with the ones being rendered.
+    BuildConfiguration(ConfigurationRequest),
+    /// Free a configuration replaced on the audio thread.
+    RetireConfiguration(Configuration),
+    /// Design the filters of the bands for a new note table or new band params.
+    DesignBands(DesignRequest),
+    /// Free the designs taken over on the audio thread, with the FIRs they replaced.
+    RetireDesigns(BandDesigns),
+    /// Spawn a pool of this many workers for the multithreaded bands, pinned or not.
+    BuildPool(usize, bool),
+    /// Stop the workers of a pool the audio thread no longer uses.
+    RetirePool(WorkerPool),
+}
+
+/// Everything needed to build a configuration, on top of the params.
+pub struct ConfigurationRequest {
+    /// Tells the audio thread which request the configuration answers.
+    pub generation: u32,
+    pub layout: Layout,
+    pub buffer_config: BufferConfig,
+    /// The pitch of the note of every band.
+    pub note_table: [i8; 96],
+}
+
+impl ConfigurationRequest {
+    pub fn build(&self, params: Arc<PluginParams>) -> Configuration {
+        Configuration::build(self.layout, params, &self.buffer_config, self.note_table)
+    }
+}
+
+/// Everything needed to design the filters of the bands, on top of the params.
+pub struct DesignRequest {
+    /// Tells the audio thread which request the designs answer.
+    pub generation: u32,
+    pub sample_rate: f32,
+    /// The designs only fit the bands of a configuration with this phase.
+    pub band_phase: BandPhase,
+    /// The pitch of the note of every band.
+    pub note_table: [i8; 96],
+}
+
+impl DesignRequest {
+    pub fn build(&self, params: Arc<PluginParams>) -> BandDesigns {
+        DesignedBand::bands(params, self.note_table, self.band_phase, self.sample_rate)
+    }
+}